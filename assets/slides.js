@@ -0,0 +1,90 @@
+// Slideshow navigation (see `codegen::slides`).
+//
+// * Next: right/down arrow, page down, space or enter.
+// * Previous: left/up arrow, page up or backspace.
+// * First & last: home & end.
+// * `s` toggles the speaker notes, and `f` full screen.
+(function () {
+    var slides = Array.prototype.slice.call(document.querySelectorAll('section[macro=slide]'));
+    var counter = document.getElementById('slide-counter');
+    var current = 0;
+    if (slides.length === 0) {
+        return;
+    }
+    function show(index, revealAll) {
+        index = Math.max(0, Math.min(slides.length - 1, index));
+        slides[current].removeAttribute('current');
+        current = index;
+        var slide = slides[current];
+        slide.setAttribute('current', '');
+        slide.querySelectorAll('[fragment]').forEach(function (item) {
+            if (revealAll) {
+                item.setAttribute('shown', '');
+            } else {
+                item.removeAttribute('shown');
+            }
+        });
+        counter.textContent = (current + 1) + ' / ' + slides.length;
+        history.replaceState(null, '', '#' + (current + 1));
+    }
+    function next() {
+        var hidden = slides[current].querySelector('[fragment]:not([shown])');
+        if (hidden) {
+            hidden.setAttribute('shown', '');
+        } else if (current < slides.length - 1) {
+            show(current + 1, false);
+        }
+    }
+    function previous() {
+        var shown = slides[current].querySelectorAll('[fragment][shown]');
+        if (shown.length > 0) {
+            shown[shown.length - 1].removeAttribute('shown');
+        } else if (current > 0) {
+            show(current - 1, true);
+        }
+    }
+    function toggleFullscreen() {
+        if (document.fullscreenElement) {
+            document.exitFullscreen();
+        } else {
+            document.documentElement.requestFullscreen();
+        }
+    }
+    document.addEventListener('keydown', function (event) {
+        if (event.altKey || event.ctrlKey || event.metaKey) {
+            return;
+        }
+        switch (event.key) {
+            case 'ArrowRight':
+            case 'ArrowDown':
+            case 'PageDown':
+            case ' ':
+            case 'Enter':
+                next();
+                break;
+            case 'ArrowLeft':
+            case 'ArrowUp':
+            case 'PageUp':
+            case 'Backspace':
+                previous();
+                break;
+            case 'Home':
+                show(0, false);
+                break;
+            case 'End':
+                show(slides.length - 1, true);
+                break;
+            case 's':
+                document.body.toggleAttribute('speaker');
+                break;
+            case 'f':
+                toggleFullscreen();
+                break;
+            default:
+                return;
+        }
+        event.preventDefault();
+    });
+    var start = parseInt(window.location.hash.slice(1), 10);
+    show(isNaN(start) ? 0 : start - 1, false);
+})();
@@ -0,0 +1,88 @@
+//! Compile time of the example sources (`cargo bench --bench compile`), and
+//! the heading ids & table of contents of the compiled trees, by rebuilding
+//! the tree with `Node::transform` (the previous approach) versus in place.
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use subscript_compiler::codegen::html::Document;
+use subscript_compiler::compiler::Compiler;
+use subscript_compiler::frontend::ast::{Node, NodeEnvironment};
+use subscript_compiler::frontend::pass::html_normalize;
+
+static SOURCES: &[(&str, &str)] = &[
+    ("chemistry", include_str!("../examples/source/chemistry.txt")),
+    ("electrical-engineering", include_str!("../examples/source/electrical-engineering.txt")),
+    ("mathematics", include_str!("../examples/source/mathematics.txt")),
+];
+
+/// The number of timed runs per source.
+static RUNS: u32 = 20;
+
+/// The mean duration of `f`, after a warm up run.
+fn bench(mut f: impl FnMut()) -> Duration {
+    f();
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        f();
+    }
+    start.elapsed() / RUNS
+}
+
+fn annotate_heading<'a>(_: NodeEnvironment<'a>, node: Node<'a>) -> Node<'a> {
+    match node {
+        Node::Tag(mut tag) if tag.is_heading_node() => {
+            let id = html_normalize::heading_id(&tag.children);
+            tag.insert_unannotated_parameter(&format!("id={}", id));
+            Node::Tag(tag)
+        }
+        node => node,
+    }
+}
+
+/// The previous approach: the table of contents, then the heading ids by
+/// rebuilding every node.
+fn headings_by_transform(body: Vec<Node>) -> (Node, Vec<Node>) {
+    let toc = html_normalize::generate_table_of_contents_tree(&body);
+    let body = body
+        .into_iter()
+        .map(|x| x.transform(NodeEnvironment::default(), Rc::new(annotate_heading)))
+        .collect();
+    (toc, body)
+}
+
+/// See `html::Document::from_nodes`.
+fn headings_in_place(body: Vec<Node>) -> (Node, Vec<Node>) {
+    let toc = html_normalize::generate_table_of_contents_tree(&body);
+    let body = body
+        .into_iter()
+        .map(html_normalize::annotate_heading_nodes)
+        .collect();
+    (toc, body)
+}
+
+fn main() {
+    println!(
+        "{:<24} {:>10} {:>20} {:>20}",
+        "source", "compile", "headings (transform)", "headings (in place)",
+    );
+    for (name, source) in SOURCES {
+        let compile = bench(|| {
+            let document = Document::from_source(source);
+            std::hint::black_box(document.render_to_string());
+        });
+        let (nodes, _) = Compiler::new().run(source);
+        // BOTH ARE TIMED ON THE SAME TREE (CLONING IT IS PART OF NEITHER)
+        let time = |f: fn(Vec<Node>) -> (Node, Vec<Node>)| {
+            let mut total = Duration::ZERO;
+            for _ in 0..=RUNS {
+                let body = nodes.clone();
+                let start = Instant::now();
+                std::hint::black_box(f(body));
+                total += start.elapsed();
+            }
+            total / (RUNS + 1)
+        };
+        let by_transform = time(headings_by_transform);
+        let in_place = time(headings_in_place);
+        println!("{:<24} {:>10.2?} {:>20.2?} {:>20.2?}", name, compile, by_transform, in_place);
+    }
+}
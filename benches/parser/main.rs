@@ -0,0 +1,55 @@
+//! Parse time of the streaming parser versus the previous one (see
+//! `reference`) on multi-megabyte inputs (`cargo bench --bench parser`). That
+//! both produce the same trees is tested in `tests/parser_differential.rs`.
+use std::time::{Duration, Instant};
+use subscript_compiler::frontend::parser;
+
+mod reference;
+
+static MATHEMATICS: &str = include_str!("../../examples/source/mathematics.txt");
+
+/// The number of timed runs per input.
+static RUNS: u32 = 5;
+
+/// The mean duration of `f`, after a warm up run.
+fn bench(mut f: impl FnMut()) -> Duration {
+    f();
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        f();
+    }
+    start.elapsed() / RUNS
+}
+
+fn main() {
+    let mathematics = MATHEMATICS.repeat(64);
+    let whitespace = |size: usize| format!("\\note{}{{x}}", " \n".repeat(size / 2));
+    // THE REFERENCE PARSER IS QUADRATIC ON WHITESPACE, SO ONLY THE SMALL
+    // INPUT IS TIMED FOR BOTH
+    let inputs = vec![
+        ("mathematics ×64", mathematics, true),
+        ("whitespace 32 KB", whitespace(32 << 10), true),
+        ("whitespace 4 MB", whitespace(4 << 20), false),
+    ];
+    println!("{:<20} {:>10} {:>12} {:>12}", "input", "size", "reference", "streaming");
+    for (name, source, with_reference) in inputs {
+        let reference = if with_reference {
+            let duration = bench(|| {
+                std::hint::black_box(reference::parse_source(&source));
+            });
+            format!("{:.2?}", duration)
+        } else {
+            String::from("-")
+        };
+        let streaming = bench(|| {
+            std::hint::black_box(parser::parse_source(&source));
+        });
+        println!(
+            "{:<20} {:>7} KB {:>12} {:>12.2?}",
+            name,
+            source.len() >> 10,
+            reference,
+            streaming,
+        );
+    }
+}
@@ -0,0 +1,512 @@
+//! The parser as of before the streaming lexer, i.e. materializing every
+//! grapheme & word up front, and scanning ahead for the next non-whitespace
+//! word from each word. Kept verbatim (modulo imports) as the reference for
+//! the differential tests (see `tests/parser_differential.rs`) & the bench.
+#![allow(unused, clippy::all)]
+use std::borrow::Cow;
+use std::collections::{LinkedList, VecDeque};
+use unicode_segmentation::UnicodeSegmentation;
+use subscript_compiler::frontend::ast::*;
+use subscript_compiler::frontend::data::*;
+use subscript_compiler::frontend::parser::{Mode, OpenToken};
+
+enum ZipperConsumed {
+    Current,
+    Right,
+}
+
+/// Tracks whether the parser is about to see the body of some verbatim tag
+/// (see `VERBATIM_TAGS`). The payload is the scope depth of the tag, and for
+/// `AfterIdent`, the fence attached to the identifier (e.g. `\verb#{...}#`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VerbatimState<'a> {
+    Inactive,
+    AfterIdent(usize, &'a str),
+    InParameters(usize),
+}
+
+#[derive(Debug, Clone)]
+struct PartialBlock<'a> {
+    open_token: Ann<OpenToken>,
+    children: LinkedList<Node<'a>>,
+    /// Whether this enclosure is (within) some math tag, wherein e.g. `\{`
+    /// is a LaTeX escape rather than the start of inline math.
+    is_math: bool,
+}
+
+#[derive(Debug, Default)]
+struct ParseTree<'a> {
+    scopes: VecDeque<PartialBlock<'a>>,
+    finalized: LinkedList<Node<'a>>,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// PARSE-TREE UTILS
+///////////////////////////////////////////////////////////////////////////////
+
+impl<'a> ParseTree<'a> {
+    fn add_child_node(&mut self, new_node: Node<'a>) {
+        match self.scopes.back_mut() {
+            Some(scope) => {
+                scope.children.push_back(new_node);
+            }
+            None => {
+                self.finalized.push_back(new_node);
+            }
+        }
+    }
+    fn in_math_scope(&self) -> bool {
+        self.scopes
+            .back()
+            .map(|x| x.is_math)
+            .unwrap_or(false)
+    }
+    /// Whether the last (non-whitespace) node of the current scope is the
+    /// identifier of some math tag (e.g. `\equation` or `\{`).
+    fn follows_math_ident(&self) -> bool {
+        let children = match self.scopes.back() {
+            Some(scope) => &scope.children,
+            None => &self.finalized,
+        };
+        children
+            .iter()
+            .rev()
+            .find(|x| !x.is_whitespace())
+            .and_then(Node::unwrap_ident)
+            .map(|x| x.data == INLINE_MATH_TAG || BLOCK_MATH_TAGS.contains(&x.data.as_ref()))
+            .unwrap_or(false)
+    }
+    fn open_new_enclosure(&mut self, new_enclosure: PartialBlock<'a>) {
+        self.scopes.push_back(new_enclosure);
+    }
+    fn close_last_enclosure(&mut self, close_word: &Word<'a>) {
+        match self.scopes.pop_back() {
+            Some(scope) => {
+                let new_node = Enclosure {
+                    kind: EnclosureKind::new(
+                        Cow::Borrowed(scope.open_token.data.as_str()),
+                        Cow::Borrowed(close_word.word),
+                    ),
+                    children: scope.children.into_iter().collect()
+                };
+                let range = {
+                    let start = scope.open_token.start();
+                    let end = close_word.range.end;
+                    CharRange::join(start, Some(end))
+                };
+                self.add_child_node(Node::Enclosure(Ann::join(range, new_node)));
+            }
+            None => {
+                let new_node = Node::InvalidToken(Ann::new(
+                    close_word.range,
+                    Cow::Borrowed(close_word.word),
+                ));
+                self.add_child_node(new_node);
+            }
+        }
+    }
+    pub fn finalize_all(self) -> Vec<Node<'a>> {
+        let ParseTree { mut scopes, mut finalized } = self;
+        let scopes = scopes.drain(..);
+        let xs = scopes
+            .map(|scope| {
+                let enclosure = Enclosure{
+                    kind: EnclosureKind::Error{
+                        open: Cow::Borrowed(scope.open_token.data.as_str()),
+                        close: None
+                    },
+                    children: scope.children.into_iter().collect()
+                };
+                Node::Enclosure(Ann::join(
+                    scope.open_token.range(),
+                    enclosure,
+                ))
+            });
+        finalized.extend(xs);
+        finalized.into_iter().collect()
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// VERBATIM BODIES
+///////////////////////////////////////////////////////////////////////////////
+
+fn is_fence(word: &str) -> bool {
+    !word.is_empty() && word.chars().all(|x| x == '#')
+}
+
+/// Splits some identifier such as `verb##` into the name and its fence.
+fn split_fence(ident: &str) -> (&str, &str) {
+    let name = ident.trim_end_matches('#');
+    (name, &ident[name.len()..])
+}
+
+/// Consumes the body of some verbatim tag, where `start` is the position of the
+/// opening fence (or curly brace) and `open` the position of the curly brace.
+/// Returns the enclosure and the position of the last consumed word.
+///
+/// Without a fence the body ends at the matching (balanced) closing brace,
+/// otherwise it ends at the first `}` followed by the same number of `#`s.
+fn parse_verbatim_body<'a>(
+    words: &[Word<'a>],
+    start: usize,
+    open: usize,
+    fence: &str,
+) -> (Node<'a>, usize) {
+    let mut close = None::<(usize, usize)>;
+    let mut depth = 0usize;
+    for pos in (open + 1)..words.len() {
+        match words[pos].word {
+            "{" if fence.is_empty() => {
+                depth += 1;
+            }
+            "}" if fence.is_empty() && depth > 0 => {
+                depth -= 1;
+            }
+            "}" if fence.is_empty() => {
+                close = Some((pos, pos));
+                break;
+            }
+            "}" if words.get(pos + 1).map(|x| x.word == fence).unwrap_or(false) => {
+                close = Some((pos, pos + 1));
+                break;
+            }
+            _ => ()
+        }
+    }
+    let body_end = close.map(|(x, _)| x).unwrap_or(words.len());
+    let body = &words[(open + 1)..body_end];
+    let text = body
+        .iter()
+        .map(|x| x.word)
+        .collect::<String>();
+    let text_range = CharRange::new(
+        words[open].range.end,
+        body.last().map(|x| x.range.end).unwrap_or(words[open].range.end),
+    );
+    let children = vec![Node::String(Ann::new(text_range, Cow::Owned(text)))];
+    match close {
+        Some((_, last)) => {
+            let range = CharRange::new(words[start].range.start, words[last].range.end);
+            let node = Node::new_enclosure(range, EnclosureKind::CurlyBrace, children);
+            (node, last)
+        }
+        None => {
+            let last = words.len() - 1;
+            let range = CharRange::new(words[start].range.start, words[last].range.end);
+            let kind = EnclosureKind::Error {
+                open: Cow::Borrowed("{"),
+                close: None,
+            };
+            (Node::new_enclosure(range, kind, children), last)
+        }
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// CORE PARSER ENGINE
+///////////////////////////////////////////////////////////////////////////////
+
+
+impl<'a> ParseTree<'a> {
+    pub fn parse_words(words: Vec<Word<'a>>) -> Vec<Node<'a>> {
+        let mut parse_tree = ParseTree::default();
+        let mut skip_to: Option<usize> = None;
+        let mut verbatim = VerbatimState::Inactive;
+        for pos in 0..words.len() {
+            if let Some(start_from) = skip_to {
+                if pos <= start_from {
+                    continue;
+                } else {
+                    skip_to = None;
+                }
+            }
+            // VERBATIM TAG BODIES
+            if let VerbatimState::AfterIdent(depth, fence) = verbatim {
+                let word = words[pos].word;
+                let is_next_open = words
+                    .get(pos + 1)
+                    .map(|x| x.word == "{")
+                    .unwrap_or(false);
+                let body = match word {
+                    "{" => Some((pos, fence)),
+                    _ if fence.is_empty() && is_fence(word) && is_next_open => Some((pos + 1, word)),
+                    _ => None,
+                };
+                if let Some((open, fence)) = body.filter(|_| depth == parse_tree.scopes.len()) {
+                    let (node, last) = parse_verbatim_body(&words, pos, open, fence);
+                    parse_tree.add_child_node(node);
+                    skip_to = Some(last);
+                    verbatim = VerbatimState::Inactive;
+                    continue;
+                }
+                if word == "[" {
+                    verbatim = VerbatimState::InParameters(depth);
+                } else if !words[pos].is_whitespace() {
+                    verbatim = VerbatimState::Inactive;
+                }
+            }
+            // ESCAPE SEQUENCES (E.G. `\}`)
+            if words[pos].word == "\\" {
+                let in_math = parse_tree.in_math_scope();
+                let escape = words
+                    .get(pos + 1)
+                    .filter(|x| x.word != "{" || in_math)
+                    .and_then(|x| escape_sequence(x.word).map(|escape| (x, escape)));
+                if let Some((next, escape)) = escape {
+                    let range = CharRange::new(words[pos].range.start, next.range.end);
+                    parse_tree.add_child_node(Node::String(Ann::new(
+                        range,
+                        Cow::Borrowed(escape),
+                    )));
+                    skip_to = Some(pos + 1);
+                    continue;
+                }
+            }
+            let forward = |by: usize| {
+                words
+                    .get(pos + by)
+                    .filter(|w| !w.is_whitespace())
+                    .map(|w| (by, w))
+            };
+            let current = &words[pos];
+            let next = {
+                let mut entry = None::<(usize, &Word)>;
+                let words_left = words.len() - pos;
+                for offset in 1..words_left {
+                    assert!(entry.is_none());
+                    entry = forward(offset);
+                    if entry.is_some() {break}
+                }
+                entry
+            };
+            let (mode, consumed) = match_word(
+                current.word,
+                next.map(|(_, x)| x.word)
+            );
+            match mode {
+                Mode::BeginEnclosure {kind} => {
+                    let start_pos = current.range.start;
+                    let new_stack = PartialBlock {
+                        open_token: Ann::new(
+                            current.range,
+                            OpenToken::new(Cow::Borrowed(kind)).unwrap()
+                        ),
+                        children: Default::default(),
+                        is_math: parse_tree.in_math_scope() || parse_tree.follows_math_ident(),
+                    };
+                    parse_tree.open_new_enclosure(new_stack);
+                }
+                Mode::EndEnclosure {kind: close_token} => {
+                    parse_tree.close_last_enclosure(current);
+                    if verbatim == VerbatimState::InParameters(parse_tree.scopes.len()) {
+                        verbatim = VerbatimState::AfterIdent(parse_tree.scopes.len(), "");
+                    }
+                }
+                Mode::Ident(ident) => {
+                    let (name, fence) = split_fence(ident);
+                    if VERBATIM_TAGS.contains(&name) {
+                        verbatim = VerbatimState::AfterIdent(parse_tree.scopes.len(), fence);
+                    }
+                    let ident = if VERBATIM_TAGS.contains(&name) {name} else {ident};
+                    let start = current.range.start;
+                    let end = next
+                        .map(|x| x.1.range.end)
+                        .unwrap_or(current.range.end);
+                    let new_node = Node::Ident(Ann::new(
+                        CharRange::new(
+                            start,
+                            end,
+                        ),
+                        Atom::Borrowed(ident)
+                    ));
+                    parse_tree.add_child_node(new_node);
+                }
+                Mode::NoOP => {
+                    let new_node = Node::String(Ann::new(
+                        current.range,
+                        Cow::Borrowed(current.word)
+                    ));
+                    parse_tree.add_child_node(new_node);
+                }
+            }
+            // FINALIZE
+            match consumed {
+                ZipperConsumed::Current => (),
+                ZipperConsumed::Right => {
+                    assert!(next.is_some());
+                    let offset = next.unwrap().0;
+                    skip_to = Some(pos + offset);
+                }
+            }
+        }
+        parse_tree.finalize_all()
+    }
+}
+
+
+
+pub fn parse_source<'a>(source: &'a str) -> Vec<Node<'a>> {
+    let words = init_words(source, init_characters(source));
+    ParseTree::parse_words(words)
+}
+
+#[derive(Debug, Clone)]
+pub struct Character<'a> {
+    range: CharRange,
+    char: &'a str,
+}
+
+impl<'a> Character<'a> {
+    pub fn is_whitespace(&self) -> bool {
+        self.char.chars().any(|x| x.is_whitespace())
+    }
+}
+
+pub fn init_characters<'a>(source: &'a str) -> Vec<Character<'a>> {
+    use itertools::Itertools;
+    let ending_byte_size = source.len();
+    let words = source
+        .grapheme_indices(true)
+        .enumerate()
+        .map(|(cix, (bix, x))| {
+            let index = CharIndex {
+                byte_index: bix,
+                char_index: cix,
+            };
+            (index, x)
+        })
+        .collect_vec();
+    let mut output = Vec::new();
+    for pos in 0..words.len() {
+        let (start, current) = words[pos];
+        let end = words
+            .get(pos + 1)
+            .map(|(pos, _)| *pos)
+            .unwrap_or_else(|| {
+                CharIndex {
+                    byte_index: ending_byte_size,
+                    char_index: pos + 1
+                }
+            });
+        output.push(Character{
+            range: CharRange{ start, end},
+            char: current
+        });
+    }
+    output
+}
+
+#[derive(Debug, Clone)]
+pub struct Word<'a> {
+    range: CharRange,
+    word: &'a str,
+}
+
+impl<'a> Word<'a> {
+    pub fn is_whitespace(&self) -> bool {
+        self.word.trim().is_empty()
+    }
+}
+
+pub fn init_words<'a>(source: &'a str, chars: Vec<Character<'a>>) -> Vec<Word<'a>> {
+    use itertools::Itertools;
+    // let mut output = Vec::new();
+    let mut current_word_start = 0usize;
+    chars
+        .into_iter()
+        .group_by(|char| {
+            if char.is_whitespace() {
+                return true
+            }
+            match char.char {
+                "\\" => true,
+                "{" => true,
+                "}" => true,
+                "[" => true,
+                "]" => true,
+                "(" => true,
+                ")" => true,
+                "=" => true,
+                ">" => true,
+                "_" => true,
+                "." => true,
+                "^" => true,
+                // INLINE FORMATTING MARKERS (SO THEY MAY BE ESCAPED)
+                "*" => true,
+                "`" => true,
+                _ => false
+            }
+        })
+        .into_iter()
+        .flat_map(|(key, chars)| {
+            let chars = chars.into_iter().collect_vec();
+            if key || chars.len() < 2 {
+                let chars = chars
+                    .into_iter()
+                    .map(|char| {
+                        Word {
+                            range: char.range,
+                            word: char.char,
+                        }
+                    })
+                    .collect_vec();
+                return chars;
+            }
+            let start = {
+                (&chars[0]).range.start
+            };
+            let end = {
+                (&chars[chars.len() - 1]).range.end
+            };
+            let word = &source[start.byte_index..end.byte_index];
+            let word = Word {
+                range: CharRange{start, end},
+                word,
+            };
+            vec![word]
+        })
+        .collect::<Vec<_>>()
+}
+
+fn match_word<'a>(current: &'a str, next: Option<&'a str>) -> (Mode<'a>, ZipperConsumed) {
+    match (current, next) {
+        ("\\", Some(next)) if next == "{"  => (
+            Mode::Ident(INLINE_MATH_TAG),
+            ZipperConsumed::Current,
+        ),
+        ("\\", Some(ident)) if !is_token(ident) && ident != " " => (
+            Mode::Ident(ident),
+            ZipperConsumed::Right
+        ),
+        (tk @ "{", _) => (
+            Mode::BeginEnclosure{kind: tk},
+            ZipperConsumed::Current
+        ),
+        (tk @ "[", _) => (
+            Mode::BeginEnclosure{kind: tk},
+            ZipperConsumed::Current
+        ),
+        (tk @ "(", _) => (
+            Mode::BeginEnclosure{kind: tk},
+            ZipperConsumed::Current
+        ),
+        (tk @ "}", _) => (
+            Mode::EndEnclosure{kind: tk},
+            ZipperConsumed::Current
+        ),
+        (tk @ "]", _) => (
+            Mode::EndEnclosure{kind: tk},
+            ZipperConsumed::Current
+        ),
+        (tk @ ")", _) => (
+            Mode::EndEnclosure{kind: tk},
+            ZipperConsumed::Current
+        ),
+        _ => (Mode::NoOP, ZipperConsumed::Current),
+    }
+}
+
+
+
+
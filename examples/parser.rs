@@ -4,8 +4,7 @@ use subscript_compiler::frontend::parser::*;
 
 fn main() {
     let source = include_str!("./other/valid.txt");
-    let words = init_words(source, init_characters(source));
-    let nodes = ParseTree::parse_words(words);
+    let nodes = ParseTree::parse_words(Lexer::new(source));
     for node in nodes {
         println!("{:#?}", node);
     }
@@ -1,5 +1,8 @@
-use std::path::PathBuf;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
+use crate::codegen::config::{Config, PassesConfig};
+use crate::codegen::settings::Settings;
 
 #[derive(StructOpt, Debug)]
 #[structopt(
@@ -12,28 +15,375 @@ enum Cli {
         source: PathBuf,
         #[structopt(short, long, parse(from_os_str))]
         output: Option<PathBuf>,
+        /// Curly quotes, dashes, ellipses & non-breaking spaces.
+        #[structopt(long)]
+        typography: bool,
+        /// The inline shorthand for bold, italic, code & links (e.g.
+        /// `**bold**`).
+        #[structopt(long)]
+        inline_format: bool,
+        /// Infer paragraphs from blank lines.
+        #[structopt(long)]
+        paragraphs: bool,
+        /// The document language (e.g. `en` or `de`).
+        #[structopt(long)]
+        lang: Option<String>,
+        /// Include the print stylesheet (for printing or saving as PDF).
+        #[structopt(long)]
+        print: bool,
+        /// The `\solution` blocks: `hidden` (collapsed, the default), `strip`
+        /// (e.g. for handouts) or `appendix`.
+        #[structopt(long)]
+        solutions: Option<String>,
+        /// The color theme: `light`, `dark`, `sepia` or `auto` (i.e. follow
+        /// the system).
+        #[structopt(long)]
+        theme: Option<String>,
+        /// Additional CSS files, included after the built-in stylesheet.
+        #[structopt(long = "stylesheet", parse(from_os_str))]
+        stylesheets: Vec<PathBuf>,
+        /// Only use the given `--stylesheet` files.
+        #[structopt(long)]
+        replace_stylesheet: bool,
+        /// The output format: `html`, `slides`, `markdown` or `text`. Defaults
+        /// to the `backends` of `subscript.toml`, or `html`.
+        #[structopt(long)]
+        format: Option<String>,
     },
+    /// Package one or more documents (as chapters, in the given order) into an
+    /// EPUB 3 book.
+    Epub {
+        #[structopt(parse(from_os_str), required = true)]
+        sources: Vec<PathBuf>,
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+        /// Defaults to the first heading of the first chapter.
+        #[structopt(long)]
+        title: Option<String>,
+        /// Curly quotes, dashes, ellipses & non-breaking spaces.
+        #[structopt(long)]
+        typography: bool,
+        /// The inline shorthand for bold, italic, code & links (e.g.
+        /// `**bold**`).
+        #[structopt(long)]
+        inline_format: bool,
+        /// Infer paragraphs from blank lines.
+        #[structopt(long)]
+        paragraphs: bool,
+        /// The book language (e.g. `en` or `de`).
+        #[structopt(long)]
+        lang: Option<String>,
+    },
+    /// Compile every source file of the given directory into HTML (mirroring
+    /// the directory layout), with a search index.
+    Site {
+        #[structopt(parse(from_os_str))]
+        root: PathBuf,
+        /// Defaults to the `output-dir` of the `subscript.toml` of the root.
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Convert a document in some other markup language into Subscript.
+    Import {
+        /// The source format (currently only `markdown`).
+        #[structopt(long)]
+        from: String,
+        #[structopt(short, long, parse(from_os_str))]
+        source: PathBuf,
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Loads the project configuration (if any) & the front matter of the given
+/// source, and reports their diagnostics. Returns the merged configuration,
+/// the project directory, and the source without its front matter.
+fn load_config<'a>(source_path: &Path, source: &'a str) -> (Config, Option<PathBuf>, Cow<'a, str>) {
+    let project_file = crate::codegen::config::discover(source_path);
+    let project_config = match project_file.as_ref() {
+        Some(project_file) => {
+            let (config, diagnostics) = Config::load(project_file);
+            let project_source = std::fs::read_to_string(project_file).unwrap_or_default();
+            for diagnostic in diagnostics {
+                eprintln!("{}", diagnostic.render(&project_file.to_string_lossy(), &project_source));
+            }
+            config
+        }
+        None => Config::default(),
+    };
+    let (front_matter, source) = crate::codegen::config::split_front_matter(source);
+    let config = match front_matter {
+        Some(front_matter) => {
+            let base = source_path.parent().unwrap_or_else(|| Path::new(""));
+            let (config, diagnostics) = Config::from_front_matter(front_matter, base);
+            for diagnostic in diagnostics {
+                eprintln!("{}", diagnostic.render(&source_path.to_string_lossy(), front_matter));
+            }
+            project_config.merge(config)
+        }
+        None => project_config,
+    };
+    let project_dir = project_file.and_then(|x| x.parent().map(ToOwned::to_owned));
+    (config, project_dir, source)
+}
+
+/// The settings for the given source; reports the configuration diagnostics.
+fn source_settings(config: &Config, source_path: &Path) -> Settings {
+    let mut settings = Settings {
+        source_dir: source_path.parent().map(ToOwned::to_owned),
+        ..Default::default()
+    };
+    for diagnostic in config.apply(&mut settings) {
+        eprintln!("{}", diagnostic.render(&source_path.to_string_lossy(), ""));
+    }
+    settings
+}
+
+/// Command line flags only ever enable settings.
+fn flag(enabled: bool) -> Option<bool> {
+    if enabled {Some(true)} else {None}
 }
 
 pub fn run_cli() {
     match Cli::from_args() {
-        Cli::Compile{source: source_path, output} => {
+        Cli::Compile{
+            source: source_path,
+            output,
+            typography,
+            inline_format,
+            paragraphs,
+            lang,
+            print,
+            solutions,
+            theme,
+            stylesheets,
+            replace_stylesheet,
+            format,
+        } => {
             let source = std::fs::read_to_string(&source_path).unwrap();
-            let output_path = output.unwrap_or_else(|| {
-                let source_path = source_path.clone();
-                let default = std::ffi::OsStr::new("html");
-                let ext = source_path.extension().unwrap_or(default);
-                let mut path = source_path.clone();
-                assert!(path.set_extension(ext));
-                path
+            let (config, project_dir, source) = load_config(&source_path, &source);
+            // COMMAND LINE FLAGS OVERRIDE THE CONFIGURATION
+            let config = config.merge(Config {
+                language: lang,
+                print: flag(print),
+                solutions,
+                theme,
+                stylesheets,
+                replace_stylesheet: flag(replace_stylesheet),
+                passes: PassesConfig {
+                    typography: flag(typography),
+                    inline_format: flag(inline_format),
+                    paragraphs: flag(paragraphs),
+                    ..Default::default()
+                },
+                ..Default::default()
             });
+            let formats = match format {
+                Some(format) => vec![format],
+                None => config.backends.clone().unwrap_or_else(|| vec![String::from("html")]),
+            };
+            // THE SOURCE PATH RELATIVE TO THE PROJECT
+            let relative_path = project_dir
+                .zip(source_path.canonicalize().ok())
+                .and_then(|(dir, path)| path.strip_prefix(dir).ok().map(ToOwned::to_owned))
+                .or_else(|| source_path.file_name().map(PathBuf::from))
+                .unwrap_or_else(|| source_path.clone());
+            let settings = source_settings(&config, &source_path);
+            for format in formats.iter() {
+                let (format, ext) = match format.as_str() {
+                    "html" => ("html", "html"),
+                    "slides" => ("slides", "html"),
+                    "markdown" | "md" => ("markdown", "md"),
+                    "text" | "txt" => ("text", "txt"),
+                    _ => {
+                        eprintln!("[ERROR!] unsupported output format {:?}; expected `html`, `slides`, `markdown` or `text`", format);
+                        std::process::exit(1);
+                    }
+                };
+                let output_path = match (output.as_ref(), config.output_dir.as_ref()) {
+                    (Some(output), _) if formats.len() == 1 => output.clone(),
+                    (Some(output), _) => output.with_extension(ext),
+                    (None, Some(output_dir)) => output_dir.join(&relative_path).with_extension(ext),
+                    (None, None) => source_path.with_extension(ext),
+                };
+                if let Some(parent) = output_path.parent() {
+                    let _ = std::fs::create_dir_all(parent);
+                }
+                let settings = Settings {
+                    output_dir: output_path.parent().map(ToOwned::to_owned),
+                    ..settings.clone()
+                };
+                // let output = crate::frontend::pass::to_html::compile_to_html(&source);
+                let (diagnostics, output) = match format {
+                    "slides" => {
+                        let output = crate::codegen::slides::Document::from_source_with(&source, &settings);
+                        (output.diagnostics.clone(), output.render_to_string())
+                    }
+                    "markdown" => {
+                        let output = crate::codegen::markdown::Document::from_source_with(&source, &settings);
+                        (output.diagnostics.clone(), output.render_to_string())
+                    }
+                    "text" => {
+                        let output = crate::codegen::text::Document::from_source_with(&source, &settings);
+                        (output.diagnostics.clone(), output.render_to_string())
+                    }
+                    _ => {
+                        let output = crate::codegen::html::Document::from_source_with(&source, &settings);
+                        (output.diagnostics.clone(), output.render_to_string())
+                    }
+                };
+                let file_name = source_path.to_string_lossy();
+                for diagnostic in diagnostics.iter() {
+                    eprintln!("{}", diagnostic.render(&file_name, &source));
+                }
+                std::fs::write(&output_path, output).unwrap();
+            }
+        }
+        Cli::Epub{sources, output, title, typography, inline_format, paragraphs, lang} => {
+            let mut book = crate::codegen::epub::Book {
+                title,
+                language: lang.clone(),
+                ..Default::default()
+            };
+            for source_path in sources {
+                let source = std::fs::read_to_string(&source_path).unwrap();
+                let (config, _, source) = load_config(&source_path, &source);
+                let config = config.merge(Config {
+                    language: lang.clone(),
+                    passes: PassesConfig {
+                        typography: flag(typography),
+                        inline_format: flag(inline_format),
+                        paragraphs: flag(paragraphs),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                });
+                let settings = source_settings(&config, &source_path);
+                if book.language.is_none() {
+                    book.language = settings.language.clone();
+                }
+                let file_name = source_path.to_string_lossy();
+                for diagnostic in book.add_chapter(&source, &settings) {
+                    eprintln!("{}", diagnostic.render(&file_name, &source));
+                }
+            }
+            let settings = Settings {
+                language: book.language.clone(),
+                ..Default::default()
+            };
+            for diagnostic in book.add_glossary(&settings) {
+                eprintln!("{}", diagnostic.render("glossary", ""));
+            }
+            if let Some(parent) = output.parent() {
+                let _ = std::fs::create_dir_all(parent);
+            }
+            std::fs::write(&output, book.render_to_bytes().unwrap()).unwrap();
+        }
+        Cli::Site{root, output} => {
+            let project_file = root.join(crate::codegen::config::CONFIG_FILE_NAME);
+            let project_config = if project_file.is_file() {
+                Config::load(&project_file).0
+            } else {
+                Config::default()
+            };
+            let output_dir = match output.or(project_config.output_dir) {
+                Some(output_dir) => output_dir,
+                None => {
+                    eprintln!("[ERROR!] no output directory; use `--output` or the `output-dir` of {}", crate::codegen::config::CONFIG_FILE_NAME);
+                    std::process::exit(1);
+                }
+            };
+            let _ = std::fs::create_dir_all(&output_dir);
+            let pages = crate::codegen::site::discover_pages(&root, Some(&output_dir));
+            let (pages, diagnostics) = crate::codegen::site::order_pages(
+                pages,
+                project_config.order.as_deref().unwrap_or_default(),
+            );
+            for diagnostic in diagnostics {
+                eprintln!("{}", diagnostic.render(&project_file.to_string_lossy(), ""));
+            }
+            let sources = pages
+                .iter()
+                .map(|page| {
+                    let source = std::fs::read_to_string(&page.source_path).unwrap();
+                    let (config, _, source) = load_config(&page.source_path, &source);
+                    let settings = source_settings(&config, &page.source_path);
+                    (settings, source.into_owned())
+                })
+                .collect::<Vec<_>>();
+            // THE GLOSSARY & INDEX SPAN ALL PAGES
+            let glossary = pages
+                .iter()
+                .zip(sources.iter())
+                .flat_map(|(page, (settings, source))| {
+                    crate::frontend::pass::glossary::entries(source, settings, Some(&page.href()))
+                })
+                .collect::<Vec<_>>();
+            let sources = pages
+                .iter()
+                .zip(sources)
+                .map(|(page, (settings, source))| {
+                    let glossary = crate::frontend::pass::glossary::rooted(&glossary, &page.root());
+                    let settings = Settings {glossary: Some(glossary), ..settings};
+                    (settings, source)
+                })
+                .collect::<Vec<_>>();
+            // COMPILE EVERY PAGE FIRST (THE NAVIGATION NEEDS THEIR TITLES)
+            let outputs = pages
+                .iter()
+                .zip(sources.iter())
+                .map(|(page, (settings, source))| {
+                    let output_path = output_dir.join(page.relative_path.with_extension("html"));
+                    if let Some(parent) = output_path.parent() {
+                        let _ = std::fs::create_dir_all(parent);
+                    }
+                    let settings = Settings {
+                        output_dir: output_path.parent().map(ToOwned::to_owned),
+                        search_root: Some(page.root()),
+                        ..settings.clone()
+                    };
+                    let output = crate::codegen::html::Document::from_source_with(source, &settings);
+                    let file_name = page.source_path.to_string_lossy();
+                    for diagnostic in output.diagnostics.iter() {
+                        eprintln!("{}", diagnostic.render(&file_name, source));
+                    }
+                    (output_path, output)
+                })
+                .collect::<Vec<_>>();
+            let titles = outputs
+                .iter()
+                .map(|(_, output)| output.title.clone())
+                .collect::<Vec<_>>();
+            let mut documents = Vec::new();
+            for (ix, (output_path, mut output)) in outputs.into_iter().enumerate() {
+                let page = &pages[ix];
+                documents.push(crate::codegen::search::SearchDocument {
+                    href: page.href(),
+                    title: output.title.clone(),
+                    sections: crate::codegen::search::index_source(&sources[ix].1, &output.title, &output.settings),
+                });
+                output.settings.navigation = Some(crate::codegen::site::Navigation::new(&pages, &titles, ix));
+                std::fs::write(&output_path, output.render_to_string()).unwrap();
+            }
+            let index = crate::codegen::search::render_search_index(&documents);
+            std::fs::write(output_dir.join(crate::codegen::search::SEARCH_INDEX_FILE_NAME), index).unwrap();
+        }
+        Cli::Import{from, source: source_path, output} => {
+            let source = std::fs::read_to_string(&source_path).unwrap();
+            let result = match from.as_str() {
+                "markdown" | "md" | "commonmark" => {
+                    crate::frontend::import::markdown::markdown_to_subscript(&source)
+                }
+                _ => {
+                    eprintln!("[ERROR!] unsupported import format {:?}; expected `markdown`", from);
+                    std::process::exit(1);
+                }
+            };
+            let output_path = output.unwrap_or_else(|| source_path.with_extension("txt"));
             if let Some(parent) = output_path.parent() {
                 let _ = std::fs::create_dir_all(parent);
             }
-            // let output = crate::frontend::pass::to_html::compile_to_html(&source);
-            let output = crate::codegen::html::Document::from_source(&source);
-            let output = output.render_to_string();
-            std::fs::write(&output_path, output).unwrap();
+            std::fs::write(&output_path, result).unwrap();
         }
     }
 }
@@ -0,0 +1,535 @@
+//! Project (`subscript.toml`) & per-document (front matter) configuration.
+//!
+//! The project file is discovered in the directory of the source file or any
+//! of its parents, and the front matter is a TOML block at the very start of
+//! the document:
+//!
+//! ```text
+//! +++
+//! language = "de"
+//! stylesheets = ["chapter.css"]
+//! +++
+//! \h1{Kapitel 1}
+//! ```
+//!
+//! Document settings override the project settings, and command line flags
+//! override both. Relative paths are resolved against the directory of the
+//! file they appear in. Unknown keys are reported (with their line) rather
+//! than ignored.
+//!
+//! ```toml
+//! output-dir = "build"
+//! order = ["index.txt", "notes/introduction.txt"]
+//! backends = ["html", "markdown"]
+//! template = "template.html"
+//! theme = "auto"
+//! stylesheets = ["extra.css"]
+//! replace-stylesheet = false
+//! allowed-tags = ["kbd-key"]
+//! language = "en"
+//! print = false
+//! solutions = "hidden"
+//!
+//! [passes]
+//! typography = true
+//! tables = true
+//! inline-format = true
+//! paragraphs = true
+//!
+//! [math.macros]
+//! RR = "\\mathbb{R}"
+//! norm = { body = "\\left\\| #1 \\right\\|", args = 1 }
+//! ```
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::frontend::ast::{CharIndex, CharRange};
+use crate::frontend::diagnostic::Diagnostic;
+use crate::codegen::settings::{MathMacro, Settings, Solutions};
+
+/// The name of the project configuration file.
+pub static CONFIG_FILE_NAME: &str = "subscript.toml";
+
+/// The line delimiting the front matter.
+pub static FRONT_MATTER_DELIMITER: &str = "+++";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    /// Where compiled documents are written, mirroring the directory layout
+    /// of the sources (relative to the project file).
+    pub output_dir: Option<PathBuf>,
+    /// The order of the pages of a site (relative to the project file); the
+    /// remaining pages follow in file name order (see `codegen::site`).
+    pub order: Option<Vec<PathBuf>>,
+    /// The output formats (see `compile --format`).
+    pub backends: Option<Vec<String>>,
+    pub template: Option<PathBuf>,
+    /// One of `html::THEMES`.
+    pub theme: Option<String>,
+    #[serde(default)]
+    pub stylesheets: Vec<PathBuf>,
+    /// Only use the given stylesheets, rather than the built-in stylesheet &
+    /// theme.
+    pub replace_stylesheet: Option<bool>,
+    /// Custom tags, in addition to the Subscript & HTML tags (see
+    /// `pass::tags`).
+    #[serde(default)]
+    pub allowed_tags: Vec<String>,
+    pub language: Option<String>,
+    pub print: Option<bool>,
+    /// One of `Solutions::NAMES`.
+    pub solutions: Option<String>,
+    #[serde(default)]
+    pub passes: PassesConfig,
+    #[serde(default)]
+    pub math: MathConfig,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct PassesConfig {
+    pub typography: Option<bool>,
+    pub tables: Option<bool>,
+    pub inline_format: Option<bool>,
+    pub paragraphs: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct MathConfig {
+    #[serde(default)]
+    pub macros: BTreeMap<String, MacroDefinition>,
+}
+
+/// E.g. `RR = "\\mathbb{R}"` or `norm = { body = "...", args = 1 }`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum MacroDefinition {
+    Simple(String),
+    WithArguments {
+        body: String,
+        #[serde(default)]
+        args: usize,
+    },
+}
+
+impl Config {
+    /// Parses the given TOML; unknown keys are reported as warnings.
+    pub fn parse(source: &str) -> (Config, Vec<Diagnostic>) {
+        let mut diagnostics = Vec::new();
+        let mut unknown_keys = Vec::new();
+        let mut deserializer = toml::Deserializer::new(source);
+        let result = serde_ignored::deserialize(&mut deserializer, |path| {
+            unknown_keys.push(path.to_string());
+        });
+        let config = match result {
+            Ok(config) => config,
+            Err(error) => {
+                diagnostics.push(Diagnostic::error(None, format!("invalid configuration: {}", error)));
+                return (Config::default(), diagnostics)
+            }
+        };
+        for key in unknown_keys {
+            let message = format!("unknown configuration key `{}`", key);
+            diagnostics.push(Diagnostic::warning(key_range(source, &key), message));
+        }
+        (config, diagnostics)
+    }
+    /// Loads the given configuration file.
+    pub fn load(path: &Path) -> (Config, Vec<Diagnostic>) {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(error) => {
+                let message = format!("failed to read {}: {}", path.display(), error);
+                return (Config::default(), vec![Diagnostic::error(None, message)])
+            }
+        };
+        let (config, diagnostics) = Config::parse(&source);
+        let base = path.parent().unwrap_or_else(|| Path::new(""));
+        (config.resolve_paths(base), diagnostics)
+    }
+    /// Parses the given front matter (see `split_front_matter`), relative to
+    /// the document directory.
+    pub fn from_front_matter(source: &str, base: &Path) -> (Config, Vec<Diagnostic>) {
+        let (mut config, mut diagnostics) = Config::parse(source);
+        // PROJECT LEVEL SETTINGS
+        let mut ignored = Vec::new();
+        if config.output_dir.take().is_some() {
+            ignored.push("output-dir");
+        }
+        if config.order.take().is_some() {
+            ignored.push("order");
+        }
+        if config.backends.take().is_some() {
+            ignored.push("backends");
+        }
+        for key in ignored {
+            let message = format!("`{}` is only supported in {}; ignored", key, CONFIG_FILE_NAME);
+            diagnostics.push(Diagnostic::warning(key_range(source, key), message));
+        }
+        (config.resolve_paths(base), diagnostics)
+    }
+    fn resolve_paths(mut self, base: &Path) -> Config {
+        self.output_dir = self.output_dir.map(|x| base.join(x));
+        self.order = self.order.map(|x| x.into_iter().map(|x| base.join(x)).collect());
+        self.template = self.template.map(|x| base.join(x));
+        self.stylesheets = self.stylesheets
+            .into_iter()
+            .map(|x| base.join(x))
+            .collect();
+        self
+    }
+    /// The settings of `other` take precedence; stylesheets, allowed tags &
+    /// macros are combined.
+    pub fn merge(self, other: Config) -> Config {
+        let mut stylesheets = self.stylesheets;
+        stylesheets.extend(other.stylesheets);
+        let mut allowed_tags = self.allowed_tags;
+        allowed_tags.extend(other.allowed_tags);
+        let mut macros = self.math.macros;
+        macros.extend(other.math.macros);
+        Config {
+            output_dir: other.output_dir.or(self.output_dir),
+            order: other.order.or(self.order),
+            backends: other.backends.or(self.backends),
+            template: other.template.or(self.template),
+            theme: other.theme.or(self.theme),
+            stylesheets,
+            replace_stylesheet: other.replace_stylesheet.or(self.replace_stylesheet),
+            allowed_tags,
+            language: other.language.or(self.language),
+            print: other.print.or(self.print),
+            solutions: other.solutions.or(self.solutions),
+            passes: PassesConfig {
+                typography: other.passes.typography.or(self.passes.typography),
+                tables: other.passes.tables.or(self.passes.tables),
+                inline_format: other.passes.inline_format.or(self.passes.inline_format),
+                paragraphs: other.passes.paragraphs.or(self.passes.paragraphs),
+            },
+            math: MathConfig {macros},
+        }
+    }
+    /// Applies the configuration to the given settings; reads the template &
+    /// stylesheets.
+    pub fn apply(&self, settings: &mut Settings) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut read = |path: &Path| match std::fs::read_to_string(path) {
+            Ok(contents) => Some(contents),
+            Err(error) => {
+                let message = format!("failed to read {}: {}", path.display(), error);
+                diagnostics.push(Diagnostic::error(None, message));
+                None
+            }
+        };
+        if let Some(template) = self.template.as_ref().and_then(|x| read(x)) {
+            settings.template = Some(template);
+        }
+        settings.stylesheets.extend(self.stylesheets.iter().filter_map(|x| read(x)));
+        if let Some(theme) = self.theme.as_ref() {
+            if crate::codegen::html::theme_stylesheet(theme).is_some() {
+                settings.theme = Some(theme.clone());
+            } else {
+                let message = format!(
+                    "unknown theme `{}`; expected one of {}",
+                    theme,
+                    crate::codegen::html::THEMES.join(", "),
+                );
+                diagnostics.push(Diagnostic::error(None, message));
+            }
+        }
+        if let Some(replace_stylesheet) = self.replace_stylesheet {
+            settings.replace_stylesheet = replace_stylesheet;
+        }
+        settings.allowed_tags.extend(self.allowed_tags.iter().cloned());
+        if let Some(language) = self.language.clone() {
+            settings.language = Some(language);
+        }
+        if let Some(print) = self.print {
+            settings.print = print;
+        }
+        if let Some(solutions) = self.solutions.as_ref() {
+            match Solutions::parse(solutions) {
+                Some(solutions) => settings.solutions = solutions,
+                None => {
+                    let message = format!(
+                        "unknown solutions mode `{}`; expected one of {}",
+                        solutions,
+                        Solutions::NAMES.join(", "),
+                    );
+                    diagnostics.push(Diagnostic::error(None, message));
+                }
+            }
+        }
+        if let Some(typography) = self.passes.typography {
+            settings.typography = typography;
+        }
+        if let Some(tables) = self.passes.tables {
+            settings.passes.tables = tables;
+        }
+        if let Some(inline_format) = self.passes.inline_format {
+            settings.passes.inline_format = inline_format;
+        }
+        if let Some(paragraphs) = self.passes.paragraphs {
+            settings.passes.paragraphs = paragraphs;
+        }
+        settings.math_macros.extend(self.math.macros.iter().map(|(name, definition)| {
+            match definition {
+                MacroDefinition::Simple(body) => MathMacro {
+                    name: name.clone(),
+                    arity: 0,
+                    body: body.clone(),
+                },
+                MacroDefinition::WithArguments{body, args} => MathMacro {
+                    name: name.clone(),
+                    arity: *args,
+                    body: body.clone(),
+                },
+            }
+        }));
+        if let Some(template) = settings.template.as_ref() {
+            if !template.contains("<!--{{body}}-->") {
+                diagnostics.push(Diagnostic::warning(None, "the template has no `<!--{{body}}-->` placeholder"));
+            }
+        }
+        diagnostics
+    }
+}
+
+/// The range of the given key path (e.g. `passes.foo`, as reported by
+/// `serde_ignored`) within the given TOML, if it’s written out as such (i.e.
+/// `foo = ...` within the `[passes]` table).
+fn key_range(source: &str, path: &str) -> Option<CharRange> {
+    let (table, key) = path.rsplit_once('.').unwrap_or(("", path));
+    let mut current_table = "";
+    let mut start = CharIndex::zero();
+    for line in source.split_inclusive('\n') {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            current_table = trimmed.trim_matches(|x| x == '[' || x == ']').trim();
+        } else if current_table == table {
+            let name = line.split('=').next().unwrap_or("");
+            if line.contains('=') && name.trim().trim_matches('"') == key {
+                let indent = &line[..line.len() - line.trim_start().len()];
+                let start = CharIndex {
+                    byte_index: start.byte_index + indent.len(),
+                    char_index: start.char_index + indent.chars().count(),
+                };
+                let name = name.trim();
+                let end = CharIndex {
+                    byte_index: start.byte_index + name.len(),
+                    char_index: start.char_index + name.chars().count(),
+                };
+                return Some(CharRange {start, end})
+            }
+        }
+        start.byte_index += line.len();
+        start.char_index += line.chars().count();
+    }
+    None
+}
+
+/// The project file for the given source file, i.e. the nearest
+/// `subscript.toml` of its directory or any of its parents.
+pub fn discover(source_path: &Path) -> Option<PathBuf> {
+    let source_path = source_path.canonicalize().ok()?;
+    source_path
+        .ancestors()
+        .skip(1)
+        .map(|x| x.join(CONFIG_FILE_NAME))
+        .find(|x| x.is_file())
+}
+
+/// Splits off the front matter (if any). The front matter lines are blanked
+/// out rather than removed, so that the diagnostics of the document keep
+/// their line numbers; likewise the front matter starts with the newline of
+/// the opening delimiter, so that its lines are those of the document.
+pub fn split_front_matter(source: &str) -> (Option<&str>, Cow<'_, str>) {
+    let is_delimiter = |line: &str| line.trim_end() == FRONT_MATTER_DELIMITER;
+    let mut lines = source.split_inclusive('\n');
+    let start = match lines.next() {
+        Some(line) if is_delimiter(line) => line.len(),
+        _ => return (None, Cow::Borrowed(source)),
+    };
+    let mut end = start;
+    for line in lines {
+        if is_delimiter(line) {
+            let rest = end + line.len();
+            let blank = "\n".repeat(source[..rest].matches('\n').count());
+            return (Some(&source[start - 1..end]), Cow::Owned(blank + &source[rest..]))
+        }
+        end += line.len();
+    }
+    (None, Cow::Borrowed(source))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::scratch_dir;
+
+    fn messages(diagnostics: &[Diagnostic]) -> Vec<&str> {
+        diagnostics.iter().map(|x| x.message.as_str()).collect()
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let source = "language = \"de\"\ncolour = 1\n[passes]\nfoo = true";
+        let (config, diagnostics) = Config::parse(source);
+        assert_eq!(config.language.as_deref(), Some("de"));
+        assert_eq!(messages(&diagnostics), vec![
+            "unknown configuration key `colour`",
+            "unknown configuration key `passes.foo`",
+        ]);
+        let lines = diagnostics
+            .iter()
+            .map(|x| x.line_column(source))
+            .collect::<Vec<_>>();
+        assert_eq!(lines, vec![Some((2, 1)), Some((4, 1))]);
+        assert_eq!(
+            diagnostics[1].render("subscript.toml", source),
+            "[WARNING!] subscript.toml:4:1: unknown configuration key `passes.foo`",
+        );
+    }
+
+    #[test]
+    fn unknown_keys_of_the_front_matter() {
+        let source = "+++\nlanguage = \"de\"\n  colour = 1\n+++\n\\h1{A}\n";
+        let (front_matter, _) = split_front_matter(source);
+        let front_matter = front_matter.unwrap();
+        let (_, diagnostics) = Config::from_front_matter(front_matter, Path::new(""));
+        assert_eq!(
+            diagnostics[0].render("a.txt", front_matter),
+            "[WARNING!] a.txt:3:3: unknown configuration key `colour`",
+        );
+    }
+
+    #[test]
+    fn invalid_toml() {
+        let (config, diagnostics) = Config::parse("language = ");
+        assert!(config.language.is_none());
+        assert!(diagnostics[0].is_error());
+        assert!(diagnostics[0].message.starts_with("invalid configuration: "));
+    }
+
+    #[test]
+    fn front_matter_ignores_project_settings() {
+        let source = "output-dir = \"build\"\norder = []\nstylesheets = [\"a.css\"]";
+        let (config, diagnostics) = Config::from_front_matter(source, Path::new("docs"));
+        assert!(config.output_dir.is_none());
+        assert!(config.order.is_none());
+        assert_eq!(config.stylesheets, vec![PathBuf::from("docs/a.css")]);
+        assert_eq!(messages(&diagnostics), vec![
+            "`output-dir` is only supported in subscript.toml; ignored",
+            "`order` is only supported in subscript.toml; ignored",
+        ]);
+    }
+
+    #[test]
+    fn merge() {
+        let (project, _) = Config::parse(concat!(
+            "language = \"en\"\ntheme = \"dark\"\nstylesheets = [\"a.css\"]\n",
+            "allowed-tags = [\"kbd-key\"]\n",
+            "[passes]\ntables = false\n",
+            "[math.macros]\nRR = \"\\\\mathbb{R}\"\nNN = \"\\\\mathbb{N}\"\n",
+        ));
+        let (document, _) = Config::parse(concat!(
+            "language = \"de\"\nstylesheets = [\"b.css\"]\nallowed-tags = [\"x-ref\"]\n",
+            "[passes]\ntypography = true\n",
+            "[math.macros]\nNN = \"N\"\n",
+        ));
+        let config = project.merge(document);
+        assert_eq!(config.language.as_deref(), Some("de"));
+        assert_eq!(config.theme.as_deref(), Some("dark"));
+        assert_eq!(config.stylesheets, vec![Path::new("a.css"), Path::new("b.css")]);
+        assert_eq!(config.allowed_tags, vec!["kbd-key", "x-ref"]);
+        assert_eq!(config.passes.tables, Some(false));
+        assert_eq!(config.passes.typography, Some(true));
+        assert_eq!(config.math.macros.len(), 2);
+        assert!(matches!(&config.math.macros["NN"], MacroDefinition::Simple(x) if x == "N"));
+    }
+
+    #[test]
+    fn apply() {
+        let (config, _) = Config::parse(concat!(
+            "language = \"de\"\nprint = true\nsolutions = \"appendix\"\ntheme = \"dark\"\n",
+            "allowed-tags = [\"kbd-key\"]\n",
+            "[passes]\ntypography = true\ninline-format = true\nparagraphs = true\n",
+            "[math.macros]\nRR = \"\\\\mathbb{R}\"\n",
+            "norm = { body = \"\\\\left| #1 \\\\right|\", args = 1 }\n",
+        ));
+        let mut settings = Settings::default();
+        assert!(config.apply(&mut settings).is_empty());
+        assert_eq!(settings.language.as_deref(), Some("de"));
+        assert!(settings.print);
+        assert_eq!(settings.solutions, Solutions::Appendix);
+        assert_eq!(settings.theme.as_deref(), Some("dark"));
+        assert_eq!(settings.allowed_tags, vec!["kbd-key"]);
+        assert!(settings.typography);
+        assert!(settings.passes.inline_format);
+        assert!(settings.passes.paragraphs);
+        assert!(settings.passes.tables);
+        let macros = settings.math_macros
+            .iter()
+            .map(|x| (x.name.as_str(), x.arity))
+            .collect::<Vec<_>>();
+        assert_eq!(macros, vec![("RR", 0), ("norm", 1)]);
+    }
+
+    #[test]
+    fn apply_reports_invalid_values() {
+        let (config, _) = Config::parse(concat!(
+            "theme = \"neon\"\nsolutions = \"shown\"\n",
+            "stylesheets = [\"/nonexistent/subscript.css\"]\n",
+        ));
+        let mut settings = Settings::default();
+        let diagnostics = config.apply(&mut settings);
+        assert!(diagnostics.iter().all(Diagnostic::is_error));
+        assert!(diagnostics[0].message.starts_with("failed to read /nonexistent/subscript.css: "));
+        assert_eq!(messages(&diagnostics[1..]), vec![
+            "unknown theme `neon`; expected one of light, dark, sepia, auto",
+            "unknown solutions mode `shown`; expected one of hidden, strip, appendix",
+        ]);
+        assert_eq!(settings.theme, None);
+        assert_eq!(settings.solutions, Solutions::Hidden);
+    }
+
+    #[test]
+    fn templates_need_a_body_placeholder() {
+        let dir = scratch_dir("config", "template");
+        std::fs::write(dir.join("template.html"), "<html></html>").unwrap();
+        std::fs::write(dir.join(CONFIG_FILE_NAME), "template = \"template.html\"").unwrap();
+        let (config, diagnostics) = Config::load(&dir.join(CONFIG_FILE_NAME));
+        assert!(diagnostics.is_empty());
+        let mut settings = Settings::default();
+        let diagnostics = config.apply(&mut settings);
+        assert_eq!(settings.template.as_deref(), Some("<html></html>"));
+        assert_eq!(messages(&diagnostics), vec![
+            "the template has no `<!--{{body}}-->` placeholder",
+        ]);
+    }
+
+    #[test]
+    fn discovery() {
+        let dir = scratch_dir("config", "discovery");
+        std::fs::create_dir_all(dir.join("notes/part")).unwrap();
+        std::fs::write(dir.join("notes/part/a.txt"), "").unwrap();
+        assert_eq!(discover(&dir.join("notes/part/a.txt")), None);
+        std::fs::write(dir.join(CONFIG_FILE_NAME), "").unwrap();
+        assert_eq!(
+            discover(&dir.join("notes/part/a.txt")),
+            Some(dir.canonicalize().unwrap().join(CONFIG_FILE_NAME)),
+        );
+    }
+
+    #[test]
+    fn front_matter() {
+        let (front_matter, rest) = split_front_matter("+++\nlanguage = \"de\"\n+++\n\\h1{A}\n");
+        assert_eq!(front_matter, Some("\nlanguage = \"de\"\n"));
+        // Blanked out, so that the line numbers stay the same.
+        assert_eq!(rest, "\n\n\n\\h1{A}\n");
+        let source = "\\h1{A}\n+++\n";
+        assert_eq!(split_front_matter(source), (None, Cow::Borrowed(source)));
+        assert_eq!(split_front_matter("+++\nunterminated\n").0, None);
+    }
+}
@@ -0,0 +1,603 @@
+//! EPUB 3 packaging.
+//!
+//! Each source document becomes an XHTML chapter; the navigation document is
+//! derived from the heading TOC of every chapter. The stylesheet (compiled
+//! from `styles/`) and local images are embedded, and math is rendered as
+//! MathML, so the archive doesn’t depend on any network resources (besides
+//! remote images).
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+use std::cell::RefCell;
+use crate::frontend::ast::*;
+use crate::frontend::diagnostic::Diagnostic;
+use crate::frontend::pass::glossary::Entry;
+use crate::compiler::{Compiler, FnPass};
+use crate::frontend::pass::images::{is_remote_path, content_hash, asset_file_name, ASSETS_DIR_NAME};
+use crate::codegen::html::{escape_html, HTML_ATTRIBUTES};
+use crate::codegen::settings::Settings;
+
+static STYLESHEET_PATH: &str = "styling.css";
+
+/// An XHTML content document.
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    pub file_name: String,
+    pub title: String,
+    /// Heading level, label & fragment identifier of each heading.
+    pub headings: Vec<(usize, String, String)>,
+    pub xhtml: String,
+    /// Manifest properties (e.g. `mathml`).
+    pub properties: Vec<&'static str>,
+}
+
+/// Some embedded file (e.g. an image).
+#[derive(Debug, Clone)]
+pub struct Resource {
+    /// Relative to the package document.
+    pub path: String,
+    pub media_type: &'static str,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Book {
+    /// Defaults to the title of the first chapter.
+    pub title: Option<String>,
+    /// Defaults to `en`.
+    pub language: Option<String>,
+    pub chapters: Vec<Chapter>,
+    pub resources: Vec<Resource>,
+    /// The `\term` & `\index` occurrences of all chapters.
+    pub glossary: Vec<Entry>,
+}
+
+fn media_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|x| x.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "svg" => "image/svg+xml",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Points the non-standard attribute selectors (e.g. `[macro=note]`) at the
+/// `data-*` attributes of the XHTML output.
+/// Substitutes the (default theme) custom properties, since reading systems
+/// may not support `var(...)`.
+fn resolve_custom_properties(css: &str) -> String {
+    let properties = css
+        .lines()
+        .map(str::trim)
+        .filter(|x| x.starts_with("--"))
+        .filter_map(|x| x.trim_end_matches(';').split_once(':'))
+        .map(|(name, value)| (format!("var({})", name.trim()), value.trim()))
+        .collect::<Vec<_>>();
+    properties
+        .iter()
+        .fold(css.to_owned(), |css, (name, value)| css.replace(name, value))
+}
+
+fn xhtml_stylesheet() -> String {
+    let css = resolve_custom_properties(include_str!("../../assets/styling.css"));
+    let css = css.as_str();
+    let mut result = String::with_capacity(css.len());
+    let mut rest = css;
+    while let Some(ix) = rest.find('[') {
+        result.push_str(&rest[..=ix]);
+        rest = &rest[ix + 1..];
+        let name = rest
+            .split(|x: char| !x.is_ascii_alphanumeric() && x != '-')
+            .next()
+            .unwrap_or("");
+        if !name.is_empty() && !HTML_ATTRIBUTES.contains(&name) {
+            result.push_str("data-");
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// The `YYYY-MM-DDThh:mm:ssZ` timestamp of the given Unix time.
+fn utc_timestamp(seconds: u64) -> String {
+    let days = (seconds / 86400) as i64;
+    let time = seconds % 86400;
+    // Civil from days (proleptic Gregorian calendar).
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = yoe + era * 400 + if month <= 2 {1} else {0};
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+    )
+}
+
+/// The modification time; `SOURCE_DATE_EPOCH` is honored for reproducible
+/// builds.
+fn modified_timestamp() -> String {
+    let seconds = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|x| x.trim().parse::<u64>().ok())
+        .unwrap_or_else(|| {
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|x| x.as_secs())
+                .unwrap_or(0)
+        });
+    utc_timestamp(seconds)
+}
+
+/// The text content of the given markup (e.g. a heading with MathML), without
+/// the annotations.
+fn plain_text(markup: &str) -> String {
+    let mut result = String::new();
+    let mut rest = markup;
+    while let Some(start) = rest.find('<') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if rest.starts_with("<annotation") {
+            rest = rest
+                .find("</annotation>")
+                .map(|end| &rest[end..])
+                .unwrap_or("");
+        }
+        rest = rest.find('>').map(|end| &rest[end + 1..]).unwrap_or("");
+    }
+    result.push_str(rest);
+    result
+}
+
+/// The heading ids are derived from the heading text (see
+/// `annotate_heading_nodes`), and so may contain whitespace & markup.
+fn fragment_id(id: &str) -> String {
+    plain_text(id)
+        .split_whitespace()
+        .map(|x| x.chars().filter(|x| x.is_alphanumeric() || "-_.".contains(*x)).collect::<String>())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn normalize_heading_ids<'a>(node: Node<'a>) -> Node<'a> {
+    let f = |_: NodeEnvironment<'a>, node: Node<'a>| -> Node<'a> {
+        match node {
+            Node::Tag(mut tag) if tag.is_heading_node() => {
+                if let Some(id) = tag.get_parameter_value("id") {
+                    tag.remove_parameter("id");
+                    tag.insert_unannotated_parameter(&format!("id={}", fragment_id(&id)));
+                }
+                Node::Tag(tag)
+            }
+            node => node,
+        }
+    };
+    node.transform(NodeEnvironment::default(), Rc::new(f))
+}
+
+/// Nested `<ol>` lists for the given headings.
+fn nav_list(entries: &[(usize, String, String)]) -> String {
+    let mut result = String::from("<ol>");
+    let mut ix = 0;
+    while ix < entries.len() {
+        let (level, label, href) = &entries[ix];
+        let end = entries[ix + 1..]
+            .iter()
+            .position(|x| x.0 <= *level)
+            .map(|x| ix + 1 + x)
+            .unwrap_or(entries.len());
+        result.push_str(&format!("<li><a href=\"{}\">{}</a>", escape_html(href), escape_html(label)));
+        if end > ix + 1 {
+            result.push_str(&nav_list(&entries[ix + 1..end]));
+        }
+        result.push_str("</li>");
+        ix = end;
+    }
+    result.push_str("</ol>");
+    result
+}
+
+fn xhtml_document(title: &str, language: &str, body: &str, extra_namespace: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+        <!DOCTYPE html>\n\
+        <html xmlns=\"http://www.w3.org/1999/xhtml\"{namespace} xml:lang=\"{lang}\" lang=\"{lang}\">\n\
+        <head>\n\
+        <meta charset=\"UTF-8\"/>\n\
+        <title>{title}</title>\n\
+        <link rel=\"stylesheet\" type=\"text/css\" href=\"{css}\"/>\n\
+        </head>\n\
+        <body>\n{body}\n</body>\n\
+        </html>\n",
+        namespace=extra_namespace,
+        lang=escape_html(language),
+        title=escape_html(title),
+        css=STYLESHEET_PATH,
+        body=body,
+    )
+}
+
+impl Book {
+    fn language(&self) -> String {
+        self.language.clone().unwrap_or_else(|| String::from("en"))
+    }
+    /// Embeds the local images, rewriting their `src` to the embedded copy.
+    fn embed_images<'a>(&mut self, node: Node<'a>, source_dir: &Path, is_remote: &mut bool) -> Node<'a> {
+        let mut resources = Vec::new();
+        let f = |env: NodeEnvironment<'a>, node: Node<'a>| -> Node<'a> {
+            let mut tag = match node {
+                Node::Tag(tag) if tag.has_name("img") && env.is_default_env() => tag,
+                node => return node,
+            };
+            let src = match tag.get_parameter_value("src") {
+                Some(src) => src,
+                None => return Node::Tag(tag),
+            };
+            if is_remote_path(&src) {
+                if !src.starts_with("data:") {
+                    *is_remote = true;
+                }
+                return Node::Tag(tag)
+            }
+            let path = source_dir.join(&src);
+            // Unreadable files are already reported by `resolve_images`.
+            if let Ok(bytes) = std::fs::read(&path) {
+                let new_src = format!("{}/{}", ASSETS_DIR_NAME, asset_file_name(&path, &bytes));
+                tag.remove_parameter("src");
+                tag.insert_unannotated_parameter(&format!("src={}", new_src));
+                resources.push(Resource {
+                    path: new_src,
+                    media_type: media_type(&path),
+                    bytes,
+                });
+            }
+            Node::Tag(tag)
+        };
+        let node = node.transform_mut(NodeEnvironment::default(), Rc::new(RefCell::new(f)));
+        for resource in resources {
+            if !self.resources.iter().any(|x| x.path == resource.path) {
+                self.resources.push(resource);
+            }
+        }
+        node
+    }
+    /// Compiles the given source into the next chapter. Returns the
+    /// diagnostics of the given source.
+    pub fn add_chapter(&mut self, source: &str, settings: &Settings) -> Vec<Diagnostic> {
+        Compiler::epub()
+            .settings(settings.clone())
+            .compile_chapter(self, source)
+    }
+    /// Adds the output of the passes (see `compiler::Compiler::run`) of the
+    /// given source as the next chapter.
+    pub fn add_chapter_nodes(
+        &mut self,
+        source: &str,
+        body: Vec<Node>,
+        diagnostics: Vec<Diagnostic>,
+        settings: &Settings,
+    ) -> Vec<Diagnostic> {
+        let mut is_remote = false;
+        let body = body
+            .into_iter()
+            .map(|x| match settings.source_dir.as_ref() {
+                Some(source_dir) => self.embed_images(x, source_dir, &mut is_remote),
+                None => x,
+            })
+            .collect::<Vec<_>>();
+        let file_name = format!("chapter-{}.xhtml", self.chapters.len() + 1);
+        self.glossary.extend(crate::frontend::pass::glossary::entries(source, settings, Some(&file_name)));
+        let toc = crate::frontend
+            ::pass
+            ::html_normalize
+            ::generate_table_of_contents_tree(&body);
+        let headings = toc
+            .into_fragment()
+            .into_iter()
+            .filter_map(Node::into_tag)
+            .flat_map(|x| x.children)
+            .filter_map(Node::into_tag)
+            .filter_map(|li| {
+                let level = li
+                    .get_parameter_value("type")?
+                    .trim_start_matches('h')
+                    .parse::<usize>()
+                    .ok()?;
+                let a = li.children.first()?.unwrap_tag()?;
+                let href = a.get_parameter_value("href")?;
+                let label = a.children.iter().map(Node::to_string).collect::<String>();
+                let label = plain_text(&label).split_whitespace().collect::<Vec<_>>().join(" ");
+                let href = format!("{}#{}", file_name, fragment_id(href.trim_start_matches('#')));
+                Some((level, label, href))
+            })
+            .collect::<Vec<_>>();
+        let title = headings
+            .first()
+            .map(|x| x.1.clone())
+            .unwrap_or_else(|| format!("Chapter {}", self.chapters.len() + 1));
+        let body = body
+            .into_iter()
+            .map(crate::frontend::pass::html_normalize::annotate_heading_nodes)
+            .map(normalize_heading_ids)
+            .map(crate::frontend::pass::to_html::node_to_html)
+            .map(|x| x.to_xhtml_str().0)
+            .collect::<Vec<_>>()
+            .join("\n");
+        let mut properties = Vec::new();
+        if body.contains("<math") {
+            properties.push("mathml");
+        }
+        if body.contains("<svg") {
+            properties.push("svg");
+        }
+        if is_remote {
+            properties.push("remote-resources");
+        }
+        let xhtml = xhtml_document(
+            &title,
+            &self.language(),
+            &format!("<main>\n{}\n</main>", body),
+            "",
+        );
+        self.chapters.push(Chapter {file_name, title, headings, xhtml, properties});
+        diagnostics
+    }
+    /// Adds the glossary & index of all chapters (if any) as the last
+    /// chapter.
+    pub fn add_glossary(&mut self, settings: &Settings) -> Vec<Diagnostic> {
+        if self.glossary.is_empty() {
+            return Vec::new()
+        }
+        let settings = Settings {
+            glossary: Some(std::mem::take(&mut self.glossary)),
+            ..settings.clone()
+        };
+        let glossary = FnPass::new("glossary", |nodes, context| {
+            let entries = context.settings.glossary.as_deref().unwrap_or_default();
+            crate::frontend::pass::glossary::glossary_pass(nodes, entries)
+        });
+        Compiler::epub()
+            .settings(settings)
+            // I.E. BEFORE THE OTHER PASSES (THE SOURCE ITSELF IS EMPTY)
+            .pass(glossary.before("tags"))
+            .compile_chapter(self, "")
+    }
+    fn title(&self) -> String {
+        self.title
+            .clone()
+            .or_else(|| self.chapters.first().map(|x| x.title.clone()))
+            .unwrap_or_else(|| String::from("Untitled"))
+    }
+    fn navigation_document(&self) -> String {
+        let entries = self.chapters
+            .iter()
+            .flat_map(|chapter| {
+                if chapter.headings.is_empty() {
+                    vec![(1, chapter.title.clone(), chapter.file_name.clone())]
+                } else {
+                    chapter.headings.clone()
+                }
+            })
+            .collect::<Vec<_>>();
+        let body = format!(
+            "<nav epub:type=\"toc\" id=\"toc\">\n<h1>Table of Contents</h1>\n{}\n</nav>",
+            nav_list(&entries),
+        );
+        xhtml_document(
+            &self.title(),
+            &self.language(),
+            &body,
+            " xmlns:epub=\"http://www.idpf.org/2007/ops\"",
+        )
+    }
+    fn package_document(&self) -> String {
+        let identifier = self.chapters
+            .iter()
+            .map(|x| content_hash(x.xhtml.as_bytes()))
+            .fold(content_hash(self.title().as_bytes()), |x, y| x ^ y.rotate_left(7));
+        let mut manifest = vec![
+            String::from("<item id=\"nav\" href=\"nav.xhtml\" media-type=\"application/xhtml+xml\" properties=\"nav\"/>"),
+            format!("<item id=\"css\" href=\"{}\" media-type=\"text/css\"/>", STYLESHEET_PATH),
+        ];
+        let mut spine = Vec::new();
+        for (ix, chapter) in self.chapters.iter().enumerate() {
+            let properties = if chapter.properties.is_empty() {
+                String::new()
+            } else {
+                format!(" properties=\"{}\"", chapter.properties.join(" "))
+            };
+            manifest.push(format!(
+                "<item id=\"chapter-{}\" href=\"{}\" media-type=\"application/xhtml+xml\"{}/>",
+                ix + 1,
+                chapter.file_name,
+                properties,
+            ));
+            spine.push(format!("<itemref idref=\"chapter-{}\"/>", ix + 1));
+        }
+        for (ix, resource) in self.resources.iter().enumerate() {
+            manifest.push(format!(
+                "<item id=\"resource-{}\" href=\"{}\" media-type=\"{}\"/>",
+                ix + 1,
+                escape_html(&resource.path),
+                resource.media_type,
+            ));
+        }
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"book-id\" xml:lang=\"{lang}\">\n\
+            <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n\
+            <dc:identifier id=\"book-id\">urn:subscript:{identifier:016x}</dc:identifier>\n\
+            <dc:title>{title}</dc:title>\n\
+            <dc:language>{lang}</dc:language>\n\
+            <meta property=\"dcterms:modified\">{modified}</meta>\n\
+            </metadata>\n\
+            <manifest>\n{manifest}\n</manifest>\n\
+            <spine>\n{spine}\n</spine>\n\
+            </package>\n",
+            lang=escape_html(&self.language()),
+            identifier=identifier,
+            title=escape_html(&self.title()),
+            modified=modified_timestamp(),
+            manifest=manifest.join("\n"),
+            spine=spine.join("\n"),
+        )
+    }
+    /// Writes the EPUB archive.
+    pub fn render_to_bytes(&self) -> zip::result::ZipResult<Vec<u8>> {
+        use zip::write::FileOptions;
+        use zip::CompressionMethod;
+        let stored = FileOptions::default().compression_method(CompressionMethod::Stored);
+        let deflated = FileOptions::default().compression_method(CompressionMethod::Deflated);
+        let mut archive = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        // Must be the first (uncompressed) entry.
+        archive.start_file("mimetype", stored)?;
+        archive.write_all(b"application/epub+zip")?;
+        archive.start_file("META-INF/container.xml", deflated)?;
+        archive.write_all(include_str!("../../assets/epub/container.xml").as_bytes())?;
+        archive.start_file("OEBPS/content.opf", deflated)?;
+        archive.write_all(self.package_document().as_bytes())?;
+        archive.start_file("OEBPS/nav.xhtml", deflated)?;
+        archive.write_all(self.navigation_document().as_bytes())?;
+        archive.start_file(format!("OEBPS/{}", STYLESHEET_PATH), deflated)?;
+        archive.write_all(xhtml_stylesheet().as_bytes())?;
+        for chapter in self.chapters.iter() {
+            archive.start_file(format!("OEBPS/{}", chapter.file_name), deflated)?;
+            archive.write_all(chapter.xhtml.as_bytes())?;
+        }
+        for resource in self.resources.iter() {
+            archive.start_file(format!("OEBPS/{}", resource.path), stored)?;
+            archive.write_all(&resource.bytes)?;
+        }
+        Ok(archive.finish()?.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(utc_timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(utc_timestamp(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(utc_timestamp(1_700_000_000), "2023-11-14T22:13:20Z");
+    }
+
+    #[test]
+    fn fragment_ids() {
+        let markup = "a <b>c</b> <annotation x=\"y\">\\d</annotation>e";
+        assert_eq!(plain_text(markup), "a c e");
+        assert_eq!(fragment_id("Some <i>nice</i>  heading?"), "Some-nice-heading");
+    }
+
+    #[test]
+    fn nested_navigation() {
+        let entries = [
+            (1, String::from("A"), String::from("a.xhtml")),
+            (2, String::from("B & C"), String::from("a.xhtml#b")),
+            (1, String::from("D"), String::from("d.xhtml")),
+        ];
+        assert_eq!(
+            nav_list(&entries),
+            concat!(
+                "<ol><li><a href=\"a.xhtml\">A</a>",
+                "<ol><li><a href=\"a.xhtml#b\">B &amp; C</a></li></ol></li>",
+                "<li><a href=\"d.xhtml\">D</a></li></ol>",
+            ),
+        );
+    }
+
+    #[test]
+    fn stylesheet_attribute_selectors() {
+        let css = xhtml_stylesheet();
+        assert!(css.contains("[data-macro=note]"));
+        assert!(!css.contains("[macro="));
+        assert!(!css.contains("var(--text-color)"));
+    }
+
+    #[test]
+    fn chapters() {
+        let mut book = Book::default();
+        let source = "\\h1{First \\{x}} \\h2{Sub} \\p{a}";
+        assert!(book.add_chapter(source, &Settings::default()).is_empty());
+        book.add_chapter("\\p{No headings}", &Settings::default());
+        let first = &book.chapters[0];
+        assert_eq!(first.file_name, "chapter-1.xhtml");
+        assert_eq!(first.headings, vec![
+            (1, String::from("First x"), String::from("chapter-1.xhtml#First-x")),
+            (2, String::from("Sub"), String::from("chapter-1.xhtml#Sub")),
+        ]);
+        assert_eq!(first.title, "First x");
+        assert_eq!(first.properties, vec!["mathml"]);
+        assert!(first.xhtml.contains("<math"));
+        assert_eq!(book.chapters[1].title, "Chapter 2");
+        assert_eq!(book.title(), first.title);
+        let nav = book.navigation_document();
+        assert!(nav.contains(concat!(
+            "<ol><li><a href=\"chapter-1.xhtml#First-x\">First x</a>",
+            "<ol><li><a href=\"chapter-1.xhtml#Sub\">Sub</a></li></ol></li>",
+            "<li><a href=\"chapter-2.xhtml\">Chapter 2</a></li></ol>",
+        )));
+    }
+
+    #[test]
+    fn glossary_chapter() {
+        let mut book = Book::default();
+        assert!(book.add_glossary(&Settings::default()).is_empty());
+        assert!(book.chapters.is_empty());
+        book.add_chapter("\\p{A \\term{word}.}", &Settings::default());
+        book.add_glossary(&Settings::default());
+        assert_eq!(book.chapters.len(), 2);
+        let glossary = &book.chapters[1];
+        assert_eq!(glossary.title, "Glossary");
+        assert!(glossary.xhtml.contains("<dt id=\"glossary-word\">word</dt>"));
+        assert!(glossary.xhtml.contains(
+            "<li>word <a href=\"chapter-1.xhtml#term-word-1\">1</a></li>",
+        ));
+    }
+
+    #[test]
+    fn archive() {
+        let mut book = Book {language: Some(String::from("de")), ..Book::default()};
+        book.add_chapter("\\h1{Title}", &Settings::default());
+        let bytes = book.render_to_bytes().unwrap();
+        // The uncompressed `mimetype` must come first.
+        assert_eq!(&bytes[30..38], b"mimetype");
+        assert_eq!(&bytes[38..58], b"application/epub+zip");
+        let mut archive = zip::ZipArchive::new(std::io::Cursor::new(bytes)).unwrap();
+        let names = (0..archive.len())
+            .map(|ix| archive.by_index(ix).unwrap().name().to_owned())
+            .collect::<Vec<_>>();
+        assert_eq!(names, vec![
+            "mimetype",
+            "META-INF/container.xml",
+            "OEBPS/content.opf",
+            "OEBPS/nav.xhtml",
+            "OEBPS/styling.css",
+            "OEBPS/chapter-1.xhtml",
+        ]);
+        let mut package = String::new();
+        archive.by_name("OEBPS/content.opf").unwrap().read_to_string(&mut package).unwrap();
+        assert!(package.contains("<dc:title>Title</dc:title>"));
+        assert!(package.contains("<dc:language>de</dc:language>"));
+        assert!(package.contains("<itemref idref=\"chapter-1\"/>"));
+    }
+}
@@ -0,0 +1,413 @@
+//! Server-side syntax highlighting for code listings.
+//!
+//! This is a small table driven lexer rather than a full grammar per language;
+//! it classifies keywords, types, strings, numbers and comments, which is all
+//! our notes need. Unknown languages are rendered as plain (escaped) text.
+use crate::codegen::html::escape_html;
+
+pub struct Language {
+    pub names: &'static [&'static str],
+    pub keywords: &'static [&'static str],
+    pub types: &'static [&'static str],
+    pub line_comments: &'static [&'static str],
+    pub block_comment: Option<(&'static str, &'static str)>,
+    pub string_delimiters: &'static [char],
+    /// Identifiers starting with an uppercase letter are highlighted as types.
+    pub capitalized_types: bool,
+}
+
+pub static LANGUAGES: &[Language] = &[
+    Language {
+        names: &["rust", "rs"],
+        keywords: &[
+            "as", "async", "await", "break", "const", "continue", "crate", "dyn",
+            "else", "enum", "extern", "false", "fn", "for", "if", "impl", "in",
+            "let", "loop", "match", "mod", "move", "mut", "pub", "ref", "return",
+            "self", "Self", "static", "struct", "super", "trait", "true", "type",
+            "unsafe", "use", "where", "while",
+        ],
+        types: &[
+            "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize",
+            "i8", "i16", "i32", "i64", "i128", "isize", "f32", "f64",
+        ],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        string_delimiters: &['"'],
+        capitalized_types: true,
+    },
+    Language {
+        names: &["c", "h", "cpp", "c++", "cc", "hpp"],
+        keywords: &[
+            "auto", "break", "case", "class", "const", "constexpr", "continue",
+            "default", "delete", "do", "else", "enum", "extern", "false", "for",
+            "goto", "if", "inline", "namespace", "new", "nullptr", "private",
+            "protected", "public", "register", "return", "sizeof", "static",
+            "struct", "switch", "template", "this", "true", "typedef", "typename",
+            "union", "using", "virtual", "volatile", "while", "#include",
+            "#define", "#ifdef", "#ifndef", "#endif", "#if", "#else", "#pragma",
+        ],
+        types: &[
+            "bool", "char", "double", "float", "int", "long", "short", "signed",
+            "unsigned", "void", "size_t", "uint8_t", "uint16_t", "uint32_t",
+            "uint64_t", "int8_t", "int16_t", "int32_t", "int64_t",
+        ],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        string_delimiters: &['"', '\''],
+        capitalized_types: false,
+    },
+    Language {
+        names: &["python", "py"],
+        keywords: &[
+            "and", "as", "assert", "async", "await", "break", "class", "continue",
+            "def", "del", "elif", "else", "except", "False", "finally", "for",
+            "from", "global", "if", "import", "in", "is", "lambda", "None",
+            "nonlocal", "not", "or", "pass", "raise", "return", "True", "try",
+            "while", "with", "yield",
+        ],
+        types: &["int", "float", "str", "bool", "list", "dict", "set", "tuple", "bytes"],
+        line_comments: &["#"],
+        block_comment: None,
+        string_delimiters: &['"', '\''],
+        capitalized_types: true,
+    },
+    Language {
+        names: &["javascript", "js", "typescript", "ts"],
+        keywords: &[
+            "async", "await", "break", "case", "catch", "class", "const",
+            "continue", "default", "delete", "do", "else", "export", "extends",
+            "false", "finally", "for", "from", "function", "if", "import", "in",
+            "instanceof", "interface", "let", "new", "null", "of", "return",
+            "switch", "this", "throw", "true", "try", "type", "typeof",
+            "undefined", "var", "void", "while", "yield",
+        ],
+        types: &["any", "boolean", "number", "string", "never", "unknown"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        string_delimiters: &['"', '\'', '`'],
+        capitalized_types: true,
+    },
+    Language {
+        names: &["java", "kotlin", "kt", "swift", "csharp", "cs"],
+        keywords: &[
+            "abstract", "break", "case", "catch", "class", "continue", "default",
+            "do", "else", "enum", "extends", "false", "final", "finally", "for",
+            "fun", "func", "guard", "if", "implements", "import", "interface",
+            "let", "new", "null", "nil", "override", "package", "private",
+            "protected", "public", "return", "static", "struct", "super",
+            "switch", "this", "throw", "throws", "true", "try", "val", "var",
+            "void", "while",
+        ],
+        types: &["boolean", "byte", "char", "double", "float", "int", "long", "short"],
+        line_comments: &["//"],
+        block_comment: Some(("/*", "*/")),
+        string_delimiters: &['"', '\''],
+        capitalized_types: true,
+    },
+    Language {
+        names: &["haskell", "hs"],
+        keywords: &[
+            "case", "class", "data", "deriving", "do", "else", "if", "import",
+            "in", "instance", "let", "module", "newtype", "of", "then", "type",
+            "where",
+        ],
+        types: &[],
+        line_comments: &["--"],
+        block_comment: Some(("{-", "-}")),
+        string_delimiters: &['"'],
+        capitalized_types: true,
+    },
+    Language {
+        names: &["bash", "sh", "shell", "zsh"],
+        keywords: &[
+            "case", "do", "done", "elif", "else", "esac", "export", "fi", "for",
+            "function", "if", "in", "local", "return", "set", "then", "while",
+        ],
+        types: &[],
+        line_comments: &["#"],
+        block_comment: None,
+        string_delimiters: &['"', '\''],
+        capitalized_types: false,
+    },
+    Language {
+        names: &["sql"],
+        keywords: &[
+            "select", "from", "where", "insert", "into", "values", "update",
+            "set", "delete", "create", "table", "drop", "alter", "join", "inner",
+            "left", "right", "outer", "on", "group", "by", "order", "having",
+            "limit", "and", "or", "not", "null", "as", "distinct", "primary",
+            "key", "foreign", "references",
+            "SELECT", "FROM", "WHERE", "INSERT", "INTO", "VALUES", "UPDATE",
+            "SET", "DELETE", "CREATE", "TABLE", "DROP", "ALTER", "JOIN", "INNER",
+            "LEFT", "RIGHT", "OUTER", "ON", "GROUP", "BY", "ORDER", "HAVING",
+            "LIMIT", "AND", "OR", "NOT", "NULL", "AS", "DISTINCT", "PRIMARY",
+            "KEY", "FOREIGN", "REFERENCES",
+        ],
+        types: &["int", "integer", "text", "varchar", "boolean", "INT", "INTEGER", "TEXT", "VARCHAR", "BOOLEAN"],
+        line_comments: &["--"],
+        block_comment: Some(("/*", "*/")),
+        string_delimiters: &['\''],
+        capitalized_types: false,
+    },
+];
+
+pub fn find_language(name: &str) -> Option<&'static Language> {
+    let name = name.trim().to_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|x| x.names.contains(&name.as_str()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TokenKind {
+    Plain,
+    Keyword,
+    Type,
+    String,
+    Number,
+    Comment,
+}
+
+impl TokenKind {
+    /// The CSS class of the `<span>` wrapping this token.
+    pub fn class_name(&self) -> Option<&'static str> {
+        match self {
+            TokenKind::Plain => None,
+            TokenKind::Keyword => Some("hl-keyword"),
+            TokenKind::Type => Some("hl-type"),
+            TokenKind::String => Some("hl-string"),
+            TokenKind::Number => Some("hl-number"),
+            TokenKind::Comment => Some("hl-comment"),
+        }
+    }
+}
+
+fn is_ident_char(x: char) -> bool {
+    x.is_alphanumeric() || x == '_' || x == '#'
+}
+
+/// Splits the source into classified tokens; concatenating the token text
+/// yields the original source.
+pub fn tokenize<'a>(language: &Language, source: &'a str) -> Vec<(TokenKind, &'a str)> {
+    let mut tokens: Vec<(TokenKind, &'a str)> = Vec::new();
+    let mut rest = source;
+    while let Some(current) = rest.chars().next() {
+        let end = if language.line_comments.iter().any(|x| rest.starts_with(x)) {
+            let end = rest.find('\n').unwrap_or(rest.len());
+            tokens.push((TokenKind::Comment, &rest[..end]));
+            end
+        } else if let Some((open, close)) = language.block_comment.filter(|(x, _)| rest.starts_with(x)) {
+            let end = rest[open.len()..]
+                .find(close)
+                .map(|x| x + open.len() + close.len())
+                .unwrap_or(rest.len());
+            tokens.push((TokenKind::Comment, &rest[..end]));
+            end
+        } else if language.string_delimiters.contains(&current) {
+            let body = &rest[current.len_utf8()..];
+            let mut end = rest.len();
+            let mut escaped = false;
+            for (ix, x) in body.char_indices() {
+                if escaped {
+                    escaped = false;
+                } else if x == '\\' {
+                    escaped = true;
+                } else if x == current {
+                    end = current.len_utf8() + ix + x.len_utf8();
+                    break;
+                } else if x == '\n' && current != '`' {
+                    end = current.len_utf8() + ix;
+                    break;
+                }
+            }
+            tokens.push((TokenKind::String, &rest[..end]));
+            end
+        } else if current.is_ascii_digit() {
+            let end = rest
+                .find(|x: char| !(x.is_ascii_alphanumeric() || x == '.' || x == '_'))
+                .unwrap_or(rest.len());
+            tokens.push((TokenKind::Number, &rest[..end]));
+            end
+        } else if is_ident_char(current) {
+            let end = rest
+                .find(|x: char| !is_ident_char(x))
+                .unwrap_or(rest.len());
+            let word = &rest[..end];
+            let kind = if language.keywords.contains(&word) {
+                TokenKind::Keyword
+            } else if language.types.contains(&word) || {
+                language.capitalized_types && word.starts_with(char::is_uppercase)
+            } {
+                TokenKind::Type
+            } else {
+                TokenKind::Plain
+            };
+            tokens.push((kind, word));
+            end
+        } else {
+            tokens.push((TokenKind::Plain, &rest[..current.len_utf8()]));
+            current.len_utf8()
+        };
+        rest = &rest[end..];
+    }
+    tokens
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CodeBlockOptions {
+    pub language: Option<String>,
+    pub line_numbers: bool,
+    /// The number of the first line (defaults to one).
+    pub start_line: usize,
+    /// The (inclusive) ranges of lines to emphasize, as numbered in the
+    /// output (i.e. counting from `start_line`).
+    pub highlighted_lines: Vec<(usize, usize)>,
+}
+
+impl CodeBlockOptions {
+    pub fn is_highlighted(&self, line: usize) -> bool {
+        self.highlighted_lines
+            .iter()
+            .any(|(start, end)| *start <= line && line <= *end)
+    }
+}
+
+/// Parses line specifications such as `2,4-6` into inclusive ranges.
+pub fn parse_line_ranges(spec: &str) -> Vec<(usize, usize)> {
+    spec.split(',')
+        .filter_map(|x| {
+            let x = x.trim();
+            match x.split_once('-') {
+                Some((start, end)) => {
+                    let start = start.trim().parse::<usize>().ok()?;
+                    let end = end.trim().parse::<usize>().ok()?;
+                    Some((start, end))
+                }
+                None => x.parse::<usize>().ok().map(|x| (x, x)),
+            }
+        })
+        .collect()
+}
+
+/// Renders the given code to the inner HTML of a `<pre>` element; each line
+/// is wrapped in a `<span class="line">`.
+pub fn highlight_to_html(code: &str, options: &CodeBlockOptions) -> String {
+    let language = options.language.as_deref().and_then(find_language);
+    let tokens = match language {
+        Some(language) => tokenize(language, code),
+        None => vec![(TokenKind::Plain, code)],
+    };
+    // SPLIT INTO LINES
+    let mut lines: Vec<Vec<(TokenKind, &str)>> = vec![Vec::new()];
+    for (kind, text) in tokens {
+        for (ix, part) in text.split('\n').enumerate() {
+            if ix > 0 {
+                lines.push(Vec::new());
+            }
+            if !part.is_empty() {
+                lines.last_mut().unwrap().push((kind, part));
+            }
+        }
+    }
+    let start_line = options.start_line.max(1);
+    let class = match options.language.as_ref() {
+        Some(language) => format!(" class=\"language-{}\"", escape_html(language)),
+        None => String::new(),
+    };
+    let mut html = format!("<code{}>", class);
+    for (ix, line) in lines.into_iter().enumerate() {
+        let number = start_line + ix;
+        let is_highlighted = options.is_highlighted(number);
+        html.push_str(if is_highlighted {
+            "<span class=\"line highlighted\">"
+        } else {
+            "<span class=\"line\">"
+        });
+        if options.line_numbers {
+            html.push_str(&format!("<span class=\"line-number\">{}</span>", number));
+        }
+        for (kind, text) in line {
+            match kind.class_name() {
+                Some(class) => {
+                    html.push_str(&format!(
+                        "<span class=\"{}\">{}</span>",
+                        class,
+                        escape_html(text),
+                    ));
+                }
+                None => html.push_str(&escape_html(text)),
+            }
+        }
+        html.push_str("</span>\n");
+    }
+    html.push_str("</code>");
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_ranges() {
+        assert_eq!(parse_line_ranges("2, 4-6,x,9"), vec![(2, 2), (4, 6), (9, 9)]);
+        // NOT EXPANDED INTO THE INDIVIDUAL LINES
+        assert_eq!(parse_line_ranges("1-1000000000"), vec![(1, 1000000000)]);
+        let options = CodeBlockOptions {
+            highlighted_lines: parse_line_ranges("2,4-6"),
+            ..CodeBlockOptions::default()
+        };
+        let highlighted = (1..=7).filter(|x| options.is_highlighted(*x)).collect::<Vec<_>>();
+        assert_eq!(highlighted, vec![2, 4, 5, 6]);
+    }
+
+    #[test]
+    fn tokens_cover_the_source() {
+        let rust = find_language("Rust").unwrap();
+        let source = "fn main() {\n    let x: String = \"a\\\"b\"; // done\n    1_000\n}";
+        let tokens = tokenize(rust, source);
+        assert_eq!(tokens.iter().map(|x| x.1).collect::<String>(), source);
+        let of_kind = |kind| tokens
+            .iter()
+            .filter(|x| x.0 == kind)
+            .map(|x| x.1)
+            .collect::<Vec<_>>();
+        assert_eq!(of_kind(TokenKind::Keyword), vec!["fn", "let"]);
+        assert_eq!(of_kind(TokenKind::Type), vec!["String"]);
+        assert_eq!(of_kind(TokenKind::String), vec!["\"a\\\"b\""]);
+        assert_eq!(of_kind(TokenKind::Comment), vec!["// done"]);
+        assert_eq!(of_kind(TokenKind::Number), vec!["1_000"]);
+    }
+
+    #[test]
+    fn unknown_languages() {
+        assert!(find_language("klingon").is_none());
+        let options = CodeBlockOptions {
+            language: Some(String::from("klingon")),
+            ..CodeBlockOptions::default()
+        };
+        assert_eq!(
+            highlight_to_html("a <b>", &options),
+            "<code class=\"language-klingon\"><span class=\"line\">a &lt;b&gt;</span>\n</code>",
+        );
+    }
+
+    #[test]
+    fn highlighted_lines_count_from_the_start_line() {
+        let options = CodeBlockOptions {
+            line_numbers: true,
+            start_line: 10,
+            highlighted_lines: vec![(11, 11)],
+            ..CodeBlockOptions::default()
+        };
+        assert_eq!(
+            highlight_to_html("a\nb", &options),
+            concat!(
+                "<code>",
+                "<span class=\"line\"><span class=\"line-number\">10</span>a</span>\n",
+                "<span class=\"line highlighted\"><span class=\"line-number\">11</span>b</span>\n",
+                "</code>",
+            ),
+        );
+    }
+}
@@ -1,6 +1,9 @@
 use std::borrow::Cow;
 use std::collections::HashMap;
 use crate::frontend::data::{LayoutKind, Text};
+use crate::frontend::diagnostic::Diagnostic;
+use crate::codegen::settings::Settings;
+use crate::codegen::site::Navigation;
 
 ///////////////////////////////////////////////////////////////////////////////
 // BASICS
@@ -50,6 +53,77 @@ impl Image {
 // HTML TREE
 ///////////////////////////////////////////////////////////////////////////////
 
+/// Elements that can’t have any children, and so are written without a closing
+/// tag (e.g. `<br></br>` is read as two line breaks).
+pub static VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "br", "col", "embed", "hr", "img", "input",
+    "link", "meta", "source", "track", "wbr",
+];
+
+/// Standard attributes; in XHTML any other (e.g. `macro`) is written as a
+/// `data-*` attribute.
+pub static HTML_ATTRIBUTES: &[&str] = &[
+    "id", "class", "style", "title", "lang", "dir", "role", "href", "src",
+    "alt", "width", "height", "loading", "rel", "name", "content", "charset",
+    "colspan", "rowspan", "headers", "scope", "span", "start", "reversed",
+    "open", "cite", "datetime", "value", "srcset", "sizes",
+];
+
+/// HTML named character references that XML doesn’t define.
+static HTML_ENTITIES: &[(&str, &str)] = &[
+    ("nbsp", "&#160;"), ("shy", "&#173;"), ("ndash", "&#8211;"),
+    ("mdash", "&#8212;"), ("hellip", "&#8230;"), ("copy", "&#169;"),
+    ("times", "&#215;"), ("deg", "&#176;"),
+];
+
+/// Makes (possibly hand written) HTML text well-formed XML; stray ampersands
+/// are escaped and HTML named character references become numeric.
+fn xml_text(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(ix) = rest.find('&') {
+        result.push_str(&rest[..ix]);
+        rest = &rest[ix..];
+        let name = rest
+            .find(';')
+            .map(|end| &rest[1..end])
+            .filter(|x| !x.is_empty() && x.len() < 10)
+            .filter(|x| x.chars().all(|x| x.is_ascii_alphanumeric() || x == '#'));
+        match name {
+            Some(name) if name.starts_with('#') || ["amp", "lt", "gt", "quot", "apos"].contains(&name) => {
+                result.push_str(&rest[..name.len() + 2]);
+                rest = &rest[name.len() + 2..];
+            }
+            Some(name) if HTML_ENTITIES.iter().any(|(x, _)| *x == name) => {
+                let (_, reference) = HTML_ENTITIES.iter().find(|(x, _)| *x == name).unwrap();
+                result.push_str(reference);
+                rest = &rest[name.len() + 2..];
+            }
+            _ => {
+                result.push_str("&amp;");
+                rest = &rest[1..];
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Escapes text for use in HTML content and (quoted) attribute values.
+pub fn escape_html(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for x in value.chars() {
+        match x {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            x => result.push(x),
+        }
+    }
+    result
+}
+
 #[derive(Debug, Clone)]
 pub struct Element<'a> {
     pub name: Text<'a>,
@@ -71,7 +145,16 @@ impl<'a> Node<'a> {
         Node::Text(Text::new(val))
     }
     pub fn to_html_str(self) -> Text<'a> {
+        self.render(false)
+    }
+    /// Well-formed XHTML (e.g. for EPUB); void elements are self-closing and
+    /// non-standard attributes are prefixed with `data-`.
+    pub fn to_xhtml_str(self) -> Text<'a> {
+        self.render(true)
+    }
+    fn render(self, xhtml: bool) -> Text<'a> {
         match self {
+            Node::Text(node) if xhtml => Text::from_string(xml_text(&node.0)),
             Node::Text(node) => node,
             Node::Element(node) => {
                 let attributes = node.attributes
@@ -84,9 +167,16 @@ impl<'a> Node<'a> {
                         let value = value.strip_prefix("\"").unwrap_or(value);
                         let value = value.strip_suffix("\'").unwrap_or(value);
                         let value = value.strip_suffix("\"").unwrap_or(value);
+                        if xhtml && !HTML_ATTRIBUTES.contains(&key) {
+                            result.push_str("data-");
+                        }
                         result.push_str(key);
                         result.push_str("=");
-                        result.push_str(&format!("{:?}", value));
+                        if xhtml {
+                            result.push_str(&xml_text(&format!("{:?}", value)));
+                        } else {
+                            result.push_str(&format!("{:?}", value));
+                        }
                         result
                     })
                     .collect::<Vec<_>>()
@@ -102,10 +192,14 @@ impl<'a> Node<'a> {
                 };
                 let children = node.children
                     .into_iter()
-                    .map(Node::to_html_str)
+                    .map(|x| x.render(xhtml))
                     .map(|x| x.0)
                     .collect::<Vec<_>>()
                     .join("");
+                if children.is_empty() && VOID_ELEMENTS.contains(&node.name.0.as_ref()) {
+                    let end = if xhtml {"/"} else {""};
+                    return Text::from_string(format!("<{}{}{}>", node.name, attributes, end))
+                }
                 let children = Text::from_string(children);
                 Text::from_string(format!(
                     "<{name}{attrs}>{children}</{name}>",
@@ -117,14 +211,19 @@ impl<'a> Node<'a> {
             Node::Fragment(nodes) => {
                 let children = nodes
                     .into_iter()
-                    .map(Node::to_html_str)
+                    .map(|x| x.render(xhtml))
                     .map(|x| x.0)
                     .collect::<Vec<_>>()
                     .join("");
                 Text::from_string(children)
             }
-            Node::Image(image) => {
-                unimplemented!()
+            Node::Image(Image::Svg{kind, payload}) => {
+                let name = match kind {
+                    LayoutKind::Block => "div",
+                    LayoutKind::Inline => "span",
+                };
+                let attribute = if xhtml {"data-macro"} else {"macro"};
+                Text::from_string(format!("<{0} {1}=\"svg\">{2}</{0}>", name, attribute, payload))
             }
         }
     }
@@ -134,24 +233,120 @@ impl<'a> Node<'a> {
 /// Render the entire document.
 #[derive(Debug, Clone)]
 pub struct Document<'a> {
+    pub title: String,
     pub toc: Node<'a>,
-    pub body: Vec<Node<'a>>
+    pub body: Vec<Node<'a>>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The template, stylesheets, etc.
+    pub settings: Settings,
+}
+
+/// Escapes the given value for a CSS string literal.
+fn escape_css_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for x in value.chars() {
+        match x {
+            '"' | '\\' => {
+                result.push('\\');
+                result.push(x);
+            }
+            '\n' => result.push_str("\\a "),
+            '<' => result.push_str("\\3c "),
+            x => result.push(x),
+        }
+    }
+    result
+}
+
+/// Escapes the given value for a JavaScript string literal.
+pub(crate) fn escape_js_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    for x in value.chars() {
+        match x {
+            '\'' | '"' | '\\' => {
+                result.push('\\');
+                result.push(x);
+            }
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '<' => result.push_str("\\u003c"),
+            x => result.push(x),
+        }
+    }
+    result
+}
+
+/// The MathJax configuration & fonts, including the given math macros.
+pub(crate) fn dependencies(settings: &Settings) -> String {
+    let macros = settings.math_macros
+        .iter()
+        .map(|x| format!(
+            "            '{}': ['{}', {}],\n",
+            escape_js_string(&x.name),
+            escape_js_string(&x.body),
+            x.arity,
+        ))
+        .collect::<String>();
+    include_str!("../../assets/deps.html").replace("            /*{{macros}}*/\n", &macros)
+}
+
+/// The names of the built-in themes.
+pub static THEMES: &[&str] = &["light", "dark", "sepia", "auto"];
+
+/// The CSS of the given built-in theme, overriding the colors of the default
+/// (`light`) theme; `auto` follows the color scheme of the reader’s system.
+pub fn theme_stylesheet(name: &str) -> Option<String> {
+    let dark = include_str!("../../assets/themes/dark.css");
+    match name {
+        "light" => Some(String::new()),
+        "dark" => Some(String::from(dark)),
+        "sepia" => Some(String::from(include_str!("../../assets/themes/sepia.css"))),
+        "auto" => Some(format!("@media (prefers-color-scheme: dark) {{\n{}}}\n", dark)),
+        _ => None,
+    }
+}
+
+/// The built-in stylesheet & theme, followed by the given stylesheets.
+pub(crate) fn stylesheet(settings: &Settings) -> String {
+    let mut css = String::new();
+    if !settings.replace_stylesheet {
+        css.push_str(include_str!("../../assets/styling.css"));
+        let theme = settings.theme
+            .as_deref()
+            .and_then(theme_stylesheet)
+            .unwrap_or_default();
+        if !theme.is_empty() {
+            css.push('\n');
+            css.push_str(&theme);
+        }
+    }
+    for stylesheet in settings.stylesheets.iter() {
+        if !css.is_empty() {
+            css.push('\n');
+        }
+        css.push_str(stylesheet);
+    }
+    css
 }
 
 impl<'a> Document<'a> {
     pub fn from_source(source: &'a str) -> Document<'a> {
-        let body = crate::frontend::pass::pp_normalize::run_compiler_frontend(source);
-        let body = crate::frontend::pass::html_normalize::html_canonicalization(body);
-        let body = body
-            .into_iter()
-            .map(crate::frontend::pass::math::latex_pass)
-            .collect::<Vec<_>>();
-        let toc = crate::frontend
-            ::pass
-            ::html_normalize
-            ::generate_table_of_contents_tree(
-                &crate::frontend::ast::Node::new_fragment(body.clone())
-            );
+        Document::from_source_with(source, &Settings::default())
+    }
+    pub fn from_source_with(source: &'a str, settings: &Settings) -> Document<'a> {
+        crate::compiler::Compiler::new()
+            .settings(settings.clone())
+            .compile(source)
+    }
+    /// Renders the output of the passes (see `compiler::Compiler::run`).
+    pub fn from_nodes(
+        body: Vec<crate::frontend::ast::Node<'a>>,
+        diagnostics: Vec<Diagnostic>,
+        settings: &Settings,
+    ) -> Document<'a> {
+        let title = crate::frontend::pass::html_normalize::document_title(&body)
+            .unwrap_or_else(|| String::from("Subscript"));
+        let toc = crate::frontend::pass::html_normalize::generate_table_of_contents_tree(&body);
         let toc = crate::frontend::pass::to_html::node_to_html(
             crate::frontend::pass::math::latex_pass(toc)
         );
@@ -160,7 +355,7 @@ impl<'a> Document<'a> {
             .map(crate::frontend::pass::html_normalize::annotate_heading_nodes)
             .map(crate::frontend::pass::to_html::node_to_html)
             .collect::<Vec<_>>();
-        Document{toc, body}
+        Document{title, toc, body, diagnostics, settings: settings.clone()}
     }
     pub fn render_to_string(self) -> String {
         let toc = self.toc.to_html_str().to_string();
@@ -170,9 +365,37 @@ impl<'a> Document<'a> {
             .map(|x| x.0)
             .collect::<Vec<_>>()
             .join("\n");
-        String::from(include_str!("../../assets/template.html"))
-            .replace("<!--{{deps}}-->", include_str!("../../assets/deps.html"))
-            .replace("/*{{css}}*/", include_str!("../../assets/styling.css"))
+        let print = if self.settings.print {
+            // THE RUNNING HEADER
+            format!(
+                "<style>\n{}\n@page {{\n  @top-center {{\n    content: \"{}\";\n  }}\n}}\n</style>",
+                include_str!("../../assets/print.css"),
+                escape_css_string(&self.title),
+            )
+        } else {
+            String::new()
+        };
+        let search = match self.settings.search_root.as_ref() {
+            Some(root) => include_str!("../../assets/search.html")
+                .replace("{{root}}", &escape_js_string(&escape_html(root))),
+            None => String::new(),
+        };
+        let navigation = self.settings.navigation.as_ref();
+        let breadcrumbs = navigation.map(Navigation::render_breadcrumbs).unwrap_or_default();
+        let sidebar = navigation.map(Navigation::render_sidebar).unwrap_or_default();
+        let pagination = navigation.map(Navigation::render_pagination).unwrap_or_default();
+        let template = self.settings.template
+            .as_deref()
+            .unwrap_or(include_str!("../../assets/template.html"));
+        String::from(template)
+            .replace("<!--{{title}}-->", &escape_html(&self.title))
+            .replace("<!--{{deps}}-->", &dependencies(&self.settings))
+            .replace("/*{{css}}*/", &stylesheet(&self.settings))
+            .replace("<!--{{print}}-->", &print)
+            .replace("<!--{{search}}-->", &search)
+            .replace("<!--{{sidebar}}-->", &sidebar)
+            .replace("<!--{{breadcrumbs}}-->", &breadcrumbs)
+            .replace("<!--{{pagination}}-->", &pagination)
             .replace("<!--{{toc}}-->", &toc)
             .replace("<!--{{body}}-->", &body)
     }
@@ -180,3 +403,64 @@ impl<'a> Document<'a> {
 
 
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn css_strings() {
+        assert_eq!(escape_css_string("A \"b\" \\ c\n<d>"), "A \\\"b\\\" \\\\ c\\a \\3c d>");
+    }
+
+    #[test]
+    fn print_profile() {
+        let source = "\\h1{Some \"Title\"} \\p{a} \\pagebreak \\p{b}";
+        let output = Document::from_source(source).render_to_string();
+        assert!(!output.contains("@page"));
+        assert!(output.contains("<div macro=\"pagebreak\"></div>"));
+        let settings = Settings {print: true, ..Settings::default()};
+        let output = Document::from_source_with(source, &settings).render_to_string();
+        assert!(output.contains("@top-center {\n    content: \"Some \\\"Title\\\"\";"));
+        assert!(output.contains("<title>Some &quot;Title&quot;</title>"));
+    }
+
+    #[test]
+    fn themes() {
+        for name in THEMES {
+            assert!(theme_stylesheet(name).is_some(), "{}", name);
+        }
+        assert_eq!(theme_stylesheet("light").as_deref(), Some(""));
+        assert!(theme_stylesheet("dark").unwrap().contains("--background-color"));
+        let auto = theme_stylesheet("auto").unwrap();
+        assert!(auto.starts_with("@media (prefers-color-scheme: dark) {\n"));
+        assert!(auto.contains(&theme_stylesheet("dark").unwrap()));
+        assert_eq!(theme_stylesheet("neon"), None);
+    }
+
+    #[test]
+    fn stylesheets() {
+        let default = include_str!("../../assets/styling.css");
+        assert_eq!(stylesheet(&Settings::default()), default);
+        let settings = Settings {
+            theme: Some(String::from("sepia")),
+            stylesheets: vec![String::from("a {}"), String::from("b {}")],
+            ..Settings::default()
+        };
+        let css = stylesheet(&settings);
+        assert!(css.starts_with(default));
+        assert!(css.contains(&theme_stylesheet("sepia").unwrap()));
+        assert!(css.ends_with("\na {}\nb {}"));
+        let settings = Settings {replace_stylesheet: true, ..settings};
+        assert_eq!(stylesheet(&settings), "a {}\nb {}");
+    }
+
+    #[test]
+    fn custom_properties() {
+        let css = include_str!("../../assets/styling.css");
+        for name in ["--note-heading-color", "--layout-cols", "--background-color"] {
+            assert!(css.contains(name), "{}", name);
+        }
+    }
+}
+
@@ -0,0 +1,575 @@
+//! Markdown (and plain text) rendering.
+//!
+//! Renders the frontend AST directly (rather than the HTML AST), since the
+//! HTML canonicalization passes lower math & code listings into HTML markup.
+//! Notes are rendered as blockquotes, math as `$...$` & `$$...$$`, and compact
+//! tables as GFM tables. The plain text backend (see `codegen::text`) shares
+//! the same block layout, without any markup.
+use std::rc::Rc;
+use std::borrow::Cow;
+use crate::frontend::data::*;
+use crate::frontend::ast::*;
+use crate::frontend::diagnostic::Diagnostic;
+use crate::codegen::settings::Settings;
+use crate::compiler::Compiler;
+
+/// Applies the rewrite rules & unescapes the escape sequences (i.e. the
+/// `html-tags` pass, without lowering the tags into HTML).
+pub(crate) fn tag_pass<'a>(children: Vec<Node<'a>>) -> Vec<Node<'a>> {
+    let f = |env: NodeEnvironment<'a>, node: Node<'a>| -> Node<'a> {
+        let is_verbatim = env.parents
+            .iter()
+            .any(|x| VERBATIM_TAGS.contains(&x.as_ref()));
+        match node {
+            Node::Tag(tag) => {
+                Node::Tag(crate::frontend::pass::html_normalize::apply_rewrite_rules(tag))
+            }
+            Node::String(txt) if env.is_default_env() && !is_verbatim => {
+                match unescape(&txt.data) {
+                    Some(literal) => Node::String(txt.map(|_| Cow::Borrowed(literal))),
+                    None => Node::String(txt),
+                }
+            }
+            node => node,
+        }
+    };
+    Node::new_fragment(children)
+        .transform(NodeEnvironment::default(), Rc::new(f))
+        .into_fragment()
+}
+
+/// Tags rendered as blocks of their own (anything else is inline).
+static BLOCK_TAGS: &[&str] = &[
+    "h1", "h2", "h3", "h4", "h5", "h6",
+    "p", "ul", "ol", "dl", "note", "layout", "section", "div", "blockquote",
+    "figure", "equation", "pre", "table", "hr", "article", "aside", "header",
+    "footer", "main", "nav", "details", "summary", "pagebreak",
+];
+
+fn is_block(node: &Node) -> bool {
+    match node {
+        Node::Tag(tag) if tag.has_name("code") => {
+            let raw = verbatim_body(tag);
+            tag.has_parameter("lang") || tag.has_parameter("language") || raw.trim().contains('\n')
+        }
+        Node::Tag(tag) => BLOCK_TAGS.contains(&tag.name()),
+        _ => false,
+    }
+}
+
+fn verbatim_body(tag: &Tag) -> String {
+    tag.children
+        .iter()
+        .cloned()
+        .flat_map(Node::unblock)
+        .map(|x| x.to_string())
+        .collect()
+}
+
+/// The children of the given tag, without the curly brace blocks.
+fn contents<'a>(tag: &Tag<'a>) -> Vec<Node<'a>> {
+    tag.children
+        .iter()
+        .cloned()
+        .flat_map(Node::unblock)
+        .collect()
+}
+
+fn prefix_lines(text: &str, first: &str, rest: &str) -> String {
+    text.lines()
+        .enumerate()
+        .map(|(ix, line)| {
+            let prefix = if ix == 0 {first} else {rest};
+            if line.is_empty() {prefix.trim_end().to_owned()} else {format!("{}{}", prefix, line)}
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Collapses the source indentation & line breaks of some paragraph.
+fn normalize_paragraph(text: &str) -> String {
+    text.lines()
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Splits the text on blank lines.
+fn split_paragraphs(text: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    for (ix, line) in text.split('\n').enumerate() {
+        if ix > 0 && line.trim().is_empty() {
+            parts.push(String::new());
+            continue;
+        }
+        let current = parts.last_mut().unwrap();
+        if ix > 0 {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    parts
+}
+
+/// The text & alignment of some table cell.
+type Cell = (String, Option<String>);
+
+pub(crate) struct Renderer {
+    /// Plain text rather than Markdown.
+    pub plain: bool,
+}
+
+impl Renderer {
+    fn escape(&self, text: &str) -> String {
+        if self.plain {
+            return text.to_owned()
+        }
+        let mut output = String::with_capacity(text.len());
+        for char in text.chars() {
+            if "\\*_`[]<&".contains(char) {
+                output.push('\\');
+            }
+            output.push(char);
+        }
+        output
+    }
+    /// Escapes the block markers (i.e. headings, blockquotes & list items or
+    /// rules) at the start of the lines of some paragraph.
+    fn escape_line_starts(&self, text: &str) -> String {
+        if self.plain {
+            return text.to_owned()
+        }
+        text.split('\n')
+            .map(|line| match line.chars().next() {
+                Some('#') | Some('>') | Some('-') => format!("\\{}", line),
+                _ => line.to_owned(),
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+    fn inline_children(&self, nodes: &[Node]) -> String {
+        nodes.iter().map(|x| self.inline(x)).collect()
+    }
+    fn wrap(&self, marker: &str, tag: &Tag) -> String {
+        let body = self.inline_children(&contents(tag));
+        if self.plain || body.trim().is_empty() {
+            return body
+        }
+        format!("{}{}{}", marker, body, marker)
+    }
+    fn inline_tag(&self, tag: &Tag) -> String {
+        match tag.name() {
+            x if x == INLINE_MATH_TAG => {
+                let latex = crate::frontend::pass::math::to_latex_source(tag.children.clone());
+                if self.plain {latex} else {format!("${}$", latex.trim())}
+            }
+            "b" | "strong" => self.wrap("**", tag),
+            "i" | "em" => self.wrap("*", tag),
+            "s" | "del" => self.wrap("~~", tag),
+            "code" => {
+                let code = verbatim_body(tag);
+                let code = code.trim();
+                match (self.plain, code.contains('`')) {
+                    (true, _) => code.to_owned(),
+                    (false, true) => format!("`` {} ``", code),
+                    (false, false) => format!("`{}`", code),
+                }
+            }
+            "verb" => self.escape(&verbatim_body(tag)),
+            "a" => {
+                let text = self.inline_children(&contents(tag));
+                // IN-DOCUMENT LINKS (E.G. NOTE LABELS) ARE DROPPED, SINCE
+                // THE IDS AREN’T RENDERED
+                let href = tag.get_parameter_value("href").filter(|x| !x.starts_with('#'));
+                match (self.plain, href) {
+                    (false, Some(href)) => format!("[{}]({})", text, href),
+                    (true, Some(href)) if text.trim() != href => format!("{} ({})", text, href),
+                    _ => text,
+                }
+            }
+            "img" => {
+                let src = tag.get_parameter_value("src").unwrap_or_default();
+                let alt = tag.get_parameter_value("alt").unwrap_or_default();
+                if self.plain {
+                    format!("[image: {}]", src)
+                } else {
+                    format!("![{}]({})", alt, src)
+                }
+            }
+            "br" if self.plain => String::from("\n"),
+            "br" => String::from("\\\n"),
+            _ => self.inline_children(&contents(tag)),
+        }
+    }
+    fn inline(&self, node: &Node) -> String {
+        match node {
+            Node::Tag(tag) => self.inline_tag(tag),
+            Node::Enclosure(Ann{data, ..}) => {
+                let children = self.inline_children(&data.children);
+                match &data.kind {
+                    EnclosureKind::SquareParen => format!("{}{}{}", self.escape("["), children, self.escape("]")),
+                    EnclosureKind::Parens => format!("({})", children),
+                    _ => children,
+                }
+            }
+            Node::String(txt) => self.escape(&txt.data),
+            Node::Ident(_) => String::new(),
+            Node::InvalidToken(txt) => self.escape(&txt.data),
+        }
+    }
+    fn heading(&self, level: usize, title: String) -> String {
+        match (self.plain, level) {
+            (false, level) => format!("{} {}", "#".repeat(level), title),
+            (true, 1) => format!("{}\n{}", title, "=".repeat(title.chars().count())),
+            (true, 2) => format!("{}\n{}", title, "-".repeat(title.chars().count())),
+            (true, _) => title,
+        }
+    }
+    fn list(&self, tag: &Tag) -> String {
+        let mut number = tag
+            .get_parameter_value("start")
+            .and_then(|x| x.parse::<usize>().ok())
+            .unwrap_or(1);
+        let is_ordered = tag.has_name("ol");
+        let mut items = Vec::new();
+        for child in contents(tag) {
+            let item = match child {
+                Node::Tag(item) if item.has_name("li") => item,
+                Node::Tag(term) if term.has_name("dt") => {
+                    let term = self.inline_children(&contents(&term));
+                    let term = normalize_paragraph(&term);
+                    items.push(if self.plain {term} else {format!("**{}**", term)});
+                    continue;
+                }
+                Node::Tag(definition) if definition.has_name("dd") => {
+                    let body = self.blocks(&contents(&definition)).join("\n\n");
+                    items.push(prefix_lines(&body, if self.plain {"  "} else {": "}, "  "));
+                    continue;
+                }
+                _ => continue,
+            };
+            let marker = if is_ordered {format!("{}. ", number)} else {String::from("- ")};
+            number += 1;
+            let body = self.blocks(&contents(&item)).join("\n");
+            items.push(prefix_lines(&body, &marker, &" ".repeat(marker.len())));
+        }
+        items.join("\n")
+    }
+    fn table(&self, tag: &Tag) -> Vec<String> {
+        let mut rows: Vec<(bool, Vec<Cell>)> = Vec::new();
+        let mut caption = None;
+        fn collect<'a>(node: &Node<'a>, rows: &mut Vec<Node<'a>>, caption: &mut Option<Node<'a>>) {
+            match node {
+                Node::Tag(tag) if tag.has_name("tr") => rows.push(node.clone()),
+                Node::Tag(tag) if tag.has_name("caption") => *caption = Some(node.clone()),
+                Node::Tag(tag) => {
+                    for child in contents(tag) {
+                        collect(&child, rows, caption);
+                    }
+                }
+                Node::Enclosure(x) => {
+                    for child in x.data.children.iter() {
+                        collect(child, rows, caption);
+                    }
+                }
+                _ => (),
+            }
+        }
+        let mut row_nodes = Vec::new();
+        for child in contents(tag) {
+            collect(&child, &mut row_nodes, &mut caption);
+        }
+        for row in row_nodes.iter().filter_map(Node::unwrap_tag) {
+            let cells = contents(row)
+                .iter()
+                .filter_map(Node::unwrap_tag)
+                .filter(|x| x.has_name("td") || x.has_name("th"))
+                .map(|cell| {
+                    let text = normalize_paragraph(&self.inline_children(&contents(cell)));
+                    let text = text.replace('\n', " ").replace('|', "\\|");
+                    let align = cell
+                        .get_parameter_value("style")
+                        .and_then(|x| x.split("text-align:").nth(1).map(ToOwned::to_owned))
+                        .map(|x| x.trim_matches(|c: char| !c.is_alphabetic()).to_owned());
+                    (text, align)
+                })
+                .collect::<Vec<_>>();
+            let is_header = contents(row)
+                .iter()
+                .filter_map(Node::unwrap_tag)
+                .any(|x| x.has_name("th"));
+            rows.push((is_header, cells));
+        }
+        let columns = rows.iter().map(|x| x.1.len()).max().unwrap_or(0);
+        if columns == 0 {
+            return Vec::new()
+        }
+        let widths = (0..columns)
+            .map(|ix| {
+                rows.iter()
+                    .filter_map(|x| x.1.get(ix))
+                    .map(|x| x.0.chars().count())
+                    .max()
+                    .unwrap_or(0)
+                    .max(3)
+            })
+            .collect::<Vec<_>>();
+        let format_row = |cells: &[Cell]| -> String {
+            let cells = (0..columns)
+                .map(|ix| {
+                    let text = cells.get(ix).map(|x| x.0.as_str()).unwrap_or("");
+                    format!("{:width$}", text, width = widths[ix])
+                })
+                .collect::<Vec<_>>();
+            if self.plain {
+                cells.join("  ").trim_end().to_owned()
+            } else {
+                format!("| {} |", cells.join(" | "))
+            }
+        };
+        let header_count = rows.iter().take_while(|x| x.0).count().max(1);
+        let alignment = rows
+            .first()
+            .map(|x| x.1.iter().map(|x| x.1.clone()).collect::<Vec<_>>())
+            .unwrap_or_default();
+        let separator = (0..columns)
+            .map(|ix| {
+                let width = widths[ix];
+                if self.plain {
+                    return "-".repeat(width)
+                }
+                match alignment.get(ix).cloned().flatten().as_deref() {
+                    Some("center") => format!(":{}:", "-".repeat(width - 2)),
+                    Some("right") => format!("{}:", "-".repeat(width - 1)),
+                    _ => "-".repeat(width),
+                }
+            })
+            .collect::<Vec<_>>();
+        let separator = if self.plain {
+            separator.join("  ")
+        } else {
+            format!("| {} |", separator.join(" | "))
+        };
+        let mut lines = Vec::new();
+        for (ix, (_, cells)) in rows.iter().enumerate() {
+            if ix == header_count {
+                lines.push(separator.clone());
+            }
+            lines.push(format_row(cells));
+        }
+        if rows.len() == header_count {
+            lines.push(separator);
+        }
+        let mut blocks = vec![lines.join("\n")];
+        if let Some(Node::Tag(caption)) = caption {
+            let caption = normalize_paragraph(&self.inline_children(&contents(&caption)));
+            blocks.push(self.escape_line_starts(&caption));
+        }
+        blocks
+    }
+    fn block(&self, tag: &Tag) -> Vec<String> {
+        let name = tag.name();
+        match name {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                let level = name[1..].parse::<usize>().unwrap_or(1);
+                let title = normalize_paragraph(&self.inline_children(&contents(tag)))
+                    .replace('\n', " ");
+                vec![self.heading(level, title)]
+            }
+            "ul" | "ol" | "dl" => vec![self.list(tag)],
+            "note" | "blockquote" if !self.plain => {
+                let body = self.blocks(&contents(tag)).join("\n\n");
+                vec![prefix_lines(&body, "> ", "> ")]
+            }
+            "figure" => {
+                let mut parts = tag.children
+                    .iter()
+                    .filter(|x| !x.is_whitespace())
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let caption = if parts.len() > 1 {parts.pop()} else {None};
+                let mut blocks = self.blocks(&parts.into_iter().flat_map(Node::unblock).collect::<Vec<_>>());
+                if let Some(caption) = caption {
+                    let caption = normalize_paragraph(&self.inline_children(&caption.unblock()));
+                    blocks.push(self.escape_line_starts(&caption));
+                }
+                blocks
+            }
+            "equation" => {
+                let latex = crate::frontend::pass::math::to_latex_source(tag.children.clone());
+                let latex = crate::frontend::pass::code::normalize_code(&latex)
+                    .lines()
+                    .map(str::trim_end)
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                if self.plain {
+                    return vec![latex]
+                }
+                let is_aligned = latex.contains('&') || latex.contains("\\\\");
+                if is_aligned {
+                    vec![format!("$$\n\\begin{{aligned}}\n{}\n\\end{{aligned}}\n$$", latex)]
+                } else {
+                    vec![format!("$$\n{}\n$$", latex)]
+                }
+            }
+            "code" | "pre" => {
+                let code = crate::frontend::pass::code::normalize_code(&verbatim_body(tag));
+                if self.plain {
+                    return vec![prefix_lines(&code, "    ", "    ")]
+                }
+                let lang = tag
+                    .get_parameter_value("lang")
+                    .or_else(|| tag.get_parameter_value("language"))
+                    .unwrap_or_default();
+                let fence = if code.contains("```") {"~~~~"} else {"```"};
+                vec![format!("{}{}\n{}\n{}", fence, lang, code, fence)]
+            }
+            "table" => self.table(tag),
+            "hr" => vec![String::from(if self.plain {"----------"} else {"---"})],
+            // A FORM FEED IN PLAIN TEXT; MARKDOWN HAS NO PAGES
+            "pagebreak" if self.plain => vec![String::from("\u{c}")],
+            "pagebreak" => Vec::new(),
+            _ => self.blocks(&contents(tag)),
+        }
+    }
+    /// Renders the given nodes as a list of blocks (e.g. paragraphs).
+    pub(crate) fn blocks(&self, nodes: &[Node]) -> Vec<String> {
+        let mut blocks = Vec::new();
+        let mut paragraph = String::new();
+        let flush = |paragraph: &mut String, blocks: &mut Vec<String>| {
+            let text = normalize_paragraph(paragraph);
+            if !text.is_empty() {
+                blocks.push(self.escape_line_starts(&text));
+            }
+            paragraph.clear();
+        };
+        for node in nodes.iter().cloned().flat_map(Node::into_fragment) {
+            match &node {
+                Node::String(txt) => {
+                    let text = self.escape(&txt.data);
+                    for (ix, part) in split_paragraphs(&text).into_iter().enumerate() {
+                        if ix > 0 {
+                            flush(&mut paragraph, &mut blocks);
+                        }
+                        paragraph.push_str(&part);
+                    }
+                }
+                Node::Tag(tag) if is_block(&node) => {
+                    flush(&mut paragraph, &mut blocks);
+                    blocks.extend(self.block(tag));
+                }
+                Node::Enclosure(x) if x.data.is_curly_brace() => {
+                    flush(&mut paragraph, &mut blocks);
+                    blocks.extend(self.blocks(&x.data.children));
+                }
+                node => paragraph.push_str(&self.inline(node)),
+            }
+        }
+        flush(&mut paragraph, &mut blocks);
+        blocks
+    }
+}
+
+/// Render the entire document.
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub body: Vec<String>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Document {
+    pub fn from_source(source: &str) -> Document {
+        Document::from_source_with(source, &Settings::default())
+    }
+    pub fn from_source_with(source: &str, settings: &Settings) -> Document {
+        let (nodes, diagnostics) = Compiler::markdown()
+            .settings(settings.clone())
+            .run(source);
+        Document::from_nodes(&nodes, diagnostics)
+    }
+    /// Renders the output of `Compiler::markdown`.
+    pub fn from_nodes(nodes: &[Node], diagnostics: Vec<Diagnostic>) -> Document {
+        let body = Renderer {plain: false}.blocks(nodes);
+        Document {body, diagnostics}
+    }
+    pub fn render_to_string(self) -> String {
+        let mut output = self.body.join("\n\n");
+        output.push('\n');
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::import::markdown::markdown_to_subscript;
+
+    fn render(source: &str) -> String {
+        Document::from_source(source).render_to_string()
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(render("a*b* [c] <d> & e_f"), "a\\*b\\* \\[c\\] \\<d> \\& e\\_f\n");
+    }
+
+    #[test]
+    fn block_markers_at_line_starts() {
+        assert_eq!(render("- a\n# b\n> c\nd - e"), "\\- a\n\n\\# b\n\n\\> c\n\nd - e\n");
+    }
+
+    #[test]
+    fn headings_notes_and_math() {
+        assert_eq!(
+            render("\\h2{Intro} \\note{See \\{x^2} \\b{now}.} \\equation{a \\\\ b}"),
+            "## Intro\n\n> See $x^2$ **now**.\n\n$$\n\\begin{aligned}\na \\\\ b\n\\end{aligned}\n$$\n",
+        );
+    }
+
+    #[test]
+    fn tables() {
+        assert_eq!(
+            render("\\table[header align=lr]{Name & Value \\\\ a|b & 1}{Caption}"),
+            "| Name | Value |\n| ---- | ----: |\n| a\\|b | 1     |\n\nTable 1. Caption\n",
+        );
+    }
+
+    #[test]
+    fn code() {
+        assert_eq!(
+            render("\\code{a`b} \\code[lang=rust]{fn f() {}}"),
+            "`` a`b ``\n\n```rust\nfn f() {}\n```\n",
+        );
+    }
+
+    /// Importing some Markdown document and exporting it again.
+    #[test]
+    fn round_trip() {
+        let markdown = concat!(
+            "# Title\n\n",
+            "Some *a*, **b** and `c`, see [x](https://a.org).\n\n",
+            "- one\n- two\n\n",
+            "1. first\n2. second\n\n",
+            "| A   | B   |\n| --- | --: |\n| 1   | 2   |\n\n",
+            "```rust\nfn main() {}\n```\n\n",
+            "$$\nx^2\n$$\n",
+        );
+        assert_eq!(render(&markdown_to_subscript(markdown)), markdown);
+    }
+
+    /// The same link, percent-encoded (see `import::markdown`).
+    #[test]
+    fn round_trip_urls() {
+        let markdown = "See [link](https://en.wikipedia.org/wiki/Rust_(programming_language)).\n";
+        let exported = render(&markdown_to_subscript(markdown));
+        assert_eq!(
+            exported,
+            "See [link](https://en.wikipedia.org/wiki/Rust_%28programming_language%29).\n",
+        );
+        assert_eq!(render(&markdown_to_subscript(&exported)), exported);
+        let text = crate::codegen::text::Document::from_source(&markdown_to_subscript(markdown))
+            .render_to_string();
+        assert!(text.contains("link (https://en.wikipedia.org/wiki/Rust_%28programming_language%29)"));
+    }
+}
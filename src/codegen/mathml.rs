@@ -0,0 +1,826 @@
+//! LaTeX (math mode) to MathML, for readers that can’t run MathJax (e.g.
+//! e-readers).
+//!
+//! Covers the commonly used subset of LaTeX: scripts, fractions, roots,
+//! `\left`/`\right` delimiters, accents, fonts, colors, spacing, Greek letters
+//! & symbols, and the `aligned`/`cases`/`matrix` family of environments
+//! (including top-level `&` & `\\` alignment). Anything else is kept as text.
+//! The LaTeX source is always attached as an annotation.
+use crate::codegen::html::escape_html;
+use crate::codegen::settings::MathMacro;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token<'a> {
+    Command(&'a str),
+    Open,
+    Close,
+    Sup,
+    Sub,
+    Prime,
+    Align,
+    NewRow,
+    Number(&'a str),
+    Char(char),
+}
+
+/// Tokens paired with their byte range in the source.
+fn tokenize(source: &str) -> Vec<(Token<'_>, usize, usize)> {
+    let mut tokens = Vec::new();
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, char)) = chars.next() {
+        let token = match char {
+            '\\' => {
+                match chars.peek().copied() {
+                    Some((_, '\\')) => {
+                        chars.next();
+                        Token::NewRow
+                    }
+                    Some((ix, x)) if x.is_ascii_alphabetic() => {
+                        let mut end = ix;
+                        while let Some((ix, x)) = chars.peek().copied() {
+                            if !x.is_ascii_alphabetic() {
+                                break;
+                            }
+                            end = ix + x.len_utf8();
+                            chars.next();
+                        }
+                        Token::Command(&source[ix..end])
+                    }
+                    Some((ix, x)) => {
+                        chars.next();
+                        Token::Command(&source[ix..ix + x.len_utf8()])
+                    }
+                    None => Token::Char('\\'),
+                }
+            }
+            '{' => Token::Open,
+            '}' => Token::Close,
+            '^' => Token::Sup,
+            '_' => Token::Sub,
+            '\'' => Token::Prime,
+            '&' => Token::Align,
+            x if x.is_whitespace() => continue,
+            x if x.is_ascii_digit() => {
+                let mut end = start + 1;
+                while let Some((ix, x)) = chars.peek().copied() {
+                    let is_decimal = x == '.' && source[ix + 1..]
+                        .chars()
+                        .next()
+                        .map(|x| x.is_ascii_digit())
+                        .unwrap_or(false);
+                    if !x.is_ascii_digit() && !is_decimal {
+                        break;
+                    }
+                    end = ix + 1;
+                    chars.next();
+                }
+                Token::Number(&source[start..end])
+            }
+            x => Token::Char(x),
+        };
+        let end = chars.peek().map(|(ix, _)| *ix).unwrap_or(source.len());
+        tokens.push((token, start, end));
+    }
+    tokens
+}
+
+/// Greek letters & other symbols written as identifiers.
+static IDENTIFIERS: &[(&str, &str)] = &[
+    ("alpha", "α"), ("beta", "β"), ("gamma", "γ"), ("delta", "δ"),
+    ("epsilon", "ϵ"), ("varepsilon", "ε"), ("zeta", "ζ"), ("eta", "η"),
+    ("theta", "θ"), ("vartheta", "ϑ"), ("iota", "ι"), ("kappa", "κ"),
+    ("lambda", "λ"), ("mu", "μ"), ("nu", "ν"), ("xi", "ξ"), ("pi", "π"),
+    ("varpi", "ϖ"), ("rho", "ρ"), ("varrho", "ϱ"), ("sigma", "σ"),
+    ("varsigma", "ς"), ("tau", "τ"), ("upsilon", "υ"), ("phi", "ϕ"),
+    ("varphi", "φ"), ("chi", "χ"), ("psi", "ψ"), ("omega", "ω"),
+    ("Gamma", "Γ"), ("Delta", "Δ"), ("Theta", "Θ"), ("Lambda", "Λ"),
+    ("Xi", "Ξ"), ("Pi", "Π"), ("Sigma", "Σ"), ("Upsilon", "Υ"), ("Phi", "Φ"),
+    ("Psi", "Ψ"), ("Omega", "Ω"),
+    ("infty", "∞"), ("partial", "∂"), ("nabla", "∇"), ("emptyset", "∅"),
+    ("varnothing", "∅"), ("ell", "ℓ"), ("hbar", "ℏ"), ("Re", "ℜ"),
+    ("Im", "ℑ"), ("aleph", "ℵ"), ("wp", "℘"), ("prime", "′"),
+    ("dots", "…"), ("ldots", "…"), ("cdots", "⋯"), ("vdots", "⋮"),
+    ("ddots", "⋱"), ("top", "⊤"), ("bot", "⊥"), ("angle", "∠"),
+    ("triangle", "△"), ("circ", "∘"), ("degree", "°"),
+];
+
+/// Symbols written as operators.
+static OPERATORS: &[(&str, &str)] = &[
+    ("pm", "±"), ("mp", "∓"), ("times", "×"), ("div", "÷"), ("cdot", "⋅"),
+    ("ast", "∗"), ("star", "⋆"), ("bullet", "∙"), ("oplus", "⊕"),
+    ("ominus", "⊖"), ("otimes", "⊗"), ("odot", "⊙"), ("wedge", "∧"),
+    ("land", "∧"), ("vee", "∨"), ("lor", "∨"), ("neg", "¬"), ("lnot", "¬"),
+    ("cap", "∩"), ("cup", "∪"), ("setminus", "∖"), ("backslash", "\\"),
+    ("leq", "≤"), ("le", "≤"), ("geq", "≥"), ("ge", "≥"), ("neq", "≠"),
+    ("ne", "≠"), ("ll", "≪"), ("gg", "≫"), ("approx", "≈"), ("sim", "∼"),
+    ("simeq", "≃"), ("cong", "≅"), ("equiv", "≡"), ("propto", "∝"),
+    ("doteq", "≐"), ("prec", "≺"), ("succ", "≻"), ("preceq", "⪯"),
+    ("succeq", "⪰"),
+    ("in", "∈"), ("notin", "∉"), ("ni", "∋"), ("subset", "⊂"),
+    ("supset", "⊃"), ("subseteq", "⊆"), ("supseteq", "⊇"), ("mid", "∣"),
+    ("nmid", "∤"), ("parallel", "∥"), ("perp", "⟂"), ("forall", "∀"),
+    ("exists", "∃"), ("nexists", "∄"), ("therefore", "∴"),
+    ("because", "∵"),
+    ("to", "→"), ("rightarrow", "→"), ("leftarrow", "←"), ("gets", "←"),
+    ("leftrightarrow", "↔"), ("Rightarrow", "⇒"), ("Leftarrow", "⇐"),
+    ("Leftrightarrow", "⇔"), ("implies", "⟹"), ("impliedby", "⟸"),
+    ("iff", "⟺"), ("mapsto", "↦"), ("longrightarrow", "⟶"),
+    ("longleftarrow", "⟵"), ("uparrow", "↑"), ("downarrow", "↓"),
+    ("hookrightarrow", "↪"), ("rightleftharpoons", "⇌"),
+    ("triangleleft", "◃"), ("triangleright", "▹"),
+    ("langle", "⟨"), ("rangle", "⟩"), ("lceil", "⌈"), ("rceil", "⌉"),
+    ("lfloor", "⌊"), ("rfloor", "⌋"), ("vert", "|"), ("Vert", "‖"),
+    ("lvert", "|"), ("rvert", "|"), ("lVert", "‖"), ("rVert", "‖"),
+    ("colon", ":"),
+];
+
+/// Operators whose scripts are placed above & below in display mode.
+static LARGE_OPERATORS: &[(&str, &str)] = &[
+    ("sum", "∑"), ("prod", "∏"), ("coprod", "∐"), ("bigcup", "⋃"),
+    ("bigcap", "⋂"), ("bigoplus", "⨁"), ("bigotimes", "⨂"),
+    ("bigvee", "⋁"), ("bigwedge", "⋀"),
+    ("lim", "lim"), ("limsup", "lim sup"), ("liminf", "lim inf"),
+    ("max", "max"), ("min", "min"), ("sup", "sup"), ("inf", "inf"),
+    ("det", "det"), ("gcd", "gcd"), ("Pr", "Pr"), ("argmax", "arg max"),
+    ("argmin", "arg min"),
+];
+
+/// Integrals; the scripts stay to the side.
+static INTEGRALS: &[(&str, &str)] = &[
+    ("int", "∫"), ("iint", "∬"), ("iiint", "∭"), ("oint", "∮"),
+];
+
+static FUNCTIONS: &[&str] = &[
+    "sin", "cos", "tan", "cot", "sec", "csc", "sinh", "cosh", "tanh",
+    "coth", "arcsin", "arccos", "arctan", "log", "ln", "lg", "exp", "arg",
+    "deg", "dim", "ker", "hom", "mod",
+];
+
+static ACCENTS: &[(&str, &str)] = &[
+    ("hat", "^"), ("widehat", "^"), ("tilde", "~"), ("widetilde", "~"),
+    ("bar", "¯"), ("overline", "¯"), ("vec", "→"), ("overrightarrow", "→"),
+    ("overleftarrow", "←"), ("dot", "˙"), ("ddot", "¨"), ("check", "ˇ"),
+    ("breve", "˘"), ("acute", "´"), ("grave", "`"), ("overbrace", "⏞"),
+];
+
+static FONTS: &[(&str, &str)] = &[
+    ("mathrm", "normal"), ("mathbf", "bold"), ("mathit", "italic"),
+    ("mathsf", "sans-serif"), ("mathtt", "monospace"),
+    ("mathbb", "double-struck"), ("mathcal", "script"),
+    ("mathscr", "script"), ("mathfrak", "fraktur"), ("boldsymbol", "bold"),
+    ("bm", "bold"),
+];
+
+static TEXT_COMMANDS: &[&str] = &[
+    "text", "textrm", "textit", "textbf", "textsf", "texttt", "mbox",
+    "textnormal",
+];
+
+static SPACES: &[(&str, &str)] = &[
+    (",", "0.1667em"), ("thinspace", "0.1667em"), (":", "0.2222em"),
+    (">", "0.2222em"), ("medspace", "0.2222em"), (";", "0.2778em"),
+    ("thickspace", "0.2778em"), (" ", "0.25em"), ("quad", "1em"),
+    ("qquad", "2em"), ("!", "-0.1667em"),
+];
+
+/// Environments, paired with their opening & closing delimiters.
+static ENVIRONMENTS: &[(&str, &str, &str)] = &[
+    ("aligned", "", ""), ("align", "", ""), ("align*", "", ""),
+    ("split", "", ""), ("gathered", "", ""), ("gather", "", ""),
+    ("gather*", "", ""), ("equation", "", ""), ("equation*", "", ""),
+    ("array", "", ""), ("matrix", "", ""), ("smallmatrix", "", ""),
+    ("pmatrix", "(", ")"), ("bmatrix", "[", "]"), ("Bmatrix", "{", "}"),
+    ("vmatrix", "|", "|"), ("Vmatrix", "‖", "‖"), ("cases", "{", ""),
+];
+
+/// The MathJax macros of `assets/deps.html`, paired with their arity.
+static MACROS: &[(&str, usize, &str)] = &[
+    ("dx", 0, "{\\, \\mathrm{d}x\\;}"),
+    ("Integrate", 3, "{\\int_{#1}^{#2}\\, #3 \\dx}"),
+    ("IntegrateFor", 4, "{\\int_{#1}^{#2}\\, #3 {\\, \\mathrm{d}{#4}\\;}}"),
+    ("I", 1, "{\\Integrate{a}{b}{#1}}"),
+    ("angles", 2, "\\langle {#1},{#2} \\rangle"),
+    ("s", 1, "\\;\\mathrm{{#1}}"),
+    ("D", 2, "{\\frac{\\mathrm{d}{#1}}{\\mathrm{d}{#2}}}"),
+    ("colorA", 1, "\\textcolor{Periwinkle}{#1}"),
+    ("colorB", 1, "\\textcolor{DarkOrchid}{#1}"),
+    ("colorC", 1, "\\textcolor{Aquamarine}{#1}"),
+    ("colorD", 1, "\\textcolor{SeaGreen}{#1}"),
+    ("dPrime", 0, "{\\prime\\prime}"),
+    ("reciprocal", 1, "\\frac{1}{#1}"),
+    ("parens", 1, "\\left({#1}\\right)"),
+];
+
+/// `dvipsnames` colors that CSS doesn’t define.
+static COLORS: &[(&str, &str)] = &[
+    ("Periwinkle", "#6e73ff"),
+];
+
+/// Font size commands, which are ignored.
+static SIZES: &[&str] = &[
+    "tiny", "scriptsize", "footnotesize", "small", "normalsize", "large",
+    "Large", "LARGE", "huge", "Huge",
+];
+
+fn lookup<'a>(table: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    table.iter().find(|(key, _)| *key == name).map(|(_, value)| *value)
+}
+
+fn mi(value: &str) -> String {
+    format!("<mi>{}</mi>", escape_html(value))
+}
+
+fn mo(value: &str) -> String {
+    format!("<mo>{}</mo>", escape_html(value))
+}
+
+fn mrow(children: Vec<String>) -> String {
+    if children.len() == 1 {
+        return children.into_iter().next().unwrap()
+    }
+    format!("<mrow>{}</mrow>", children.concat())
+}
+
+/// Text content; leading & trailing spaces would otherwise be dropped.
+fn mtext(value: &str) -> String {
+    let value = escape_html(value);
+    let trimmed_start = value.trim_start();
+    let trimmed = trimmed_start.trim_end();
+    format!(
+        "<mtext>{}{}{}</mtext>",
+        "\u{a0}".repeat(value.len() - trimmed_start.len()),
+        trimmed,
+        "\u{a0}".repeat(trimmed_start.len() - trimmed.len()),
+    )
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    tokens: Vec<(Token<'a>, usize, usize)>,
+    ix: usize,
+    display: bool,
+    macros: &'a [MathMacro],
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<Token<'a>> {
+        self.tokens.get(self.ix).map(|x| x.0)
+    }
+    fn next(&mut self) -> Option<Token<'a>> {
+        let token = self.peek();
+        self.ix += 1;
+        token
+    }
+    fn is_row_end(&self) -> bool {
+        matches!(
+            self.peek(),
+            None | Some(Token::Close) | Some(Token::Align) | Some(Token::NewRow)
+                | Some(Token::Command("end")) | Some(Token::Command("right"))
+        )
+    }
+    /// The raw source of the next group (e.g. `\text{...}`), or the next token.
+    fn raw_argument(&mut self) -> String {
+        match self.peek() {
+            Some(Token::Open) => {
+                let start = self.tokens[self.ix].2;
+                let mut depth = 0;
+                while let Some((token, token_start, _)) = self.tokens.get(self.ix).copied() {
+                    self.ix += 1;
+                    match token {
+                        Token::Open => depth += 1,
+                        Token::Close if depth == 1 => {
+                            return self.source[start..token_start].to_owned()
+                        }
+                        Token::Close => depth -= 1,
+                        _ => (),
+                    }
+                }
+                self.source[start..].to_owned()
+            }
+            Some(_) => {
+                let (_, start, end) = self.tokens[self.ix];
+                self.ix += 1;
+                self.source[start..end].trim().to_owned()
+            }
+            None => String::new(),
+        }
+    }
+    /// An optional `[...]` argument.
+    fn optional_argument(&mut self) -> Option<Vec<String>> {
+        if self.peek() != Some(Token::Char('[')) {
+            return None
+        }
+        self.ix += 1;
+        let mut children = Vec::new();
+        while !self.is_row_end() && self.peek() != Some(Token::Char(']')) {
+            children.push(self.script());
+        }
+        if self.peek() == Some(Token::Char(']')) {
+            self.ix += 1;
+        }
+        Some(children)
+    }
+    fn argument(&mut self) -> String {
+        match self.peek() {
+            Some(Token::Open) => self.atom(),
+            Some(Token::Number(number)) => {
+                // Only the first digit is an argument (e.g. `x^23`).
+                let (_, start, end) = self.tokens[self.ix];
+                let first = number.chars().next().unwrap();
+                if number.len() > 1 {
+                    let offset = start + first.len_utf8();
+                    self.tokens[self.ix] = (Token::Number(&self.source[offset..end]), offset, end);
+                } else {
+                    self.ix += 1;
+                }
+                format!("<mn>{}</mn>", first)
+            }
+            Some(_) if !self.is_row_end() => self.atom(),
+            _ => String::from("<mrow></mrow>"),
+        }
+    }
+    fn row(&mut self) -> Vec<String> {
+        let mut children = Vec::new();
+        while !self.is_row_end() {
+            children.push(self.script());
+        }
+        children
+    }
+    /// An atom followed by its scripts (if any).
+    fn script(&mut self) -> String {
+        let (base, is_large) = match self.peek() {
+            Some(Token::Command(name)) if lookup(LARGE_OPERATORS, name).is_some() => {
+                self.ix += 1;
+                let symbol = lookup(LARGE_OPERATORS, name).unwrap();
+                let base = if symbol.chars().all(char::is_alphabetic) && symbol.len() > 1 {
+                    format!("<mo movablelimits=\"true\" form=\"prefix\">{}</mo>", symbol)
+                } else {
+                    format!("<mo largeop=\"true\" movablelimits=\"true\">{}</mo>", symbol)
+                };
+                (base, true)
+            }
+            Some(Token::Sup) | Some(Token::Sub) | Some(Token::Prime) => {
+                (String::from("<mrow></mrow>"), false)
+            }
+            _ => (self.atom(), false),
+        };
+        let mut sup: Option<String> = None;
+        let mut sub: Option<String> = None;
+        let mut primes = String::new();
+        loop {
+            match self.peek() {
+                Some(Token::Sup) if sup.is_none() => {
+                    self.ix += 1;
+                    sup = Some(self.argument());
+                }
+                Some(Token::Sub) if sub.is_none() => {
+                    self.ix += 1;
+                    sub = Some(self.argument());
+                }
+                Some(Token::Prime) if sup.is_none() => {
+                    self.ix += 1;
+                    primes.push('′');
+                }
+                Some(Token::Command("limits")) | Some(Token::Command("nolimits")) => {
+                    self.ix += 1;
+                }
+                _ => break,
+            }
+        }
+        let sup = match (sup, primes.is_empty()) {
+            (Some(sup), false) => Some(format!("<mrow>{}{}</mrow>", mo(&primes), sup)),
+            (None, false) => Some(mo(&primes)),
+            (sup, true) => sup,
+        };
+        let (under, over, both) = if is_large && self.display {
+            ("munder", "mover", "munderover")
+        } else {
+            ("msub", "msup", "msubsup")
+        };
+        match (sub, sup) {
+            (Some(sub), Some(sup)) => format!("<{0}>{1}{2}{3}</{0}>", both, base, sub, sup),
+            (Some(sub), None) => format!("<{0}>{1}{2}</{0}>", under, base, sub),
+            (None, Some(sup)) => format!("<{0}>{1}{2}</{0}>", over, base, sup),
+            (None, None) => base,
+        }
+    }
+    fn delimiter(&mut self) -> String {
+        let value = match self.next() {
+            Some(Token::Char('.')) | None => return String::new(),
+            Some(Token::Char(x)) => x.to_string(),
+            Some(Token::Command("{")) | Some(Token::Command("lbrace")) => String::from("{"),
+            Some(Token::Command("}")) | Some(Token::Command("rbrace")) => String::from("}"),
+            Some(Token::Command("|")) => String::from("‖"),
+            Some(Token::Command(name)) => {
+                lookup(OPERATORS, name).unwrap_or(name).to_owned()
+            }
+            Some(_) => return String::new(),
+        };
+        format!("<mo fence=\"true\" stretchy=\"true\">{}</mo>", escape_html(&value))
+    }
+    /// The rows & cells of some (possibly implicit) table.
+    fn table_rows(&mut self) -> Vec<Vec<String>> {
+        let mut rows = vec![vec![]];
+        loop {
+            let cell = mrow(self.row());
+            rows.last_mut().unwrap().push(cell);
+            match self.peek() {
+                Some(Token::Align) => {
+                    self.ix += 1;
+                }
+                Some(Token::NewRow) => {
+                    self.ix += 1;
+                    rows.push(Vec::new());
+                }
+                Some(Token::Close) => {
+                    // Unbalanced closing brace.
+                    self.ix += 1;
+                }
+                _ => break,
+            }
+        }
+        // A trailing `\\` doesn’t start a new row.
+        if rows.len() > 1 && rows.last().map(|x| x.iter().all(|x| x == "<mrow></mrow>")).unwrap_or(false) {
+            rows.pop();
+        }
+        rows
+    }
+    fn table(rows: Vec<Vec<String>>, is_aligned: bool) -> String {
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+        let alignment = if is_aligned {
+            (0..columns)
+                .map(|ix| if ix % 2 == 0 {"right"} else {"left"})
+                .collect::<Vec<_>>()
+                .join(" ")
+        } else {
+            String::from("center")
+        };
+        let spacing = if is_aligned {" columnspacing=\"0em\""} else {""};
+        let rows = rows
+            .into_iter()
+            .map(|row| {
+                let cells = row
+                    .into_iter()
+                    .map(|x| format!("<mtd>{}</mtd>", x))
+                    .collect::<String>();
+                format!("<mtr>{}</mtr>", cells)
+            })
+            .collect::<String>();
+        format!(
+            "<mtable displaystyle=\"true\" columnalign=\"{}\"{}>{}</mtable>",
+            alignment,
+            spacing,
+            rows,
+        )
+    }
+    /// Either `\begin{name}...\end{name}`, or (if `is_group`) the MathJax
+    /// form `\name{...}`.
+    fn environment(&mut self, name: String, is_group: bool) -> String {
+        if name == "array" {
+            let _ = self.raw_argument();
+        }
+        if is_group {
+            self.ix += 1;
+        }
+        let rows = self.table_rows();
+        if is_group {
+            if self.peek() == Some(Token::Close) {
+                self.ix += 1;
+            }
+        } else if self.peek() == Some(Token::Command("end")) {
+            self.ix += 1;
+            let _ = self.raw_argument();
+        }
+        let (open, close) = ENVIRONMENTS
+            .iter()
+            .find(|(key, _, _)| *key == name)
+            .map(|(_, open, close)| (*open, *close))
+            .unwrap_or(("", ""));
+        let is_aligned = ["aligned", "align", "align*", "split"].contains(&name.as_str());
+        let table = if name == "cases" {
+            Self::table(rows, false).replace("columnalign=\"center\"", "columnalign=\"left\"")
+        } else {
+            Self::table(rows, is_aligned)
+        };
+        let fence = |x: &str| if x.is_empty() {
+            String::new()
+        } else {
+            format!("<mo fence=\"true\" stretchy=\"true\">{}</mo>", escape_html(x))
+        };
+        if open.is_empty() && close.is_empty() {
+            return table
+        }
+        format!("<mrow>{}{}{}</mrow>", fence(open), table, fence(close))
+    }
+    fn command(&mut self, name: &'a str) -> String {
+        if let Some(value) = lookup(IDENTIFIERS, name) {
+            let is_upright = value.chars().next().map(|x| x.is_uppercase()).unwrap_or(false)
+                || !value.chars().all(char::is_alphabetic);
+            return if is_upright {
+                format!("<mi mathvariant=\"normal\">{}</mi>", value)
+            } else {
+                mi(value)
+            }
+        }
+        if let Some(value) = lookup(OPERATORS, name) {
+            return mo(value)
+        }
+        if let Some(value) = lookup(INTEGRALS, name) {
+            return format!("<mo largeop=\"true\">{}</mo>", value)
+        }
+        if let Some(width) = lookup(SPACES, name) {
+            return format!("<mspace width=\"{}\"></mspace>", width)
+        }
+        if FUNCTIONS.contains(&name) {
+            return format!("<mi>{}</mi><mo>\u{2061}</mo>", name)
+        }
+        if let Some(accent) = lookup(ACCENTS, name) {
+            let base = self.argument();
+            return format!("<mover accent=\"true\">{}{}</mover>", base, mo(accent))
+        }
+        if let Some(variant) = lookup(FONTS, name) {
+            return self.argument().replace("<mi>", &format!("<mi mathvariant=\"{}\">", variant))
+        }
+        if TEXT_COMMANDS.contains(&name) {
+            return mtext(&self.raw_argument())
+        }
+        if SIZES.contains(&name) {
+            return String::new()
+        }
+        let user_macro = self.macros
+            .iter()
+            .find(|x| x.name == name)
+            .map(|x| (x.arity, x.body.as_str()));
+        let builtin_macro = MACROS
+            .iter()
+            .find(|x| x.0 == name)
+            .map(|x| (x.1, x.2));
+        if let Some((arity, body)) = user_macro.or(builtin_macro) {
+            let mut expansion = body.to_string();
+            for ix in 1..=arity {
+                let argument = self.raw_argument();
+                expansion = expansion.replace(&format!("#{}", ix), &argument);
+            }
+            let mut parser = Parser {
+                source: &expansion,
+                tokens: tokenize(&expansion),
+                ix: 0,
+                display: self.display,
+                macros: self.macros,
+            };
+            return mrow(parser.row())
+        }
+        match name {
+            "{" | "}" | "%" | "$" | "#" | "&" | "_" => mo(name),
+            "|" => mo("‖"),
+            "lbrace" => mo("{"),
+            "rbrace" => mo("}"),
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let numerator = self.argument();
+                let denominator = self.argument();
+                format!("<mfrac>{}{}</mfrac>", numerator, denominator)
+            }
+            "binom" | "dbinom" | "tbinom" => {
+                let top = self.argument();
+                let bottom = self.argument();
+                format!(
+                    "<mrow><mo>(</mo><mfrac linethickness=\"0\">{}{}</mfrac><mo>)</mo></mrow>",
+                    top,
+                    bottom,
+                )
+            }
+            "sqrt" => {
+                let index = self.optional_argument();
+                let radicand = self.argument();
+                match index {
+                    Some(index) => format!("<mroot>{}{}</mroot>", radicand, mrow(index)),
+                    None => format!("<msqrt>{}</msqrt>", radicand),
+                }
+            }
+            "operatorname" => {
+                let name = self.raw_argument();
+                format!("<mi>{}</mi><mo>\u{2061}</mo>", escape_html(name.trim()))
+            }
+            "underline" => {
+                let base = self.argument();
+                format!("<munder accentunder=\"true\">{}<mo>_</mo></munder>", base)
+            }
+            "underbrace" => {
+                let base = self.argument();
+                format!("<munder accentunder=\"true\">{}<mo>⏟</mo></munder>", base)
+            }
+            "overset" | "stackrel" => {
+                let over = self.argument();
+                let base = self.argument();
+                format!("<mover>{}{}</mover>", base, over)
+            }
+            "underset" => {
+                let under = self.argument();
+                let base = self.argument();
+                format!("<munder>{}{}</munder>", base, under)
+            }
+            "textcolor" => {
+                let color = self.raw_argument();
+                let color = lookup(COLORS, color.trim()).map(ToOwned::to_owned).unwrap_or(color);
+                let body = self.argument();
+                format!("<mstyle mathcolor=\"{}\">{}</mstyle>", escape_html(color.trim()), body)
+            }
+            "color" => {
+                let color = self.raw_argument();
+                let color = lookup(COLORS, color.trim()).map(ToOwned::to_owned).unwrap_or(color);
+                let body = self.row();
+                format!("<mstyle mathcolor=\"{}\">{}</mstyle>", escape_html(color.trim()), body.concat())
+            }
+            "displaystyle" | "textstyle" => {
+                let body = self.row();
+                format!(
+                    "<mstyle displaystyle=\"{}\">{}</mstyle>",
+                    name == "displaystyle",
+                    body.concat(),
+                )
+            }
+            "left" => {
+                let open = self.delimiter();
+                let body = self.row();
+                let close = if self.peek() == Some(Token::Command("right")) {
+                    self.ix += 1;
+                    self.delimiter()
+                } else {
+                    String::new()
+                };
+                format!("<mrow>{}{}{}</mrow>", open, body.concat(), close)
+            }
+            "big" | "Big" | "bigg" | "Bigg" | "bigl" | "bigr" | "Bigl" | "Bigr"
+            | "biggl" | "biggr" | "Biggl" | "Biggr" | "middle" => self.delimiter(),
+            "begin" => {
+                let name = self.raw_argument();
+                self.environment(name, false)
+            }
+            name if ENVIRONMENTS.iter().any(|x| x.0 == name) && self.peek() == Some(Token::Open) => {
+                self.environment(name.to_owned(), true)
+            }
+            "not" => {
+                let next = self.atom();
+                next.replacen("</mo>", "\u{338}</mo>", 1)
+            }
+            _ => mtext(&format!("\\{}", name)),
+        }
+    }
+    fn atom(&mut self) -> String {
+        match self.next() {
+            Some(Token::Open) => {
+                let children = self.row();
+                if self.peek() == Some(Token::Close) {
+                    self.ix += 1;
+                }
+                format!("<mrow>{}</mrow>", children.concat())
+            }
+            Some(Token::Number(number)) => format!("<mn>{}</mn>", number),
+            Some(Token::Char(x)) if x.is_alphabetic() => mi(&x.to_string()),
+            Some(Token::Char('-')) => mo("−"),
+            Some(Token::Char('*')) => mo("∗"),
+            Some(Token::Char(x)) => mo(&x.to_string()),
+            Some(Token::Command(name)) => self.command(name),
+            Some(Token::Prime) => mo("′"),
+            Some(_) | None => String::from("<mrow></mrow>"),
+        }
+    }
+}
+
+/// Entrypoint. Converts the LaTeX (math mode) source into a `<math>` element.
+pub fn latex_to_mathml(latex: &str, display: bool) -> String {
+    latex_to_mathml_with(latex, display, &[])
+}
+
+/// Like `latex_to_mathml`, with additional (e.g. per project) macros.
+pub fn latex_to_mathml_with(latex: &str, display: bool, macros: &[MathMacro]) -> String {
+    let mut parser = Parser {
+        source: latex,
+        tokens: tokenize(latex),
+        ix: 0,
+        display,
+        macros,
+    };
+    let mut rows = parser.table_rows();
+    while parser.ix < parser.tokens.len() {
+        // E.g. a stray `\end` or `\right`.
+        parser.ix += 1;
+        rows.extend(parser.table_rows());
+    }
+    let body = if rows.len() == 1 && rows[0].len() == 1 {
+        rows.pop().unwrap().pop().unwrap()
+    } else {
+        Parser::table(rows, true)
+    };
+    format!(
+        "<math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"{}\">\
+        <semantics><mrow>{}</mrow>\
+        <annotation encoding=\"application/x-tex\">{}</annotation>\
+        </semantics></math>",
+        if display {"block"} else {"inline"},
+        body,
+        escape_html(latex.trim()),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The converted expression, without the `<math>` wrapper & annotation.
+    fn body(latex: &str) -> String {
+        let math = latex_to_mathml(latex, false);
+        let start = math.find("<semantics><mrow>").unwrap() + "<semantics><mrow>".len();
+        let end = math.rfind("</mrow><annotation").unwrap();
+        math[start..end].to_owned()
+    }
+
+    #[test]
+    fn scripts_and_fractions() {
+        assert_eq!(body("x^2_i"), "<msubsup><mi>x</mi><mi>i</mi><mn>2</mn></msubsup>");
+        assert_eq!(
+            body("\\frac{a}{b+1}"),
+            concat!(
+                "<mfrac><mrow><mi>a</mi></mrow><mrow><mi>b</mi><mo>+</mo><mn>1</mn>",
+                "</mrow></mfrac>",
+            ),
+        );
+        assert_eq!(body("\\sqrt{x}"), "<msqrt><mrow><mi>x</mi></mrow></msqrt>");
+    }
+
+    #[test]
+    fn symbols_and_functions() {
+        assert_eq!(
+            body("\\alpha \\leq \\sin x"),
+            concat!(
+                "<mrow><mi>α</mi><mo>≤</mo><mi>sin</mi><mo>\u{2061}</mo><mi>x</mi>",
+                "</mrow>",
+            ),
+        );
+        assert_eq!(
+            body("\\sum_{i=1}^n"),
+            concat!(
+                "<msubsup><mo largeop=\"true\" movablelimits=\"true\">∑</mo><mrow>",
+                "<mi>i</mi><mo>=</mo><mn>1</mn></mrow><mi>n</mi></msubsup>",
+            ),
+        );
+    }
+
+    #[test]
+    fn alignment() {
+        assert_eq!(
+            body("a &= b \\\\ &= c"),
+            concat!(
+                "<mtable displaystyle=\"true\" columnalign=\"right left\" columnspacing=\"0em\">",
+                "<mtr><mtd><mi>a</mi></mtd><mtd><mrow><mo>=</mo><mi>b</mi></mrow></mtd>",
+                "</mtr><mtr><mtd><mrow></mrow></mtd><mtd><mrow><mo>=</mo><mi>c</mi>",
+                "</mrow></mtd></mtr></mtable>",
+            ),
+        );
+        assert_eq!(
+            body("\\begin{cases} 1 & x \\end{cases}"),
+            concat!(
+                "<mrow><mo fence=\"true\" stretchy=\"true\">{</mo>",
+                "<mtable displaystyle=\"true\" columnalign=\"left\"><mtr><mtd>",
+                "<mn>1</mn></mtd><mtd><mi>x</mi></mtd></mtr></mtable></mrow>",
+            ),
+        );
+    }
+
+    #[test]
+    fn unknown_commands_are_kept_as_text() {
+        assert_eq!(body("\\foo x"), "<mrow><mtext>\\foo</mtext><mi>x</mi></mrow>");
+    }
+
+    #[test]
+    fn macros() {
+        let macros = [MathMacro {
+            name: String::from("norm"),
+            arity: 1,
+            body: String::from("\\left| #1 \\right|"),
+        }];
+        assert_eq!(
+            latex_to_mathml_with("\\norm{v}", false, &macros),
+            concat!(
+                "<math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"inline\">",
+                "<semantics><mrow><mrow><mo fence=\"true\" stretchy=\"true\">|</mo>",
+                "<mi>v</mi><mo fence=\"true\" stretchy=\"true\">|</mo></mrow></mrow>",
+                "<annotation encoding=\"application/x-tex\">\\norm{v}</annotation>",
+                "</semantics></math>",
+            ),
+        );
+    }
+
+    #[test]
+    fn display_and_annotation() {
+        assert_eq!(
+            latex_to_mathml("a<b", true),
+            concat!(
+                "<math xmlns=\"http://www.w3.org/1998/Math/MathML\" display=\"block\">",
+                "<semantics><mrow><mrow><mi>a</mi><mo>&lt;</mo><mi>b</mi></mrow></mrow>",
+                "<annotation encoding=\"application/x-tex\">a&lt;b</annotation>",
+                "</semantics></math>",
+            ),
+        );
+    }
+}
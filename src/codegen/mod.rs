@@ -1 +1,11 @@
-pub mod html;
\ No newline at end of file
+pub mod html;
+pub mod epub;
+pub mod markdown;
+pub mod mathml;
+pub mod text;
+pub mod slides;
+pub mod config;
+pub mod settings;
+pub mod highlight;
+pub mod search;
+pub mod site;
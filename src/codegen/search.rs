@@ -0,0 +1,272 @@
+//! Full-text search index for compiled sites.
+//!
+//! Every document is split into sections at its headings, and the words of
+//! each section (including the symbol names of its math, e.g. `alpha` for
+//! `\alpha`) are mapped to the section. The index is emitted as a script
+//! (rather than JSON), so that the search widget (see `assets/search.html`)
+//! also works from `file://` URLs:
+//!
+//! ```text
+//! window.SUBSCRIPT_SEARCH_INDEX = {
+//!     "documents": [["notes/week-1.html", "Week 1"], ...],
+//!     "sections": [[0, "Heading%20id", "Heading", "Snippet..."], ...],
+//!     "terms": {"word": [0, 3, ...], ...}
+//! };
+//! ```
+use std::collections::{BTreeMap, BTreeSet};
+use crate::frontend::data::*;
+use crate::frontend::ast::*;
+use crate::codegen::settings::Settings;
+use crate::codegen::html::escape_js_string;
+
+/// The file name of the search index, at the root of the site.
+pub static SEARCH_INDEX_FILE_NAME: &str = "search-index.js";
+
+/// The maximum length (in characters) of the section snippets.
+static SNIPPET_LENGTH: usize = 160;
+
+/// Words shorter than this aren’t indexed.
+static MIN_WORD_LENGTH: usize = 2;
+
+/// Tags without any searchable text.
+static IGNORED_TAGS: &[&str] = &[INLINE_SVG_TAG, "script", "style", "img"];
+
+/// The text following some heading (or the start of the document).
+#[derive(Debug, Clone)]
+pub struct Section {
+    /// The id of the heading (see `annotate_heading_nodes`); empty for the
+    /// text before the first heading.
+    pub anchor: String,
+    pub heading: String,
+    pub text: String,
+    /// The symbol names of the math (e.g. `alpha` or `int`).
+    pub symbols: Vec<String>,
+}
+
+/// A compiled document of the site.
+#[derive(Debug, Clone)]
+pub struct SearchDocument {
+    /// Relative to the root of the site, e.g. `notes/week-1.html`.
+    pub href: String,
+    pub title: String,
+    pub sections: Vec<Section>,
+}
+
+fn math_symbols(nodes: &[Node], symbols: &mut Vec<String>) {
+    for node in nodes {
+        match node {
+            Node::Ident(ident) => symbols.push(ident.data.to_string()),
+            Node::Tag(tag) => {
+                symbols.push(tag.name().to_owned());
+                math_symbols(&tag.children, symbols);
+            }
+            Node::Enclosure(block) => math_symbols(&block.data.children, symbols),
+            _ => (),
+        }
+    }
+}
+
+/// The text of the given nodes (e.g. of some heading), without any math.
+fn text_of(nodes: &[Node]) -> String {
+    nodes
+        .iter()
+        .map(|node| match node {
+            Node::Tag(tag) if tag.has_name("equation") || tag.has_name(INLINE_MATH_TAG) => String::new(),
+            Node::Tag(tag) if IGNORED_TAGS.contains(&tag.name()) => String::new(),
+            Node::Tag(tag) => text_of(&tag.children),
+            Node::Enclosure(block) => text_of(&block.data.children),
+            Node::String(text) => text.data.to_string(),
+            _ => String::new(),
+        })
+        .collect()
+}
+
+fn collect(nodes: &[Node], sections: &mut Vec<Section>) {
+    for node in nodes {
+        match node {
+            Node::Tag(tag) if tag.is_heading_node() => {
+                // THE ID OF THE COMPILED HEADING
+                let heading = crate::frontend::pass::math::latex_pass(Node::Tag(tag.clone()));
+                let anchor = heading
+                    .unwrap_tag()
+                    .map(|x| crate::frontend::pass::html_normalize::heading_id(&x.children))
+                    .unwrap_or_default();
+                sections.push(Section {
+                    anchor,
+                    heading: text_of(&tag.children),
+                    text: String::new(),
+                    symbols: Vec::new(),
+                });
+            }
+            Node::Tag(tag) if tag.has_name("equation") || tag.has_name(INLINE_MATH_TAG) => {
+                math_symbols(&tag.children, &mut sections.last_mut().unwrap().symbols);
+            }
+            Node::Tag(tag) if IGNORED_TAGS.contains(&tag.name()) => (),
+            Node::Tag(tag) => {
+                if BLOCK_LEVEL_TAGS.contains(&tag.name()) {
+                    sections.last_mut().unwrap().text.push(' ');
+                }
+                collect(&tag.children, sections);
+            }
+            Node::Enclosure(block) => collect(&block.data.children, sections),
+            Node::String(text) => sections.last_mut().unwrap().text.push_str(&text.data),
+            _ => (),
+        }
+    }
+}
+
+/// The sections of the given source. Math is indexed by its symbol names,
+/// rather than rendered.
+pub fn index_source(source: &str, title: &str, settings: &Settings) -> Vec<Section> {
+    let (nodes, _) = crate::compiler::Compiler::search()
+        .settings(settings.clone())
+        .run(source);
+    let mut sections = vec![Section {
+        anchor: String::new(),
+        heading: title.to_owned(),
+        text: String::new(),
+        symbols: Vec::new(),
+    }];
+    collect(&nodes, &mut sections);
+    sections
+        .into_iter()
+        .map(|section| Section {
+            heading: section.heading.split_whitespace().collect::<Vec<_>>().join(" "),
+            text: section.text.split_whitespace().collect::<Vec<_>>().join(" "),
+            ..section
+        })
+        .filter(|x| !x.text.is_empty() || !x.anchor.is_empty())
+        .collect()
+}
+
+fn words(text: &str) -> impl Iterator<Item=String> + '_ {
+    text
+        .split(|x: char| !x.is_alphanumeric())
+        .filter(|x| x.chars().count() >= MIN_WORD_LENGTH)
+        .map(str::to_lowercase)
+}
+
+fn snippet(text: &str) -> String {
+    if text.chars().count() <= SNIPPET_LENGTH {
+        return text.to_owned()
+    }
+    let mut snippet = text.chars().take(SNIPPET_LENGTH).collect::<String>();
+    if let Some(ix) = snippet.rfind(' ') {
+        snippet.truncate(ix);
+    }
+    snippet.push('…');
+    snippet
+}
+
+/// The search index script for the given documents.
+pub fn render_search_index(documents: &[SearchDocument]) -> String {
+    let mut terms: BTreeMap<String, BTreeSet<usize>> = BTreeMap::new();
+    let mut sections = Vec::new();
+    for (document_ix, document) in documents.iter().enumerate() {
+        for section in document.sections.iter() {
+            let section_ix = sections.len();
+            let words = words(&section.heading)
+                .chain(words(&section.text))
+                .chain(section.symbols.iter().flat_map(|x| words(x)));
+            for word in words {
+                terms.entry(word).or_default().insert(section_ix);
+            }
+            sections.push(format!(
+                "[{}, \"{}\", \"{}\", \"{}\"]",
+                document_ix,
+                escape_js_string(&section.anchor),
+                escape_js_string(&section.heading),
+                escape_js_string(&snippet(&section.text)),
+            ));
+        }
+    }
+    let documents = documents
+        .iter()
+        .map(|x| format!("[\"{}\", \"{}\"]", escape_js_string(&x.href), escape_js_string(&x.title)))
+        .collect::<Vec<_>>();
+    let terms = terms
+        .iter()
+        .map(|(term, sections)| format!(
+            "\"{}\": [{}]",
+            escape_js_string(term),
+            sections.iter().map(ToString::to_string).collect::<Vec<_>>().join(", "),
+        ))
+        .collect::<Vec<_>>();
+    format!(
+        "window.SUBSCRIPT_SEARCH_INDEX = {{\n\"documents\": [\n{}\n],\n\"sections\": [\n{}\n],\n\"terms\": {{\n{}\n}}\n}};\n",
+        documents.join(",\n"),
+        sections.join(",\n"),
+        terms.join(",\n"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The anchor, heading, text & symbols of each section.
+    fn sections(source: &str) -> Vec<[String; 4]> {
+        index_source(source, "Title", &Settings::default())
+            .into_iter()
+            .map(|x| [x.anchor, x.heading, x.text, x.symbols.join(" ")])
+            .collect()
+    }
+
+    #[test]
+    fn sections_at_headings() {
+        let source = "\\p{Intro.} \\h2{First \\{x}} \\p{One \\b{two}.}\\p{Three.} \\h3{Empty}";
+        assert_eq!(sections(source), vec![
+            ["", "Title", "Intro.", ""],
+            ["First \\(x\\)", "First", "One two. Three.", ""],
+            ["Empty", "Empty", "", ""],
+        ]);
+        // Without any text before the first heading.
+        assert_eq!(sections("\\h2{A} \\p{a}"), vec![["A", "A", "a", ""]]);
+    }
+
+    #[test]
+    fn math_symbols_and_ignored_tags() {
+        let source = concat!(
+            "\\p{Let \\{\\alpha + \\frac{1}{x}} be.} ",
+            "\\equation{\\int f} \\script{x} \\img[src=a.png]",
+        );
+        assert_eq!(sections(source), vec![["", "Title", "Let be.", "alpha frac int"]]);
+    }
+
+    #[test]
+    fn snippets() {
+        assert_eq!(snippet("short"), "short");
+        let text = "word ".repeat(40);
+        let snippet = snippet(text.trim());
+        assert!(snippet.ends_with("word…"));
+        assert!(snippet.chars().count() <= SNIPPET_LENGTH + 1);
+    }
+
+    #[test]
+    fn search_index() {
+        let settings = Settings::default();
+        let documents = [
+            SearchDocument {
+                href: String::from("a.html"),
+                title: String::from("A \"1\""),
+                sections: index_source("\\p{Groups and rings.}", "A", &settings),
+            },
+            SearchDocument {
+                href: String::from("b/c.html"),
+                title: String::from("C"),
+                sections: index_source("\\h2{Rings} \\p{A ring \\{\\alpha}.}", "C", &settings),
+            },
+        ];
+        assert_eq!(render_search_index(&documents), concat!(
+            "window.SUBSCRIPT_SEARCH_INDEX = {\n",
+            "\"documents\": [\n[\"a.html\", \"A \\\"1\\\"\"],\n[\"b/c.html\", \"C\"]\n],\n",
+            "\"sections\": [\n",
+            "[0, \"\", \"A\", \"Groups and rings.\"],\n",
+            "[1, \"Rings\", \"Rings\", \"A ring .\"]\n",
+            "],\n",
+            "\"terms\": {\n",
+            "\"alpha\": [1],\n\"and\": [0],\n\"groups\": [0],\n\"ring\": [1],\n\"rings\": [0, 1]\n",
+            "}\n};\n",
+        ));
+    }
+}
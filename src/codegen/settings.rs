@@ -0,0 +1,105 @@
+//! Per-document compile settings shared by the codegen backends.
+use std::path::PathBuf;
+use crate::codegen::site::Navigation;
+use crate::frontend::pass::glossary::Entry;
+
+#[derive(Debug, Clone, Default)]
+pub struct Settings {
+    /// Directory of the source file; local asset paths are resolved relative
+    /// to this. When absent, asset paths are left untouched.
+    pub source_dir: Option<PathBuf>,
+    /// Directory the compiled document is written to. Local assets are copied
+    /// into its `assets` subdirectory.
+    pub output_dir: Option<PathBuf>,
+    /// Enables the smart typography pass (curly quotes, dashes, etc.).
+    pub typography: bool,
+    /// The document language (e.g. `en` or `de`); selects the quotation marks.
+    pub language: Option<String>,
+    /// Emits the print stylesheet (page size, margins, running headers &
+    /// page break control); the HTML backend only.
+    pub print: bool,
+    /// Replaces the built-in HTML template; see `assets/template.html` for
+    /// the placeholders.
+    pub template: Option<String>,
+    /// The color theme (see `html::theme_stylesheet`); `light` by default.
+    pub theme: Option<String>,
+    /// Additional CSS, included after the built-in stylesheet.
+    pub stylesheets: Vec<String>,
+    /// Only include the given `stylesheets`, rather than the built-in
+    /// stylesheet & theme.
+    pub replace_stylesheet: bool,
+    /// Additional math macros (for MathJax & MathML).
+    pub math_macros: Vec<MathMacro>,
+    /// Tags allowed in addition to the Subscript & HTML tags (e.g. custom
+    /// elements); other tags are reported (see `pass::tags`).
+    pub allowed_tags: Vec<String>,
+    /// The optional frontend passes.
+    pub passes: Passes,
+    /// What to do with the `\solution` blocks of exercises.
+    pub solutions: Solutions,
+    /// The entries of the appended glossary & index (e.g. of every page of a
+    /// site, see `pass::glossary::rooted`); those of the document itself by
+    /// default.
+    pub glossary: Option<Vec<Entry>>,
+    /// The path from the document to the root of the site (e.g. `../`);
+    /// enables the search widget (see `codegen::search`).
+    pub search_root: Option<String>,
+    /// The previous/next links, sidebar & breadcrumbs of a site page.
+    pub navigation: Option<Navigation>,
+}
+
+/// See `frontend::pass::notes`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Solutions {
+    /// Collapsed below their exercise (i.e. a self-test).
+    #[default]
+    Hidden,
+    /// Removed, e.g. for handouts.
+    Strip,
+    /// Collected into a section at the end of the document.
+    Appendix,
+}
+
+impl Solutions {
+    pub const NAMES: &'static [&'static str] = &["hidden", "strip", "appendix"];
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "hidden" => Some(Solutions::Hidden),
+            "strip" => Some(Solutions::Strip),
+            "appendix" => Some(Solutions::Appendix),
+            _ => None,
+        }
+    }
+}
+
+/// A LaTeX macro, e.g. `\norm{x}` for `\left\| x \right\|`.
+#[derive(Debug, Clone)]
+pub struct MathMacro {
+    pub name: String,
+    /// The number of arguments (i.e. `#1` to `#N` in the body).
+    pub arity: usize,
+    pub body: String,
+}
+
+/// The optional frontend passes; enabled by default, except for the inline
+/// shorthand & paragraph inference (which would change the meaning or output
+/// of existing documents).
+#[derive(Debug, Clone)]
+pub struct Passes {
+    /// The compact `\table` row & column syntax.
+    pub tables: bool,
+    /// The inline shorthand for bold, italic, code & links; opt-in.
+    pub inline_format: bool,
+    /// Paragraph inference (HTML backends only); opt-in.
+    pub paragraphs: bool,
+}
+
+impl Default for Passes {
+    fn default() -> Self {
+        Passes {
+            tables: true,
+            inline_format: false,
+            paragraphs: false,
+        }
+    }
+}
@@ -0,0 +1,379 @@
+//! Multi-document sites, i.e. every source file of some directory compiled
+//! into HTML (mirroring the directory layout), with a shared search index
+//! (see `codegen::search`).
+//!
+//! The pages are ordered by the `order` of the project configuration, and
+//! otherwise by file name (comparing numbers numerically, e.g. `2-groups.txt`
+//! before `10-fields.txt`, with the `index.txt` of each directory first). Each
+//! page links to the previous & next page, lists every page in a sidebar,
+//! and gets breadcrumbs from its directory (see `Navigation`).
+use std::cmp::Ordering;
+use std::path::{Path, PathBuf};
+use crate::frontend::diagnostic::Diagnostic;
+use crate::codegen::html::escape_html;
+
+/// The extension of the source files.
+pub static SOURCE_EXTENSION: &str = "txt";
+
+/// The file stem of the page representing its directory (i.e. in the
+/// breadcrumbs).
+pub static INDEX_FILE_STEM: &str = "index";
+
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub source_path: PathBuf,
+    /// Relative to the root of the site, e.g. `notes/week-1.txt`.
+    pub relative_path: PathBuf,
+}
+
+impl Page {
+    /// The compiled document, relative to the root of the site (e.g.
+    /// `notes/week-1.html`).
+    pub fn href(&self) -> String {
+        self.relative_path
+            .with_extension("html")
+            .components()
+            .map(|x| x.as_os_str().to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+    /// The path from the compiled document to the root of the site (e.g.
+    /// `../`).
+    pub fn root(&self) -> String {
+        let depth = self.relative_path.components().count().saturating_sub(1);
+        "../".repeat(depth)
+    }
+    fn is_index(&self) -> bool {
+        self.relative_path.file_stem().and_then(|x| x.to_str()) == Some(INDEX_FILE_STEM)
+    }
+    /// The directories containing the page, e.g. `notes` & `notes/week-1`.
+    fn directories(&self) -> Vec<PathBuf> {
+        self.relative_path
+            .ancestors()
+            .skip(1)
+            .filter(|x| !x.as_os_str().is_empty())
+            .map(ToOwned::to_owned)
+            .collect::<Vec<_>>()
+            .into_iter()
+            .rev()
+            .collect()
+    }
+}
+
+/// Compares file names, with runs of digits compared numerically.
+fn compare_names(left: &str, right: &str) -> Ordering {
+    fn chunks(name: &str) -> Vec<(bool, &str)> {
+        let mut results = Vec::new();
+        let mut start = 0;
+        for (ix, c) in name.char_indices().skip(1) {
+            let previous = name[..ix].chars().next_back().unwrap();
+            if previous.is_ascii_digit() != c.is_ascii_digit() {
+                results.push(&name[start..ix]);
+                start = ix;
+            }
+        }
+        results.push(&name[start..]);
+        results
+            .into_iter()
+            .map(|x| (x.starts_with(|c: char| c.is_ascii_digit()), x))
+            .collect()
+    }
+    for (left, right) in chunks(left).into_iter().zip(chunks(right)) {
+        let ordering = match (left, right) {
+            ((true, left), (true, right)) => {
+                let left = left.trim_start_matches('0');
+                let right = right.trim_start_matches('0');
+                left.len().cmp(&right.len()).then_with(|| left.cmp(right))
+            }
+            ((_, left), (_, right)) => left.to_lowercase().cmp(&right.to_lowercase()),
+        };
+        if ordering != Ordering::Equal {
+            return ordering
+        }
+    }
+    left.len().cmp(&right.len()).then_with(|| left.cmp(right))
+}
+
+/// The source files under the given directory (in file name order), except
+/// for those under `exclude` (i.e. the output directory).
+pub fn discover_pages(root: &Path, exclude: Option<&Path>) -> Vec<Page> {
+    fn go(dir: &Path, exclude: Option<&Path>, results: &mut Vec<PathBuf>) {
+        let mut entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries.filter_map(Result::ok).map(|x| x.path()).collect::<Vec<_>>(),
+            Err(_) => return,
+        };
+        entries.sort_by(|left, right| {
+            let is_index = |x: &Path| {
+                x.is_file() && x.file_stem().and_then(|x| x.to_str()) == Some(INDEX_FILE_STEM)
+            };
+            let name = |x: &Path| x.file_name().map(|x| x.to_string_lossy().into_owned()).unwrap_or_default();
+            is_index(right)
+                .cmp(&is_index(left))
+                .then_with(|| compare_names(&name(left), &name(right)))
+        });
+        for path in entries {
+            if exclude.map(|x| path.canonicalize().ok().as_deref() == Some(x)).unwrap_or(false) {
+                continue;
+            }
+            if path.is_dir() {
+                go(&path, exclude, results);
+            } else if path.extension().and_then(|x| x.to_str()) == Some(SOURCE_EXTENSION) {
+                results.push(path);
+            }
+        }
+    }
+    let exclude = exclude.and_then(|x| x.canonicalize().ok());
+    let mut results = Vec::new();
+    go(root, exclude.as_deref(), &mut results);
+    results
+        .into_iter()
+        .map(|source_path| {
+            let relative_path = source_path
+                .strip_prefix(root)
+                .map(ToOwned::to_owned)
+                .unwrap_or_else(|_| source_path.clone());
+            Page {source_path, relative_path}
+        })
+        .collect()
+}
+
+/// Moves the pages listed in `order` (i.e. source paths) to the front, in
+/// the given order; reports the entries that aren’t pages of the site.
+pub fn order_pages(pages: Vec<Page>, order: &[PathBuf]) -> (Vec<Page>, Vec<Diagnostic>) {
+    let canonical = |x: &Path| x.canonicalize().unwrap_or_else(|_| x.to_owned());
+    let mut rest = pages;
+    let mut results = Vec::new();
+    let mut diagnostics = Vec::new();
+    for path in order {
+        let path = canonical(path);
+        match rest.iter().position(|x| canonical(&x.source_path) == path) {
+            Some(ix) => results.push(rest.remove(ix)),
+            None => {
+                let message = format!("`order` entry {} isn’t a page of the site; ignored", path.display());
+                diagnostics.push(Diagnostic::warning(None, message));
+            }
+        }
+    }
+    results.extend(rest);
+    (results, diagnostics)
+}
+
+#[derive(Debug, Clone)]
+pub struct Link {
+    /// Relative to the current page; `None` for directories without an
+    /// index page.
+    pub href: Option<String>,
+    pub title: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct SidebarEntry {
+    pub link: Link,
+    /// The directory depth, where the index page of a directory has the depth
+    /// of the directory itself.
+    pub depth: usize,
+    pub current: bool,
+}
+
+/// The links between the pages of a site, rendered into the template (see
+/// `assets/template.html`).
+#[derive(Debug, Clone, Default)]
+pub struct Navigation {
+    pub previous: Option<Link>,
+    pub next: Option<Link>,
+    /// From the root of the site to the current page (without a link).
+    pub breadcrumbs: Vec<Link>,
+    /// Every page, in site order.
+    pub sidebar: Vec<SidebarEntry>,
+}
+
+impl Navigation {
+    /// The navigation of `pages[current]`; `titles` are the titles of the
+    /// pages.
+    pub fn new(pages: &[Page], titles: &[String], current: usize) -> Navigation {
+        let page = &pages[current];
+        let link = |ix: usize| Link {
+            href: Some(format!("{}{}", page.root(), pages[ix].href())),
+            title: titles[ix].clone(),
+        };
+        // BREADCRUMBS
+        let index_of = |dir: &Path| pages
+            .iter()
+            .position(|x| x.is_index() && x.relative_path.parent() == Some(dir));
+        let mut directories = page.directories();
+        if page.is_index() {
+            directories.pop();
+        }
+        let mut breadcrumbs = Vec::new();
+        // THE ROOT, UNLESS THIS IS ITS INDEX PAGE
+        if !(page.is_index() && page.directories().is_empty()) {
+            breadcrumbs.push(index_of(Path::new("")).map(link).unwrap_or_else(|| Link {
+                href: None,
+                title: String::from("Home"),
+            }));
+        }
+        for dir in directories {
+            breadcrumbs.push(index_of(&dir).map(link).unwrap_or_else(|| Link {
+                href: None,
+                title: dir
+                    .file_name()
+                    .map(|x| x.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+            }));
+        }
+        breadcrumbs.push(Link {href: None, title: titles[current].clone()});
+        // SIDEBAR
+        let sidebar = pages
+            .iter()
+            .enumerate()
+            .map(|(ix, x)| SidebarEntry {
+                link: link(ix),
+                depth: x.directories().len() - (x.is_index() && !x.directories().is_empty()) as usize,
+                current: ix == current,
+            })
+            .collect();
+        Navigation {
+            previous: current.checked_sub(1).map(link),
+            next: Some(current + 1).filter(|x| *x < pages.len()).map(link),
+            breadcrumbs,
+            sidebar,
+        }
+    }
+    fn render_link(link: &Link) -> String {
+        match link.href.as_ref() {
+            Some(href) => format!("<a href=\"{}\">{}</a>", escape_html(href), escape_html(&link.title)),
+            None => format!("<span>{}</span>", escape_html(&link.title)),
+        }
+    }
+    pub fn render_breadcrumbs(&self) -> String {
+        let links = self.breadcrumbs
+            .iter()
+            .map(Navigation::render_link)
+            .collect::<Vec<_>>()
+            .join(" › ");
+        format!("<nav id=\"breadcrumbs\">{}</nav>", links)
+    }
+    pub fn render_sidebar(&self) -> String {
+        let entries = self.sidebar
+            .iter()
+            .map(|x| format!(
+                "<li depth=\"{}\"{}>{}</li>",
+                x.depth,
+                if x.current {" aria-current=\"page\""} else {""},
+                Navigation::render_link(&x.link),
+            ))
+            .collect::<Vec<_>>()
+            .join("\n");
+        format!("<nav id=\"sidebar\"><ul>\n{}\n</ul></nav>", entries)
+    }
+    /// The previous & next links.
+    pub fn render_pagination(&self) -> String {
+        let previous = self.previous
+            .as_ref()
+            .map(|x| format!("<a rel=\"prev\" href=\"{}\">← {}</a>", escape_html(x.href.as_deref().unwrap_or_default()), escape_html(&x.title)))
+            .unwrap_or_default();
+        let next = self.next
+            .as_ref()
+            .map(|x| format!("<a rel=\"next\" href=\"{}\">{} →</a>", escape_html(x.href.as_deref().unwrap_or_default()), escape_html(&x.title)))
+            .unwrap_or_default();
+        format!("<nav id=\"pagination\">{}{}</nav>", previous, next)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::scratch_dir;
+
+    fn page(path: &str) -> Page {
+        Page {source_path: PathBuf::from(path), relative_path: PathBuf::from(path)}
+    }
+
+    #[test]
+    fn hrefs() {
+        assert_eq!(page("index.txt").href(), "index.html");
+        assert_eq!(page("notes/week-1.txt").href(), "notes/week-1.html");
+        assert_eq!(page("index.txt").root(), "");
+        assert_eq!(page("a/b/c.txt").root(), "../../");
+    }
+
+    #[test]
+    fn names_with_numbers() {
+        let mut names = vec!["10-fields", "2-groups", "Appendix", "02-rings", "a", "1"];
+        names.sort_by(|x, y| compare_names(x, y));
+        assert_eq!(names, vec!["1", "2-groups", "02-rings", "10-fields", "a", "Appendix"]);
+    }
+
+    #[test]
+    fn discovery_and_order() {
+        let dir = scratch_dir("site", "discovery");
+        let paths = [
+            "b.txt", "index.txt", "10.txt", "2.txt", "notes/x.txt", "notes/index.txt", "out/y.txt",
+            "z.md",
+        ];
+        for path in paths {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let pages = discover_pages(&dir, Some(&dir.join("out")));
+        let paths = |pages: &[Page]| pages.iter().map(Page::href).collect::<Vec<_>>();
+        assert_eq!(paths(&pages), vec![
+            "index.html", "2.html", "10.html", "b.html", "notes/index.html", "notes/x.html",
+        ]);
+        let order = [dir.join("notes/x.txt"), dir.join("b.txt"), dir.join("missing.txt")];
+        let (pages, diagnostics) = order_pages(pages, &order);
+        assert_eq!(paths(&pages), vec![
+            "notes/x.html", "b.html", "index.html", "2.html", "10.html", "notes/index.html",
+        ]);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].message.contains("missing.txt"));
+    }
+
+    #[test]
+    fn navigation() {
+        let pages = [
+            page("index.txt"),
+            page("a.txt"),
+            page("notes/index.txt"),
+            page("notes/b.txt"),
+            page("other/c.txt"),
+        ];
+        let titles = ["Home", "A", "Notes", "B", "C"]
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<_>>();
+        let navigation = Navigation::new(&pages, &titles, 3);
+        assert_eq!(navigation.render_breadcrumbs(), concat!(
+            "<nav id=\"breadcrumbs\"><a href=\"../index.html\">Home</a> › ",
+            "<a href=\"../notes/index.html\">Notes</a> › <span>B</span></nav>",
+        ));
+        assert_eq!(navigation.render_sidebar(), concat!(
+            "<nav id=\"sidebar\"><ul>\n",
+            "<li depth=\"0\"><a href=\"../index.html\">Home</a></li>\n",
+            "<li depth=\"0\"><a href=\"../a.html\">A</a></li>\n",
+            "<li depth=\"0\"><a href=\"../notes/index.html\">Notes</a></li>\n",
+            "<li depth=\"1\" aria-current=\"page\"><a href=\"../notes/b.html\">B</a></li>\n",
+            "<li depth=\"1\"><a href=\"../other/c.html\">C</a></li>\n",
+            "</ul></nav>",
+        ));
+        assert_eq!(navigation.render_pagination(), concat!(
+            "<nav id=\"pagination\">",
+            "<a rel=\"prev\" href=\"../notes/index.html\">← Notes</a>",
+            "<a rel=\"next\" href=\"../other/c.html\">C →</a>",
+            "</nav>",
+        ));
+        // Directories without an index page aren’t linked.
+        let navigation = Navigation::new(&pages, &titles, 4);
+        assert_eq!(navigation.render_breadcrumbs(), concat!(
+            "<nav id=\"breadcrumbs\"><a href=\"../index.html\">Home</a> › ",
+            "<span>other</span> › <span>C</span></nav>",
+        ));
+        assert_eq!(
+            navigation.render_pagination(),
+            "<nav id=\"pagination\"><a rel=\"prev\" href=\"../notes/b.html\">← B</a></nav>",
+        );
+        let navigation = Navigation::new(&pages, &titles, 0);
+        assert_eq!(navigation.render_breadcrumbs(), "<nav id=\"breadcrumbs\"><span>Home</span></nav>");
+    }
+}
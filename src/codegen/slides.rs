@@ -0,0 +1,238 @@
+//! Slideshow rendering.
+//!
+//! Slides are either explicit (`\slide{...}`) or delimited by the top level
+//! `\h1` & `\h2` headings. Speaker notes (`\note[speaker]{...}`) are moved out
+//! of the slide body, and the items of `incremental` lists (or of every list
+//! within an `\slide[incremental]`) are revealed one at a time. Math & layout
+//! work as in the document template.
+use std::cell::RefCell;
+use std::rc::Rc;
+use crate::frontend::ast::*;
+use crate::frontend::diagnostic::Diagnostic;
+use crate::codegen::html;
+use crate::codegen::settings::Settings;
+
+/// The parameter for revealing list items one at a time.
+pub static INCREMENTAL_PARAMETER: &str = "incremental";
+
+#[derive(Debug, Clone)]
+pub struct Slide<'a> {
+    pub body: Vec<html::Node<'a>>,
+    pub notes: Vec<html::Node<'a>>,
+}
+
+/// Render the entire slideshow.
+#[derive(Debug, Clone)]
+pub struct Document<'a> {
+    pub title: String,
+    pub slides: Vec<Slide<'a>>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The stylesheets, math macros, etc.
+    pub settings: Settings,
+}
+
+fn is_macro(tag: &Tag, name: &str) -> bool {
+    tag.get_parameter_value("macro")
+        .map(|x| x.trim_matches('\'') == name)
+        .unwrap_or(false)
+}
+
+fn new_slide<'a>(children: Vec<Node<'a>>) -> Tag<'a> {
+    let mut slide = Tag::new(Ann::unannotated("section"), children);
+    slide.insert_unannotated_parameter("macro=slide");
+    slide
+}
+
+/// Explicit slides, and slides delimited by the top level headings.
+fn split_slides<'a>(nodes: Vec<Node<'a>>) -> Vec<Tag<'a>> {
+    let mut slides = Vec::new();
+    let mut current: Vec<Node<'a>> = Vec::new();
+    let flush = |current: &mut Vec<Node<'a>>, slides: &mut Vec<Tag<'a>>| {
+        if current.iter().any(|x| !x.is_whitespace()) {
+            slides.push(new_slide(std::mem::take(current)));
+        }
+        current.clear();
+    };
+    for node in nodes.into_iter().flat_map(Node::into_fragment) {
+        match node {
+            Node::Tag(tag) if tag.has_name("section") && is_macro(&tag, "slide") => {
+                flush(&mut current, &mut slides);
+                slides.push(tag);
+            }
+            Node::Tag(tag) if tag.has_name("h1") || tag.has_name("h2") => {
+                flush(&mut current, &mut slides);
+                current.push(Node::Tag(tag));
+            }
+            node => current.push(node),
+        }
+    }
+    flush(&mut current, &mut slides);
+    slides
+}
+
+/// Removes the speaker notes from the slide, and marks the incremental list
+/// items.
+fn process_slide<'a>(slide: Tag<'a>) -> (Node<'a>, Vec<Node<'a>>) {
+    let is_incremental = slide.has_parameter(INCREMENTAL_PARAMETER);
+    let mut notes = Vec::new();
+    let f = |_: NodeEnvironment<'a>, node: Node<'a>| -> Node<'a> {
+        match node {
+            Node::Tag(tag) if is_macro(&tag, "note") && tag.has_parameter("speaker") => {
+                notes.extend(tag.children);
+                Node::new_fragment(Vec::new())
+            }
+            Node::Tag(mut tag) if tag.has_name("ul") || tag.has_name("ol") => {
+                if is_incremental || tag.has_parameter(INCREMENTAL_PARAMETER) {
+                    for child in tag.children.iter_mut() {
+                        match child.unwrap_tag_mut() {
+                            Some(item) if item.has_name("li") => {
+                                item.insert_unannotated_parameter("fragment");
+                            }
+                            _ => (),
+                        }
+                    }
+                }
+                Node::Tag(tag)
+            }
+            node => node,
+        }
+    };
+    let body = Node::Tag(slide).transform_mut(NodeEnvironment::default(), Rc::new(RefCell::new(f)));
+    (body, notes)
+}
+
+impl<'a> Document<'a> {
+    pub fn from_source(source: &'a str) -> Document<'a> {
+        Document::from_source_with(source, &Settings::default())
+    }
+    pub fn from_source_with(source: &'a str, settings: &Settings) -> Document<'a> {
+        crate::compiler::Compiler::new()
+            .settings(settings.clone())
+            .compile_slides(source)
+    }
+    /// Renders the output of the passes (see `compiler::Compiler::run`).
+    pub fn from_nodes(body: Vec<Node<'a>>, diagnostics: Vec<Diagnostic>, settings: &Settings) -> Document<'a> {
+        let title = crate::frontend::pass::html_normalize::document_title(&body)
+            .unwrap_or_else(|| String::from("Subscript"));
+        let slides = split_slides(body)
+            .into_iter()
+            .map(process_slide)
+            .map(|(body, notes)| {
+                let body = crate::frontend::pass::html_normalize::annotate_heading_nodes(body);
+                Slide {
+                    body: body
+                        .into_tag()
+                        .map(|x| x.children)
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(crate::frontend::pass::to_html::node_to_html)
+                        .collect(),
+                    notes: notes
+                        .into_iter()
+                        .map(crate::frontend::pass::to_html::node_to_html)
+                        .collect(),
+                }
+            })
+            .collect::<Vec<_>>();
+        Document{title, slides, diagnostics, settings: settings.clone()}
+    }
+    pub fn render_to_string(self) -> String {
+        let render = |nodes: Vec<html::Node>| -> String {
+            nodes
+                .into_iter()
+                .map(html::Node::to_html_str)
+                .map(|x| x.0)
+                .collect::<Vec<_>>()
+                .join("")
+        };
+        let slides = self.slides
+            .into_iter()
+            .enumerate()
+            .map(|(ix, slide)| {
+                let notes = if slide.notes.is_empty() {
+                    String::new()
+                } else {
+                    format!("<aside macro=\"speaker-notes\">{}</aside>", render(slide.notes))
+                };
+                format!(
+                    "<section macro=\"slide\" id=\"slide-{}\">{}{}</section>",
+                    ix + 1,
+                    render(slide.body),
+                    notes,
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        String::from(include_str!("../../assets/slides.html"))
+            .replace("<!--{{title}}-->", &html::escape_html(&self.title))
+            .replace("<!--{{deps}}-->", &html::dependencies(&self.settings))
+            .replace("/*{{css}}*/", &html::stylesheet(&self.settings))
+            .replace("/*{{slides-css}}*/", include_str!("../../assets/slides.css"))
+            .replace("/*{{slides-js}}*/", include_str!("../../assets/slides.js"))
+            .replace("<!--{{slides}}-->", &slides)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The body & speaker notes of each slide.
+    fn slides(source: &str) -> (Vec<String>, Vec<String>) {
+        let render = |nodes: Vec<html::Node>| -> String {
+            nodes
+                .into_iter()
+                .map(|x| x.to_html_str().0)
+                .collect::<String>()
+        };
+        Document::from_source(source)
+            .slides
+            .into_iter()
+            .map(|x| (render(x.body), render(x.notes)))
+            .unzip()
+    }
+
+    #[test]
+    fn headings_delimit_slides() {
+        let (bodies, _) = slides("\\h1{A} \\p{a} \\h2{B} \\h3{C} \\p{b}");
+        assert_eq!(bodies, vec![
+            "<h1 id=\"A\">A</h1> <p>a</p> ",
+            "<h2 id=\"B\">B</h2> <h3 id=\"C\">C</h3> <p>b</p>",
+        ]);
+    }
+
+    #[test]
+    fn explicit_slides() {
+        let (bodies, _) = slides("\\p{intro} \\slide{\\p{x}} \\slide{\\p{y}}");
+        assert_eq!(bodies, vec!["<p>intro</p> ", "<p>x</p>", "<p>y</p>"]);
+    }
+
+    #[test]
+    fn speaker_notes() {
+        let (bodies, notes) = slides("\\h1{A} \\note[speaker]{Say hi.} \\note{Shown.}");
+        assert_eq!(bodies, vec![
+            "<h1 id=\"A\">A</h1>  <div macro=\"note\">Shown.</div>",
+        ]);
+        assert_eq!(notes, vec!["Say hi."]);
+    }
+
+    #[test]
+    fn incremental_lists() {
+        let (bodies, _) = slides("\\h1{A} \\ul[incremental]{\\li{a}\\li{b}} \\ul{\\li{c}}");
+        assert_eq!(bodies, vec![concat!(
+            "<h1 id=\"A\">A</h1> ",
+            "<ul incremental=\"\"><li fragment=\"\">a</li><li fragment=\"\">b</li></ul> ",
+            "<ul><li>c</li></ul>",
+        )]);
+        let (bodies, _) = slides("\\slide[incremental]{\\ol{\\li{a}}}");
+        assert_eq!(bodies, vec!["<ol><li fragment=\"\">a</li></ol>"]);
+    }
+
+    #[test]
+    fn document() {
+        let output = Document::from_source("\\h1{Deck & more} \\h2{Next}").render_to_string();
+        assert!(output.contains("<title>Deck &amp; more</title>"));
+        assert!(output.contains("<section macro=\"slide\" id=\"slide-1\"><h1 "));
+        assert!(output.contains("<section macro=\"slide\" id=\"slide-2\">"));
+    }
+}
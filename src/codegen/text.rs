@@ -0,0 +1,72 @@
+//! Plain text rendering.
+//!
+//! Shares the block layout of the Markdown backend (see `codegen::markdown`),
+//! without any markup; math is printed as its LaTeX source.
+use crate::frontend::diagnostic::Diagnostic;
+use crate::codegen::settings::Settings;
+use crate::codegen::markdown::Renderer;
+use crate::compiler::Compiler;
+use crate::frontend::ast::Node;
+
+/// Render the entire document.
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub body: Vec<String>,
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+impl Document {
+    pub fn from_source(source: &str) -> Document {
+        Document::from_source_with(source, &Settings::default())
+    }
+    pub fn from_source_with(source: &str, settings: &Settings) -> Document {
+        let (nodes, diagnostics) = Compiler::markdown()
+            .settings(settings.clone())
+            .run(source);
+        Document::from_nodes(&nodes, diagnostics)
+    }
+    /// Renders the output of `Compiler::markdown`.
+    pub fn from_nodes(nodes: &[Node], diagnostics: Vec<Diagnostic>) -> Document {
+        let body = Renderer {plain: true}.blocks(nodes);
+        Document {body, diagnostics}
+    }
+    pub fn render_to_string(self) -> String {
+        let mut output = self.body.join("\n\n");
+        output.push('\n');
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str) -> String {
+        Document::from_source(source).render_to_string()
+    }
+
+    #[test]
+    fn no_markup() {
+        assert_eq!(
+            render("\\h1{Title} \\p{Some \\b{a*b} and \\{x^2}, see \\a[href=https://a.org]{x}.} \\note{N}"),
+            "Title\n=====\n\nSome a*b and x^2, see x (https://a.org).\n\nN\n",
+        );
+    }
+
+    #[test]
+    fn lists_and_tables() {
+        assert_eq!(
+            render("\\list{- a\n- b} \\table[header]{A & B \\\\ 1 & 2}"),
+            "- a\n- b\n\nA    B\n---  ---\n1    2\n",
+        );
+    }
+
+    #[test]
+    fn page_breaks() {
+        let source = "\\p{a} \\pagebreak \\p{b}";
+        assert_eq!(render(source), "a\n\n\u{c}\n\nb\n");
+        // Markdown has no pages.
+        let markdown = crate::codegen::markdown::Document::from_source(source);
+        assert_eq!(markdown.render_to_string(), "a\n\nb\n");
+    }
+}
@@ -0,0 +1,593 @@
+//! Library entrypoint, i.e. the HTML pipeline as a sequence of named passes.
+//!
+//! ```ignore
+//! use subscript_compiler::compiler::{Compiler, FnPass};
+//!
+//! let document = Compiler::new()
+//!     .settings(settings)
+//!     .disable("typography")
+//!     .pass(FnPass::new("shout", |nodes, _| shout(nodes)).after("notes").before("paragraphs"))
+//!     .inspect(|stage, nodes| eprintln!("after {}: {} nodes", stage, nodes.len()))
+//!     .compile(source);
+//! ```
+//!
+//! The source is parsed (the `parse` stage), run through the passes (see
+//! `BUILTIN_PASSES`), and rendered (i.e. the table of contents, heading ids &
+//! HTML; see `html::Document::from_nodes`), or into slides (see
+//! `compile_slides`). The EPUB backend starts from `Compiler::epub` (see
+//! `EPUB_PASSES` & `compile_chapter`), and the Markdown & plain text backends
+//! from `Compiler::markdown` (see `MARKDOWN_PASSES`).
+//! Passes are ordered by their `after` & `before` constraints, and otherwise
+//! kept in the order they were added (the built-in passes first, in the given
+//! order).
+use crate::frontend::ast::*;
+use crate::frontend::diagnostic::Diagnostic;
+use crate::codegen::html::Document;
+use crate::codegen::settings::Settings;
+use crate::frontend::pass::glossary::Entry;
+
+/// The state shared by the passes of a compilation.
+pub struct PassContext<'s> {
+    pub settings: &'s Settings,
+    pub diagnostics: Vec<Diagnostic>,
+    /// The `\term` & `\index` occurrences (see the `terms` pass).
+    pub terms: Vec<Entry>,
+}
+
+/// An AST to AST transformation.
+pub trait Pass {
+    /// Unique; registering a pass with the name of another replaces it.
+    fn name(&self) -> &str;
+    /// The passes this pass runs after (if enabled).
+    fn after(&self) -> Vec<&str> {
+        Vec::new()
+    }
+    /// The passes this pass runs before (if enabled).
+    fn before(&self) -> Vec<&str> {
+        Vec::new()
+    }
+    /// E.g. depending on `Settings::passes`.
+    fn enabled(&self, _settings: &Settings) -> bool {
+        true
+    }
+    fn run<'a>(&self, nodes: Vec<Node<'a>>, context: &mut PassContext) -> Vec<Node<'a>>;
+}
+
+/// A pass of the compiler itself.
+#[derive(Clone, Copy)]
+pub struct BuiltinPass {
+    pub name: &'static str,
+    enabled: fn(&Settings) -> bool,
+    run: for<'a> fn(Vec<Node<'a>>, &mut PassContext) -> Vec<Node<'a>>,
+}
+
+impl Pass for BuiltinPass {
+    fn name(&self) -> &str {
+        self.name
+    }
+    fn enabled(&self, settings: &Settings) -> bool {
+        (self.enabled)(settings)
+    }
+    fn run<'a>(&self, nodes: Vec<Node<'a>>, context: &mut PassContext) -> Vec<Node<'a>> {
+        (self.run)(nodes, context)
+    }
+}
+
+fn always(_: &Settings) -> bool {
+    true
+}
+
+fn tags<'a>(nodes: Vec<Node<'a>>, context: &mut PassContext) -> Vec<Node<'a>> {
+    let diagnostics = crate::frontend::pass::tags::unknown_tags(&nodes, context.settings);
+    context.diagnostics.extend(diagnostics);
+    nodes
+}
+fn terms<'a>(nodes: Vec<Node<'a>>, context: &mut PassContext) -> Vec<Node<'a>> {
+    crate::frontend::pass::glossary::term_pass(nodes, context.settings, &mut context.terms)
+}
+fn glossary<'a>(nodes: Vec<Node<'a>>, context: &mut PassContext) -> Vec<Node<'a>> {
+    let entries = context.settings.glossary.as_ref().unwrap_or(&context.terms);
+    crate::frontend::pass::glossary::glossary_pass(nodes, entries)
+}
+fn tables<'a>(nodes: Vec<Node<'a>>, _: &mut PassContext) -> Vec<Node<'a>> {
+    crate::frontend::pass::table::table_pass(nodes)
+}
+fn inline_format<'a>(nodes: Vec<Node<'a>>, _: &mut PassContext) -> Vec<Node<'a>> {
+    crate::frontend::pass::inline_format::inline_format_pass(nodes)
+}
+fn typography<'a>(nodes: Vec<Node<'a>>, context: &mut PassContext) -> Vec<Node<'a>> {
+    crate::frontend::pass::typography::typography_pass(nodes, context.settings)
+}
+fn notes<'a>(nodes: Vec<Node<'a>>, context: &mut PassContext) -> Vec<Node<'a>> {
+    crate::frontend::pass::notes::notes_pass(nodes, context.settings)
+}
+fn code<'a>(nodes: Vec<Node<'a>>, _: &mut PassContext) -> Vec<Node<'a>> {
+    nodes.into_iter().map(crate::frontend::pass::code::code_pass).collect()
+}
+fn html_tags<'a>(nodes: Vec<Node<'a>>, _: &mut PassContext) -> Vec<Node<'a>> {
+    let nodes = nodes
+        .into_iter()
+        .map(crate::frontend::pass::html_normalize::node_passes)
+        .collect();
+    crate::frontend::pass::html_normalize::child_list_passes(nodes)
+}
+fn lists<'a>(nodes: Vec<Node<'a>>, _: &mut PassContext) -> Vec<Node<'a>> {
+    nodes.into_iter().map(crate::frontend::pass::list::list_pass).collect()
+}
+fn paragraphs<'a>(nodes: Vec<Node<'a>>, _: &mut PassContext) -> Vec<Node<'a>> {
+    crate::frontend::pass::paragraph::paragraph_pass(nodes)
+}
+fn markdown_tags<'a>(nodes: Vec<Node<'a>>, _: &mut PassContext) -> Vec<Node<'a>> {
+    crate::codegen::markdown::tag_pass(nodes)
+}
+fn images<'a>(nodes: Vec<Node<'a>>, context: &mut PassContext) -> Vec<Node<'a>> {
+    let settings = context.settings;
+    let diagnostics = &mut context.diagnostics;
+    nodes
+        .into_iter()
+        .map(|x| crate::frontend::pass::images::resolve_images(x, settings, diagnostics))
+        .collect()
+}
+fn layout<'a>(nodes: Vec<Node<'a>>, context: &mut PassContext) -> Vec<Node<'a>> {
+    nodes
+        .into_iter()
+        .map(|x| crate::frontend::pass::layout::layout_pass(x, &mut context.diagnostics))
+        .collect()
+}
+fn math<'a>(nodes: Vec<Node<'a>>, _: &mut PassContext) -> Vec<Node<'a>> {
+    nodes.into_iter().map(crate::frontend::pass::math::latex_pass).collect()
+}
+fn embedded_images<'a>(nodes: Vec<Node<'a>>, context: &mut PassContext) -> Vec<Node<'a>> {
+    // EMBEDDED INTO THE ARCHIVE (SEE `epub::Book::embed_images`), RATHER THAN COPIED
+    let settings = Settings {output_dir: None, ..context.settings.clone()};
+    let diagnostics = &mut context.diagnostics;
+    nodes
+        .into_iter()
+        .map(|x| crate::frontend::pass::images::resolve_images(x, &settings, diagnostics))
+        .collect()
+}
+fn mathml<'a>(nodes: Vec<Node<'a>>, context: &mut PassContext) -> Vec<Node<'a>> {
+    let macros = &context.settings.math_macros;
+    nodes
+        .into_iter()
+        .map(|x| crate::frontend::pass::math::mathml_pass(x, macros))
+        .collect()
+}
+
+const TAGS: BuiltinPass = BuiltinPass {name: "tags", enabled: always, run: tags};
+const TERMS: BuiltinPass = BuiltinPass {name: "terms", enabled: always, run: terms};
+const GLOSSARY: BuiltinPass = BuiltinPass {name: "glossary", enabled: always, run: glossary};
+const TABLES: BuiltinPass = BuiltinPass {name: "tables", enabled: |x| x.passes.tables, run: tables};
+const INLINE_FORMAT: BuiltinPass = BuiltinPass {name: "inline-format", enabled: |x| x.passes.inline_format, run: inline_format};
+const TYPOGRAPHY: BuiltinPass = BuiltinPass {name: "typography", enabled: |x| x.typography, run: typography};
+const NOTES: BuiltinPass = BuiltinPass {name: "notes", enabled: always, run: notes};
+const LISTS: BuiltinPass = BuiltinPass {name: "lists", enabled: always, run: lists};
+const LAYOUT: BuiltinPass = BuiltinPass {name: "layout", enabled: always, run: layout};
+
+/// The passes shared by the HTML based backends (and the search index), in
+/// order.
+pub static CANONICALIZATION_PASSES: &[BuiltinPass] = &[
+    TAGS,
+    TERMS,
+    GLOSSARY,
+    TABLES,
+    INLINE_FORMAT,
+    TYPOGRAPHY,
+    NOTES,
+    BuiltinPass {name: "code", enabled: always, run: code},
+    BuiltinPass {name: "html-tags", enabled: always, run: html_tags},
+    LISTS,
+    BuiltinPass {name: "paragraphs", enabled: |x| x.passes.paragraphs, run: paragraphs},
+];
+
+/// The passes of the HTML & slides backends, following
+/// `CANONICALIZATION_PASSES`.
+pub static BUILTIN_PASSES: &[BuiltinPass] = &[
+    BuiltinPass {name: "images", enabled: always, run: images},
+    LAYOUT,
+    BuiltinPass {name: "math", enabled: always, run: math},
+];
+
+/// The passes of the EPUB backend, following `CANONICALIZATION_PASSES`; the
+/// images are embedded into the archive, and math is rendered as MathML.
+pub static EPUB_PASSES: &[BuiltinPass] = &[
+    BuiltinPass {name: "images", enabled: always, run: embedded_images},
+    LAYOUT,
+    BuiltinPass {name: "math", enabled: always, run: mathml},
+];
+
+/// The passes of the Markdown & plain text backends, i.e. the backend
+/// agnostic subset of `CANONICALIZATION_PASSES` (which leaves math & code
+/// listings as is), in order.
+pub static MARKDOWN_PASSES: &[BuiltinPass] = &[
+    TAGS,
+    TERMS,
+    GLOSSARY,
+    TABLES,
+    INLINE_FORMAT,
+    TYPOGRAPHY,
+    NOTES,
+    BuiltinPass {name: "markdown-tags", enabled: always, run: markdown_tags},
+    LISTS,
+];
+
+fn without_glossary() -> impl Iterator<Item=&'static BuiltinPass> {
+    CANONICALIZATION_PASSES.iter().filter(|x| x.name != GLOSSARY.name)
+}
+
+type PassFn = Box<dyn for<'a> Fn(Vec<Node<'a>>, &mut PassContext) -> Vec<Node<'a>>>;
+
+/// A pass from a closure.
+pub struct FnPass {
+    name: String,
+    after: Vec<String>,
+    before: Vec<String>,
+    run: PassFn,
+}
+
+impl FnPass {
+    pub fn new(
+        name: &str,
+        run: impl for<'a> Fn(Vec<Node<'a>>, &mut PassContext) -> Vec<Node<'a>> + 'static,
+    ) -> FnPass {
+        FnPass {
+            name: name.to_owned(),
+            after: Vec::new(),
+            before: Vec::new(),
+            run: Box::new(run),
+        }
+    }
+    pub fn after(mut self, name: &str) -> FnPass {
+        self.after.push(name.to_owned());
+        self
+    }
+    pub fn before(mut self, name: &str) -> FnPass {
+        self.before.push(name.to_owned());
+        self
+    }
+}
+
+impl Pass for FnPass {
+    fn name(&self) -> &str {
+        &self.name
+    }
+    fn after(&self) -> Vec<&str> {
+        self.after.iter().map(String::as_str).collect()
+    }
+    fn before(&self) -> Vec<&str> {
+        self.before.iter().map(String::as_str).collect()
+    }
+    fn run<'a>(&self, nodes: Vec<Node<'a>>, context: &mut PassContext) -> Vec<Node<'a>> {
+        (self.run)(nodes, context)
+    }
+}
+
+struct Registered {
+    pass: Box<dyn Pass>,
+    /// The built-in passes (and their replacements) are implicitly ordered
+    /// after one another.
+    builtin: bool,
+}
+
+/// The name of the stage before the first pass, i.e. the parsed source.
+pub static PARSE_STAGE: &str = "parse";
+
+type Inspector = Box<dyn Fn(&str, &[Node])>;
+
+pub struct Compiler {
+    settings: Settings,
+    /// The built-in passes come first, so that the custom passes default to
+    /// running after them.
+    passes: Vec<Registered>,
+    disabled: Vec<String>,
+    inspectors: Vec<Inspector>,
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler::new()
+    }
+}
+
+impl Compiler {
+    /// The built-in passes of the HTML backend, with the default settings.
+    pub fn new() -> Compiler {
+        Compiler::with_builtins(CANONICALIZATION_PASSES.iter().chain(BUILTIN_PASSES))
+    }
+    /// The built-in passes of the Markdown & plain text backends (see
+    /// `markdown::Document::from_nodes`), with the default settings.
+    pub fn markdown() -> Compiler {
+        Compiler::with_builtins(MARKDOWN_PASSES.iter())
+    }
+    /// The built-in passes of the EPUB backend (see `compile_chapter`), with
+    /// the default settings. The glossary is a chapter of its own (see
+    /// `epub::Book::add_glossary`).
+    pub fn epub() -> Compiler {
+        Compiler::with_builtins(without_glossary().chain(EPUB_PASSES))
+    }
+    /// The built-in passes of the search index (see `search::index_source`),
+    /// i.e. leaving math & the glossary out, with the default settings.
+    pub fn search() -> Compiler {
+        Compiler::with_builtins(without_glossary())
+    }
+    fn with_builtins<'p>(builtins: impl Iterator<Item=&'p BuiltinPass>) -> Compiler {
+        let passes = builtins
+            .map(|x| Registered {pass: Box::new(*x), builtin: true})
+            .collect();
+        Compiler {
+            settings: Settings::default(),
+            passes,
+            disabled: Vec::new(),
+            inspectors: Vec::new(),
+        }
+    }
+    pub fn settings(mut self, settings: Settings) -> Compiler {
+        self.settings = settings;
+        self
+    }
+    /// Adds the given pass, or replaces the pass of the same name (keeping
+    /// its position, if it’s a built-in pass).
+    pub fn pass(mut self, pass: impl Pass + 'static) -> Compiler {
+        let existing = self.passes
+            .iter()
+            .position(|x| x.pass.name() == pass.name());
+        match existing {
+            Some(ix) => self.passes[ix].pass = Box::new(pass),
+            None => self.passes.push(Registered {pass: Box::new(pass), builtin: false}),
+        }
+        self
+    }
+    /// Disables the pass of the given name (e.g. `typography`).
+    pub fn disable(mut self, name: &str) -> Compiler {
+        self.disabled.push(name.to_owned());
+        self
+    }
+    /// Calls the given function with the name & tree of every stage, i.e.
+    /// `PARSE_STAGE` and then after every pass.
+    pub fn inspect(mut self, f: impl Fn(&str, &[Node]) + 'static) -> Compiler {
+        self.inspectors.push(Box::new(f));
+        self
+    }
+    /// The enabled passes, in order; reports unknown pass names & cyclic
+    /// ordering constraints (in which case the passes involved run in the
+    /// order they were added).
+    fn ordered(&self) -> (Vec<&dyn Pass>, Vec<Diagnostic>) {
+        let mut diagnostics = Vec::new();
+        let known = |name: &str| self.passes.iter().any(|x| x.pass.name() == name);
+        for name in self.disabled.iter().filter(|x| !known(x)) {
+            diagnostics.push(Diagnostic::warning(None, format!("cannot disable unknown pass `{}`", name)));
+        }
+        let passes = self.passes
+            .iter()
+            .filter(|x| !self.disabled.iter().any(|name| name == x.pass.name()))
+            .filter(|x| x.pass.enabled(&self.settings))
+            .collect::<Vec<_>>();
+        let position = |name: &str| passes.iter().position(|x| x.pass.name() == name);
+        // EDGES, I.E. `(FROM, TO)`
+        let mut edges = Vec::new();
+        let builtins = passes
+            .iter()
+            .enumerate()
+            .filter(|(_, x)| x.builtin)
+            .map(|(ix, _)| ix)
+            .collect::<Vec<_>>();
+        edges.extend(builtins.windows(2).map(|x| (x[0], x[1])));
+        for (ix, x) in passes.iter().enumerate() {
+            let pass = x.pass.as_ref();
+            let constraints = pass.after()
+                .into_iter()
+                .map(|x| (x, true))
+                .chain(pass.before().into_iter().map(|x| (x, false)));
+            for (name, after) in constraints {
+                match position(name) {
+                    Some(other) if after => edges.push((other, ix)),
+                    Some(other) => edges.push((ix, other)),
+                    None if known(name) => (),
+                    None => {
+                        let message = format!("pass `{}` is ordered relative to unknown pass `{}`", pass.name(), name);
+                        diagnostics.push(Diagnostic::warning(None, message));
+                    }
+                }
+            }
+        }
+        // TOPOLOGICAL SORT, PREFERRING THE ORDER THE PASSES WERE ADDED IN
+        let mut remaining = (0..passes.len()).collect::<Vec<_>>();
+        let mut order = Vec::new();
+        while !remaining.is_empty() {
+            let next = remaining
+                .iter()
+                .position(|x| !edges.iter().any(|(from, to)| to == x && remaining.contains(from)));
+            match next {
+                Some(next) => order.push(remaining.remove(next)),
+                None => {
+                    let names = remaining
+                        .iter()
+                        .map(|x| format!("`{}`", passes[*x].pass.name()))
+                        .collect::<Vec<_>>();
+                    let message = format!("cyclic ordering constraints between the passes {}", names.join(", "));
+                    diagnostics.push(Diagnostic::error(None, message));
+                    order.append(&mut remaining);
+                }
+            }
+        }
+        let passes = order.into_iter().map(|x| passes[x].pass.as_ref()).collect();
+        (passes, diagnostics)
+    }
+    /// The names of the enabled passes, in order.
+    pub fn pass_names(&self) -> Vec<String> {
+        self.ordered().0.into_iter().map(|x| x.name().to_owned()).collect()
+    }
+    /// Parses the given source and runs the passes; returns the resulting
+    /// tree (i.e. before rendering).
+    pub fn run<'a>(&self, source: &'a str) -> (Vec<Node<'a>>, Vec<Diagnostic>) {
+        let (passes, diagnostics) = self.ordered();
+        let mut context = PassContext {settings: &self.settings, diagnostics, terms: Vec::new()};
+        let mut nodes = crate::frontend::pass::pp_normalize::run_compiler_frontend(source);
+        for inspector in self.inspectors.iter() {
+            inspector(PARSE_STAGE, &nodes);
+        }
+        for pass in passes {
+            nodes = pass.run(nodes, &mut context);
+            for inspector in self.inspectors.iter() {
+                inspector(pass.name(), &nodes);
+            }
+        }
+        (nodes, context.diagnostics)
+    }
+    /// Compiles the given source into an HTML document (i.e. with the passes
+    /// of `Compiler::new`).
+    pub fn compile<'a>(&self, source: &'a str) -> Document<'a> {
+        let (nodes, diagnostics) = self.run(source);
+        Document::from_nodes(nodes, diagnostics, &self.settings)
+    }
+    /// Compiles the given source into a slideshow (i.e. with the passes of
+    /// `Compiler::new`).
+    pub fn compile_slides<'a>(&self, source: &'a str) -> crate::codegen::slides::Document<'a> {
+        let (nodes, diagnostics) = self.run(source);
+        crate::codegen::slides::Document::from_nodes(nodes, diagnostics, &self.settings)
+    }
+    /// Compiles the given source into the next chapter of the given book
+    /// (i.e. with the passes of `Compiler::epub`); returns the diagnostics of
+    /// the given source.
+    pub fn compile_chapter(&self, book: &mut crate::codegen::epub::Book, source: &str) -> Vec<Diagnostic> {
+        let (nodes, diagnostics) = self.run(source);
+        book.add_chapter_nodes(source, nodes, diagnostics, &self.settings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    fn noop(name: &str) -> FnPass {
+        FnPass::new(name, |nodes, _| nodes)
+    }
+
+    #[test]
+    fn builtin_passes() {
+        assert_eq!(Compiler::new().pass_names(), vec![
+            "tags", "terms", "glossary", "tables", "notes", "code", "html-tags", "lists", "images",
+            "layout", "math",
+        ]);
+        assert_eq!(
+            Compiler::markdown().pass_names(),
+            vec!["tags", "terms", "glossary", "tables", "notes", "markdown-tags", "lists"],
+        );
+        let mut names = Compiler::new().pass_names();
+        names.remove(2);
+        assert_eq!(Compiler::epub().pass_names(), names);
+        assert_eq!(Compiler::search().pass_names(), names[..7]);
+        let settings = Settings {typography: true, ..Settings::default()};
+        let names = Compiler::new().settings(settings).pass_names();
+        assert_eq!(names[..5], ["tags", "terms", "glossary", "tables", "typography"]);
+        let mut settings = Settings::default();
+        settings.passes.paragraphs = true;
+        let names = Compiler::new().settings(settings).pass_names();
+        assert_eq!(names[7..10], ["lists", "paragraphs", "images"]);
+    }
+
+    #[test]
+    fn ordering_constraints() {
+        let compiler = Compiler::markdown()
+            .pass(noop("last"))
+            .pass(noop("b").after("notes").before("markdown-tags"))
+            .pass(noop("a").before("b"));
+        assert_eq!(compiler.pass_names(), vec![
+            "tags", "terms", "glossary", "tables", "notes", "last", "a", "b", "markdown-tags",
+            "lists",
+        ]);
+    }
+
+    #[test]
+    fn disabled_and_replaced_passes() {
+        let compiler = Compiler::markdown()
+            .disable("notes")
+            .pass(noop("tables"))
+            .pass(noop("x").after("notes"));
+        // Constraints on disabled passes are ignored.
+        assert_eq!(
+            compiler.pass_names(),
+            vec!["tags", "terms", "glossary", "tables", "markdown-tags", "lists", "x"],
+        );
+        let (nodes, diagnostics) = compiler.run("\\theorem{a} \\table{b & c}");
+        assert!(diagnostics.is_empty());
+        assert_eq!(crate::frontend::printer::print_source(&nodes), "\\theorem{a} \\table{b & c}");
+    }
+
+    #[test]
+    fn unknown_passes_and_cycles() {
+        let compiler = Compiler::markdown()
+            .disable("nope")
+            .pass(noop("a").after("missing"))
+            .pass(noop("b").after("c"))
+            .pass(noop("c").after("b"));
+        let (_, diagnostics) = compiler.run("");
+        let messages = diagnostics.iter().map(|x| x.message.as_str()).collect::<Vec<_>>();
+        assert_eq!(messages, vec![
+            "cannot disable unknown pass `nope`",
+            "pass `a` is ordered relative to unknown pass `missing`",
+            "cyclic ordering constraints between the passes `b`, `c`",
+        ]);
+        assert!(diagnostics[2].is_error());
+    }
+
+    #[test]
+    fn custom_passes_and_inspectors() {
+        let stages = Rc::new(RefCell::new(Vec::new()));
+        let compiler = Compiler::markdown()
+            .pass(FnPass::new("warn", |nodes, context| {
+                context.diagnostics.push(Diagnostic::warning(None, "warned"));
+                nodes
+            }).after("glossary").before("tables"))
+            .inspect({
+                let stages = stages.clone();
+                move |stage, nodes| stages.borrow_mut().push(format!("{} {}", stage, nodes.len()))
+            });
+        let (_, diagnostics) = compiler.run("\\p{a} \\p{b}");
+        assert_eq!(diagnostics[0].message, "warned");
+        assert_eq!(*stages.borrow(), vec![
+            "parse 3", "tags 3", "terms 3", "glossary 3", "warn 3", "tables 3", "notes 3",
+            "markdown-tags 3", "lists 3",
+        ]);
+    }
+
+    #[test]
+    fn compile() {
+        let document = Compiler::new().compile("\\h1{A} b");
+        assert_eq!(document.title, "A");
+        assert_eq!(document.body.len(), 2);
+        // PARAGRAPH INFERENCE IS OPT-IN
+        assert!(!document.render_to_string().contains("<p>"));
+        let mut settings = Settings::default();
+        settings.passes.paragraphs = true;
+        let document = Compiler::new().settings(settings).compile("\\h1{A} b");
+        assert!(document.render_to_string().contains("<p>b</p>"));
+    }
+
+    #[test]
+    fn slides_and_epub_run_the_passes() {
+        let source = "\\h1{A} \\table{b & c}";
+        let slides = Compiler::new().disable("tables").compile_slides(source).render_to_string();
+        assert!(slides.contains("<table>b & c</table>"));
+        let mut book = crate::codegen::epub::Book::default();
+        Compiler::epub().disable("tables").compile_chapter(&mut book, source);
+        assert!(!book.chapters[0].xhtml.contains("<td>"));
+        // CUSTOM PASSES & INSPECTORS
+        let stages = Rc::new(RefCell::new(Vec::new()));
+        let compiler = Compiler::epub()
+            .pass(FnPass::new("warn", |nodes, context| {
+                context.diagnostics.push(Diagnostic::warning(None, "warned"));
+                nodes
+            }))
+            .inspect({
+                let stages = stages.clone();
+                move |stage, _| stages.borrow_mut().push(stage.to_owned())
+            });
+        let diagnostics = compiler.compile_chapter(&mut book, source);
+        assert_eq!(diagnostics[0].message, "warned");
+        assert_eq!(stages.borrow().last().unwrap(), "warn");
+        assert!(book.chapters[1].xhtml.contains("<td>b</td>"));
+    }
+}
@@ -150,19 +150,15 @@ impl<'a> Tag<'a> {
         return self.name() == name
     }
     pub fn insert_parameter(&mut self, value: Ann<&str>) {
-        let mut args = self.parameters.clone().unwrap_or(Vec::new());
-        args.push(Node::String(Ann::join(
+        self.parameters.get_or_insert_with(Vec::new).push(Node::String(Ann::join(
             value.range,
             Cow::Owned(value.data.to_owned()),
         )));
-        self.parameters = Some(args);
     }
     pub fn insert_unannotated_parameter(&mut self, value: &str) {
-        let mut args = self.parameters.clone().unwrap_or(Vec::new());
-        args.push(Node::String(Ann::unannotated(
+        self.parameters.get_or_insert_with(Vec::new).push(Node::String(Ann::unannotated(
             Cow::Owned(value.to_owned())
         )));
-        self.parameters = Some(args);
     }
     // /// Short for `Tag::insert_unannotated_parameter`
     // pub fn insert_attr(&mut self, value: &str) {
@@ -184,6 +180,30 @@ impl<'a> Tag<'a> {
             })
             .map(Clone::clone)
     }
+    /// The right hand side of some `key=value` parameter.
+    pub fn get_parameter_value(&self, key: &str) -> Option<String> {
+        let param = self.get_parameter(key)?;
+        let (_, value) = param.data.split_once("=")?;
+        Some(value.trim().to_owned())
+    }
+    pub fn has_parameter(&self, key: &str) -> bool {
+        self.get_parameter(key).is_some()
+    }
+    pub fn remove_parameter(&mut self, key: &str) {
+        if let Some(parameters) = self.parameters.as_mut() {
+            parameters.retain(|x| {
+                let str: &str = match x.unwrap_string() {
+                    Some(x) => &x.data,
+                    None => return true,
+                };
+                let str = str
+                    .split_once("=")
+                    .map(|(x, _)| x)
+                    .unwrap_or(str);
+                str.trim() != key
+            });
+        }
+    }
     pub fn name(&self) -> &str {
         &self.name.data
     }
@@ -197,12 +217,14 @@ impl<'a> Tag<'a> {
 
 #[derive(Debug, Clone, Default)]
 pub struct NodeEnvironment<'a> {
-    pub parents: Vec<Atom<'a>>,
+    /// Shared by the environments of siblings (i.e. cloning is cheap), and
+    /// copied on `push_parent`.
+    pub parents: Rc<Vec<Atom<'a>>>,
 }
 
 impl<'a> NodeEnvironment<'a> {
     pub fn push_parent(&mut self, name: Atom<'a>) {
-        self.parents.push(name)
+        Rc::make_mut(&mut self.parents).push(name)
     }
     pub fn is_math_env(&self) -> bool {
         self.parents
@@ -245,7 +267,8 @@ pub enum Node<'a> {
     String(Ann<Atom<'a>>),
     /// Some unbalanced token that isn’t associated with an enclosure. 
     /// In Subscript, enclosure symbols must be balanced. If the author
-    /// must use such in their publications, then use the tag version. 
+    /// must use such in their publications, then use the escaped version
+    /// (e.g. `\}` or `\]`, see `ESCAPE_SEQUENCES`) or `\verb`.
     InvalidToken(Ann<Atom<'a>>),
 }
 
@@ -446,6 +469,43 @@ impl<'a> Node<'a> {
             _ => None,
         }
     }
+    /// Detaches the node from its source, e.g. to insert a fragment parsed
+    /// from some other (temporary) string.
+    pub fn into_owned(self) -> Node<'static> {
+        fn atom(x: Atom) -> Atom<'static> {
+            Cow::Owned(x.into_owned())
+        }
+        fn nodes(xs: Vec<Node>) -> Vec<Node<'static>> {
+            xs.into_iter().map(Node::into_owned).collect()
+        }
+        match self {
+            Node::Tag(tag) => Node::Tag(Tag {
+                name: tag.name.map(atom),
+                parameters: tag.parameters.map(nodes),
+                children: nodes(tag.children),
+                rewrite_rules: tag.rewrite_rules
+                    .into_iter()
+                    .map(|x| RewriteRule {from: x.from.into_owned(), to: x.to.into_owned()})
+                    .collect(),
+            }),
+            Node::Ident(x) => Node::Ident(x.map(atom)),
+            Node::Enclosure(x) => Node::Enclosure(x.map(|x| Enclosure {
+                kind: match x.kind {
+                    EnclosureKind::CurlyBrace => EnclosureKind::CurlyBrace,
+                    EnclosureKind::SquareParen => EnclosureKind::SquareParen,
+                    EnclosureKind::Parens => EnclosureKind::Parens,
+                    EnclosureKind::Fragment => EnclosureKind::Fragment,
+                    EnclosureKind::Error {open, close} => EnclosureKind::Error {
+                        open: atom(open),
+                        close: close.map(atom),
+                    },
+                },
+                children: nodes(x.children),
+            })),
+            Node::String(x) => Node::String(x.map(atom)),
+            Node::InvalidToken(x) => Node::InvalidToken(x.map(atom)),
+        }
+    }
 
     /// Bottom up 'node to ndoe' transformation.
     pub fn transform<F: Fn(NodeEnvironment<'a>, Node<'a>) -> Node<'a>>(
@@ -726,7 +786,31 @@ impl<'a> Node<'a> {
                 };
                 vec![highlight]
             }
-            Node::String(value) => Vec::new(),
+            Node::String(value) if unescape(&value.data).is_some() => {
+                let highlight = Highlight {
+                    kind: HighlightKind::Escape(value.data),
+                    range: value.range,
+                    binder,
+                    nesting,
+                };
+                vec![highlight]
+            }
+            Node::String(value) => {
+                let is_verbatim = nesting
+                    .last()
+                    .map(|x| VERBATIM_TAGS.contains(&x.as_ref()))
+                    .unwrap_or(false);
+                if is_verbatim {
+                    let highlight = Highlight {
+                        kind: HighlightKind::Verbatim,
+                        range: value.range,
+                        binder,
+                        nesting,
+                    };
+                    return vec![highlight]
+                }
+                Vec::new()
+            }
         }
     }
 
@@ -880,5 +964,9 @@ pub enum HighlightKind<'a> {
     },
     InvalidToken(Atom<'a>),
     Ident(Atom<'a>),
+    /// Some escape sequence, e.g. `\}`.
+    Escape(Atom<'a>),
+    /// The body of some verbatim tag, e.g. `\code{...}`.
+    Verbatim,
 }
 
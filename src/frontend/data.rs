@@ -6,6 +6,15 @@ use lazy_static::lazy_static;
 
 
 pub static INLINE_MATH_TAG: &'static str = "[inline-math]";
+/// Internal - an SVG file inlined by the image pass; the child is the payload.
+pub static INLINE_SVG_TAG: &str = "[inline-svg]";
+/// Tags whose (curly brace) body is kept verbatim by the parser. The body may
+/// be fenced with `#`s (e.g. `\code#{ ... }#`) to allow unbalanced braces.
+pub static VERBATIM_TAGS: &[&str] = &[
+    "code",
+    "pre",
+    "verb",
+];
 pub static BLOCK_MATH_TAGS: &[&'static str] = &[
     "equation",
 ];
@@ -22,10 +31,73 @@ lazy_static! {
     ]);
 }
 
+/// Tags that are never wrapped in an inferred paragraph (see `pass::paragraph`).
+pub static BLOCK_LEVEL_TAGS: &[&str] = &[
+    "address",
+    "article",
+    "aside",
+    "blockquote",
+    "caption",
+    "dd",
+    "details",
+    "div",
+    "dl",
+    "dt",
+    "equation",
+    "figcaption",
+    "figure",
+    "footer",
+    "form",
+    "h1",
+    "h2",
+    "h3",
+    "h4",
+    "h5",
+    "h6",
+    "header",
+    "hr",
+    "layout",
+    "li",
+    "main",
+    "nav",
+    "note",
+    "ol",
+    "p",
+    "pre",
+    "section",
+    "summary",
+    "table",
+    "tbody",
+    "td",
+    "tfoot",
+    "th",
+    "thead",
+    "tr",
+    "ul",
+];
+
 pub static ALL_SUBSCRIPT_TAGS: &[&'static str] = &[
     "note",
     "layout",
     "equation",
+    "figure",
+    "slide",
+    "pagebreak",
+    "theorem",
+    "lemma",
+    "definition",
+    "proof",
+    "example",
+    "exercise",
+    "warning",
+    "solution",
+    "term",
+    "index",
+];
+
+/// Subscript tags that may be written without any arguments (e.g. `\pagebreak`).
+pub static VOID_SUBSCRIPT_TAGS: &[&str] = &[
+    "pagebreak",
 ];
 
 pub static ALLOWED_HTML_TAGS: &[&'static str] = &[
@@ -35,6 +107,8 @@ pub static ALLOWED_HTML_TAGS: &[&'static str] = &[
     "footer",
     "header",
     "h1",
+    "main",
+    "nav",
     "section",
     "blockquote",
     "dd",
@@ -237,6 +311,48 @@ fn get_begin_kind_for(end_kind: &str) -> &str {
     }
 }
 
+/// Backslash escapes for writing special tokens literally, paired with the
+/// literal text they produce outside of math mode (in math mode the escape is
+/// passed through to LaTeX as is, e.g. `\\` is a line break).
+///
+/// Note that outside of math mode `\{` is not an escape; it begins inline
+/// math. Use `\verb#{{}#` for a literal opening curly brace.
+///
+/// In particular, `\\` outside of math mode is a single literal backslash
+/// (previously the two backslashes were passed through, and the second began
+/// a tag); use `\verb{\\}` for two literal backslashes.
+pub static ESCAPE_SEQUENCES: &[(&str, &str)] = &[
+    ("\\\\", "\\"),
+    ("\\{", "{"),
+    ("\\}", "}"),
+    ("\\[", "["),
+    ("\\]", "]"),
+    ("\\(", "("),
+    ("\\)", ")"),
+    ("\\_", "_"),
+    ("\\^", "^"),
+    ("\\=", "="),
+    ("\\>", ">"),
+    ("\\*", "*"),
+    ("\\`", "`"),
+];
+
+/// The escape sequence for the given token (if it’s escapable).
+pub fn escape_sequence(token: &str) -> Option<&'static str> {
+    ESCAPE_SEQUENCES
+        .iter()
+        .find(|(escape, _)| &escape[1..] == token)
+        .map(|(escape, _)| *escape)
+}
+
+/// The literal text of the given escape sequence (e.g. `\}` to `}`).
+pub fn unescape(value: &str) -> Option<&'static str> {
+    ESCAPE_SEQUENCES
+        .iter()
+        .find(|(escape, _)| *escape == value)
+        .map(|(_, literal)| *literal)
+}
+
 pub fn is_token<'a>(value: &'a str) -> bool {
     for tk in TOKEN_SET {
         if *tk == value {
@@ -344,3 +460,18 @@ impl<'a, T> Enclosure<'a, T> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escape_sequences_round_trip() {
+        for (escape, literal) in ESCAPE_SEQUENCES {
+            assert_eq!(unescape(escape), Some(*literal));
+            assert_eq!(escape_sequence(literal), Some(*escape));
+        }
+        assert_eq!(unescape("}"), None);
+        assert_eq!(escape_sequence("a"), None);
+    }
+}
@@ -0,0 +1,77 @@
+//! Compiler diagnostics (i.e. warnings & errors) reported back to the author.
+use serde::{Serialize, Deserialize};
+use crate::frontend::ast::CharRange;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Source location, if the offending node still has one.
+    pub range: Option<CharRange>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn warning(range: Option<CharRange>, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Warning,
+            range,
+            message: message.into(),
+        }
+    }
+    pub fn error(range: Option<CharRange>, message: impl Into<String>) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            range,
+            message: message.into(),
+        }
+    }
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+    /// The one-based `(line, column)` of the diagnostic within the given source.
+    pub fn line_column(&self, source: &str) -> Option<(usize, usize)> {
+        let byte_index = self.range?.start.byte_index;
+        let before = source.get(..byte_index)?;
+        let line = before.matches('\n').count() + 1;
+        let column = before
+            .rsplit('\n')
+            .next()
+            .map(|x| x.chars().count() + 1)
+            .unwrap_or(1);
+        Some((line, column))
+    }
+    /// Formats the diagnostic for terminal output, e.g.
+    /// `[WARNING!] notes.txt:12:5: image file not found`.
+    pub fn render(&self, file_name: &str, source: &str) -> String {
+        let label = match self.severity {
+            Severity::Warning => "[WARNING!]",
+            Severity::Error => "[ERROR!]",
+        };
+        match self.line_column(source) {
+            Some((line, column)) => format!(
+                "{} {}:{}:{}: {}",
+                label,
+                file_name,
+                line,
+                column,
+                self.message,
+            ),
+            None => format!("{} {}: {}", label, file_name, self.message),
+        }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "[WARNING!] {}", self.message),
+            Severity::Error => write!(f, "[ERROR!] {}", self.message),
+        }
+    }
+}
@@ -0,0 +1,604 @@
+//! CommonMark to Subscript conversion.
+//!
+//! The Markdown document is converted into the frontend AST (i.e. the same
+//! tree the parser would produce for the equivalent Subscript source), which
+//! is then printed via `frontend::printer`.
+//!
+//! | Markdown                | Subscript                          |
+//! | ----------------------- | ---------------------------------- |
+//! | `# Title`               | `\h1{Title}`                       |
+//! | `*a*`, `**b**`, `~~c~~` | `\i{a}`, `\b{b}`, `\s{c}`           |
+//! | `` `code` ``            | `\code{code}`                      |
+//! | fenced code blocks      | `\code[lang=rust]{...}`            |
+//! | `[text](url)`           | `\a[href=url]{text}`               |
+//! | `![alt](src)`           | `\img[src=src]` (or a `\figure`)    |
+//! | `$x$`, `$$x$$`          | `\{x}`, `\equation{x}`             |
+//! | lists                   | `\ul{\li{...}}`, `\ol{\li{...}}`   |
+//! | tables                  | `\table[header align=lc]{...}`     |
+//!
+//! Paragraphs are separated by blank lines (see `pass::paragraph`).
+use std::borrow::Cow;
+use pulldown_cmark::{
+    Alignment,
+    CodeBlockKind,
+    Event,
+    Options,
+    Parser,
+    Tag as MdTag,
+};
+use crate::frontend::data::*;
+use crate::frontend::ast::*;
+use crate::frontend::printer::print_source;
+
+const INDENT: &str = "    ";
+
+enum Frame {
+    Document,
+    Paragraph,
+    Heading(usize),
+    BlockQuote,
+    List(Option<u64>),
+    Item,
+    CodeBlock(Option<String>),
+    Inline(&'static str),
+    Link(String),
+    Image(String),
+    Table(Vec<Alignment>),
+    TableHead,
+    TableRow,
+    TableCell,
+    /// Unsupported containers; the contents are kept.
+    Other,
+}
+
+struct Block {
+    is_paragraph: bool,
+    node: Node<'static>,
+}
+
+struct Scope {
+    frame: Frame,
+    inline: Vec<Node<'static>>,
+    blocks: Vec<Block>,
+    text: String,
+    header_rows: usize,
+    cells: Vec<Vec<Node<'static>>>,
+    rows: Vec<Vec<Vec<Node<'static>>>>,
+}
+
+impl Scope {
+    fn new(frame: Frame) -> Self {
+        Scope {
+            frame,
+            inline: Vec::new(),
+            blocks: Vec::new(),
+            text: String::new(),
+            header_rows: 0,
+            cells: Vec::new(),
+            rows: Vec::new(),
+        }
+    }
+    /// Moves the pending inline content into a block of its own.
+    fn flush_inline(&mut self) {
+        let inline = trim_inline(std::mem::take(&mut self.inline));
+        if !inline.is_empty() {
+            self.blocks.push(Block {
+                is_paragraph: true,
+                node: Node::new_fragment(inline),
+            });
+        }
+    }
+}
+
+fn string(value: impl Into<String>) -> Node<'static> {
+    Node::String(Ann::unannotated(Cow::Owned(value.into())))
+}
+
+fn tag(name: &'static str, children: Vec<Node<'static>>) -> Tag<'static> {
+    Tag::new(Ann::unannotated(name), vec![
+        Node::unannotated_enclosure(EnclosureKind::CurlyBrace, children)
+    ])
+}
+
+fn trim_inline(mut nodes: Vec<Node<'static>>) -> Vec<Node<'static>> {
+    while nodes.first().map(Node::is_whitespace).unwrap_or(false) {
+        nodes.remove(0);
+    }
+    while nodes.last().map(Node::is_whitespace).unwrap_or(false) {
+        nodes.pop();
+    }
+    nodes
+}
+
+fn is_boundary(x: Option<char>) -> bool {
+    x.map(|x| !x.is_alphanumeric()).unwrap_or(true)
+}
+
+/// Converts Markdown text into Subscript text, escaping the special tokens.
+fn text_nodes(text: &str) -> Vec<Node<'static>> {
+    let depth = text.chars().fold(0isize, |depth, x| match x {
+        '(' => depth + 1,
+        ')' => depth - 1,
+        _ => depth,
+    });
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut nodes = Vec::new();
+    let mut current = String::new();
+    let mut ix = 0;
+    while ix < chars.len() {
+        let char = chars[ix];
+        let previous = ix.checked_sub(1).map(|ix| chars[ix]);
+        let next = chars.get(ix + 1).copied();
+        let is_escaped = match char {
+            '\\' | '}' | '[' | ']' | '`' => true,
+            '(' | ')' => depth != 0,
+            // THE INLINE FORMATTING MARKERS ONLY COUNT AT WORD BOUNDARIES
+            '*' | '_' => is_boundary(previous) || is_boundary(next),
+            _ => false,
+        };
+        if (is_escaped || char == '{') && !current.is_empty() {
+            nodes.push(string(std::mem::take(&mut current)));
+        }
+        if char == '{' {
+            // `\{` BEGINS INLINE MATH, SO CURLY BRACES ARE WRITTEN AS `\verb{{...}}`
+            let end = matching_curly_brace(&chars, ix).unwrap_or(ix);
+            let literal = chars[ix..=end].iter().collect::<String>();
+            nodes.push(Node::Tag(tag("verb", vec![string(literal)])));
+            ix = end;
+        } else if is_escaped {
+            nodes.push(string(format!("\\{}", char)));
+        } else {
+            current.push(char);
+        }
+        ix += 1;
+    }
+    if !current.is_empty() {
+        nodes.push(string(current));
+    }
+    nodes
+}
+
+fn matching_curly_brace(chars: &[char], start: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    for (ix, char) in chars.iter().enumerate().skip(start) {
+        match char {
+            '{' => depth += 1,
+            '}' if depth == 1 => return Some(ix),
+            '}' => depth -= 1,
+            _ => (),
+        }
+    }
+    None
+}
+
+/// Percent-encodes the characters that would end (or nest within) a
+/// parameter value, e.g. `Rust_(language)` to `Rust_%28language%29`.
+fn url_parameter(url: &str) -> String {
+    let mut encoded = String::with_capacity(url.len());
+    for char in url.chars() {
+        if "()[]{}".contains(char) || char.is_whitespace() {
+            let mut buffer = [0; 4];
+            for byte in char.encode_utf8(&mut buffer).bytes() {
+                encoded.push_str(&format!("%{:02X}", byte));
+            }
+        } else {
+            encoded.push(char);
+        }
+    }
+    encoded
+}
+
+fn plain_text(nodes: &[Node]) -> String {
+    nodes
+        .iter()
+        .map(|x| match x.unwrap_string() {
+            Some(x) => unescape(&x.data).unwrap_or(&x.data).to_owned(),
+            None => x.to_string(),
+        })
+        .collect()
+}
+
+/// Images keep their alt text as a child until here; a lone image becomes a
+/// figure with the alt text as its caption, otherwise the alt text is dropped
+/// (parameter values can’t contain whitespace).
+fn finish_paragraph(block: Block) -> Block {
+    if !block.is_paragraph {
+        return block
+    }
+    let inline = block.node.into_fragment();
+    if let [Node::Tag(img)] = inline.as_slice() {
+        if img.has_name("img") && !img.children.is_empty() {
+            let mut img = img.clone();
+            let caption = img.children.pop().unwrap();
+            let mut figure = tag("figure", vec![Node::Tag(img)]);
+            figure.children.push(caption);
+            return Block {is_paragraph: false, node: Node::Tag(figure)}
+        }
+    }
+    let inline = inline
+        .into_iter()
+        .map(|x| match x {
+            Node::Tag(mut img) if img.has_name("img") => {
+                img.children.clear();
+                Node::Tag(img)
+            }
+            x => x,
+        })
+        .collect();
+    Block {is_paragraph: true, node: Node::new_fragment(inline)}
+}
+
+/// Lays out the given blocks on separate (indented) lines within some block.
+fn layout_blocks(blocks: Vec<Block>, depth: usize) -> Vec<Node<'static>> {
+    let indent = INDENT.repeat(depth);
+    let mut nodes = Vec::new();
+    let mut previous_is_paragraph = false;
+    for (ix, block) in blocks.into_iter().enumerate() {
+        let separator = match (ix, previous_is_paragraph && block.is_paragraph) {
+            (0, _) => format!("\n{}", indent),
+            (_, true) => format!("\n\n{}", indent),
+            (_, false) => format!("\n{}", indent),
+        };
+        previous_is_paragraph = block.is_paragraph;
+        nodes.push(string(separator));
+        nodes.push(block.node);
+    }
+    nodes.push(string(format!("\n{}", INDENT.repeat(depth.saturating_sub(1)))));
+    nodes
+}
+
+struct Converter {
+    stack: Vec<Scope>,
+}
+
+impl Converter {
+    fn current(&mut self) -> &mut Scope {
+        self.stack.last_mut().unwrap()
+    }
+    /// The indentation level of the current block.
+    fn depth(&self) -> usize {
+        self.stack
+            .iter()
+            .filter(|x| matches!(x.frame, Frame::BlockQuote | Frame::List(_) | Frame::Item))
+            .count()
+    }
+    fn push_inline(&mut self, node: Node<'static>) {
+        self.current().inline.push(node);
+    }
+    fn push_block(&mut self, node: Node<'static>) {
+        let scope = self.current();
+        scope.flush_inline();
+        scope.blocks.push(Block {is_paragraph: false, node});
+    }
+    fn start(&mut self, tag: MdTag) {
+        let frame = match tag {
+            MdTag::Paragraph => Frame::Paragraph,
+            MdTag::Heading{level, ..} => Frame::Heading(level as usize),
+            MdTag::BlockQuote(_) => Frame::BlockQuote,
+            MdTag::List(start) => Frame::List(start),
+            MdTag::Item => Frame::Item,
+            MdTag::CodeBlock(CodeBlockKind::Fenced(lang)) => {
+                let lang = lang.split_whitespace().next().map(ToOwned::to_owned);
+                Frame::CodeBlock(lang)
+            }
+            MdTag::CodeBlock(CodeBlockKind::Indented) => Frame::CodeBlock(None),
+            MdTag::Emphasis => Frame::Inline("i"),
+            MdTag::Strong => Frame::Inline("b"),
+            MdTag::Strikethrough => Frame::Inline("s"),
+            MdTag::Superscript => Frame::Inline("sup"),
+            MdTag::Subscript => Frame::Inline("sub"),
+            MdTag::Link{dest_url, ..} => Frame::Link(dest_url.to_string()),
+            MdTag::Image{dest_url, ..} => Frame::Image(dest_url.to_string()),
+            MdTag::Table(alignment) => Frame::Table(alignment),
+            MdTag::TableHead => Frame::TableHead,
+            MdTag::TableRow => Frame::TableRow,
+            MdTag::TableCell => Frame::TableCell,
+            _ => Frame::Other,
+        };
+        if let Frame::Item = frame {
+            self.current().flush_inline();
+        }
+        self.stack.push(Scope::new(frame));
+    }
+    fn end(&mut self) {
+        let mut scope = self.stack.pop().unwrap();
+        let depth = self.depth();
+        match scope.frame {
+            Frame::Paragraph => {
+                scope.flush_inline();
+                let parent = self.current();
+                parent.flush_inline();
+                parent.blocks.extend(scope.blocks.into_iter().map(finish_paragraph));
+            }
+            Frame::Heading(level) => {
+                let name = ["h1", "h2", "h3", "h4", "h5", "h6"][level.clamp(1, 6) - 1];
+                let heading = tag(name, trim_inline(scope.inline));
+                self.push_block(Node::Tag(heading));
+            }
+            Frame::BlockQuote => {
+                scope.flush_inline();
+                let quote = tag("blockquote", layout_blocks(scope.blocks, depth + 1));
+                self.push_block(Node::Tag(quote));
+            }
+            Frame::List(start) => {
+                scope.flush_inline();
+                let mut list = tag(
+                    if start.is_some() {"ol"} else {"ul"},
+                    layout_blocks(scope.blocks, depth + 1),
+                );
+                if let Some(start) = start.filter(|x| *x != 1) {
+                    list.insert_unannotated_parameter(&format!("start={}", start));
+                }
+                self.push_block(Node::Tag(list));
+            }
+            Frame::Item => {
+                let children = if scope.blocks.is_empty() {
+                    trim_inline(scope.inline)
+                } else if scope.blocks.len() == 1 && scope.inline.is_empty() && scope.blocks[0].is_paragraph {
+                    scope.blocks.pop().unwrap().node.into_fragment()
+                } else {
+                    scope.flush_inline();
+                    layout_blocks(scope.blocks, depth + 1)
+                };
+                let item = Node::Tag(tag("li", children));
+                self.current().blocks.push(Block {is_paragraph: false, node: item});
+            }
+            Frame::CodeBlock(lang) => {
+                let mut code = Tag::new(Ann::unannotated("code"), vec![
+                    Node::unannotated_enclosure(EnclosureKind::CurlyBrace, vec![
+                        string(format!("\n{}", scope.text))
+                    ])
+                ]);
+                if let Some(lang) = lang {
+                    code.insert_unannotated_parameter(&format!("lang={}", lang));
+                }
+                self.push_block(Node::Tag(code));
+            }
+            Frame::Inline(name) => {
+                self.push_inline(Node::Tag(tag(name, scope.inline)));
+            }
+            Frame::Link(url) => {
+                let mut link = tag("a", scope.inline);
+                link.insert_unannotated_parameter(&format!("href={}", url_parameter(&url)));
+                self.push_inline(Node::Tag(link));
+            }
+            Frame::Image(url) => {
+                // THE ALT TEXT IS KEPT AS A CHILD FOR NOW (SEE `finish_paragraph`)
+                let mut img = Tag::new(Ann::unannotated("img"), Vec::new());
+                img.insert_unannotated_parameter(&format!("src={}", url_parameter(&url)));
+                let alt = plain_text(&scope.inline);
+                if !alt.trim().is_empty() {
+                    img.children.push(Node::unannotated_enclosure(
+                        EnclosureKind::CurlyBrace,
+                        scope.inline,
+                    ));
+                }
+                self.push_inline(Node::Tag(img));
+            }
+            Frame::TableCell => {
+                let cell = trim_inline(scope.inline);
+                self.current().cells.push(cell);
+            }
+            Frame::TableHead | Frame::TableRow => {
+                let is_head = matches!(scope.frame, Frame::TableHead);
+                let table = self.current();
+                table.rows.push(scope.cells);
+                if is_head {
+                    table.header_rows += 1;
+                }
+            }
+            Frame::Table(alignment) => {
+                self.push_block(Node::Tag(table_tag(scope.rows, &alignment, scope.header_rows, depth)));
+            }
+            Frame::Other | Frame::Document => {
+                scope.flush_inline();
+                let parent = self.current();
+                parent.flush_inline();
+                parent.blocks.extend(scope.blocks);
+            }
+        }
+    }
+    fn event(&mut self, event: Event) {
+        let depth = self.depth();
+        match event {
+            Event::Start(tag) => self.start(tag),
+            Event::End(_) => self.end(),
+            Event::Text(text) => {
+                let scope = self.current();
+                match scope.frame {
+                    Frame::CodeBlock(_) => scope.text.push_str(&text),
+                    _ => scope.inline.extend(text_nodes(&text)),
+                }
+            }
+            Event::Code(code) => {
+                let code = Tag::new(Ann::unannotated("code"), vec![
+                    Node::unannotated_enclosure(EnclosureKind::CurlyBrace, vec![
+                        string(code.to_string())
+                    ])
+                ]);
+                self.push_inline(Node::Tag(code));
+            }
+            Event::InlineMath(math) => {
+                let math = Tag::new(Ann::unannotated(INLINE_MATH_TAG), vec![
+                    string(math.to_string())
+                ]);
+                self.push_inline(Node::Tag(math));
+            }
+            Event::DisplayMath(math) => {
+                let math = tag("equation", vec![
+                    string(format!("\n{}{}", INDENT.repeat(depth + 1), math.trim())),
+                    string(format!("\n{}", INDENT.repeat(depth))),
+                ]);
+                // SPLITS THE ENCLOSING PARAGRAPH
+                self.push_block(Node::Tag(math));
+            }
+            Event::Html(html) | Event::InlineHtml(html) => {
+                self.push_inline(string(html.to_string()));
+            }
+            Event::SoftBreak => {
+                self.push_inline(string(format!("\n{}", INDENT.repeat(depth))));
+            }
+            Event::HardBreak => {
+                self.push_inline(Node::Tag(tag("br", Vec::new())));
+                self.push_inline(string(format!("\n{}", INDENT.repeat(depth))));
+            }
+            Event::Rule => {
+                self.push_block(Node::Tag(tag("hr", Vec::new())));
+            }
+            Event::TaskListMarker(checked) => {
+                self.push_inline(string(if checked {"☑ "} else {"☐ "}));
+            }
+            Event::FootnoteReference(name) => {
+                self.push_inline(Node::Tag(tag("sup", text_nodes(&name))));
+            }
+        }
+    }
+}
+
+fn table_tag(
+    rows: Vec<Vec<Vec<Node<'static>>>>,
+    alignment: &[Alignment],
+    header_rows: usize,
+    depth: usize,
+) -> Tag<'static> {
+    let indent = INDENT.repeat(depth + 1);
+    let mut children = Vec::new();
+    let row_count = rows.len();
+    for (ix, row) in rows.into_iter().enumerate() {
+        children.push(string(format!("\n{}", indent)));
+        for (column, cell) in row.into_iter().enumerate() {
+            if column > 0 {
+                children.push(string(" & "));
+            }
+            children.extend(cell);
+        }
+        if ix + 1 < row_count {
+            children.push(string(" "));
+            children.push(string("\\\\"));
+        }
+    }
+    children.push(string(format!("\n{}", INDENT.repeat(depth))));
+    let mut table = tag("table", children);
+    match header_rows {
+        0 => (),
+        1 => table.insert_unannotated_parameter("header"),
+        n => table.insert_unannotated_parameter(&format!("header={}", n)),
+    }
+    if alignment.iter().any(|x| *x != Alignment::None) {
+        let alignment = alignment
+            .iter()
+            .map(|x| match x {
+                Alignment::Center => 'c',
+                Alignment::Right => 'r',
+                Alignment::Left | Alignment::None => 'l',
+            })
+            .collect::<String>();
+        table.insert_unannotated_parameter(&format!("align={}", alignment));
+    }
+    table
+}
+
+/// Converts the given Markdown document into the frontend AST.
+pub fn markdown_to_ast(source: &str) -> Vec<Node<'static>> {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_MATH;
+    let mut converter = Converter {stack: vec![Scope::new(Frame::Document)]};
+    for event in Parser::new_ext(source, options) {
+        converter.event(event);
+    }
+    let mut document = converter.stack.pop().unwrap();
+    document.flush_inline();
+    let mut nodes = Vec::new();
+    for (ix, block) in document.blocks.into_iter().enumerate() {
+        if ix > 0 {
+            nodes.push(string("\n\n"));
+        }
+        nodes.push(block.node);
+    }
+    nodes.push(string("\n"));
+    nodes
+}
+
+/// Entrypoint. Converts the given Markdown document into Subscript source code.
+pub fn markdown_to_subscript(source: &str) -> String {
+    print_source(&markdown_to_ast(source))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn headings_and_inline_markup() {
+        assert_eq!(
+            markdown_to_subscript("# Title\n\nSome *a*, **b**, ~~c~~ & `d {`."),
+            "\\h1{Title}\n\nSome \\i{a}, \\b{b}, \\s{c} & \\code#{d {}#.\n",
+        );
+    }
+
+    #[test]
+    fn links_images_and_math() {
+        assert_eq!(
+            markdown_to_subscript("[x](https://a.org) ![alt](a.png) $x^2$\n\n$$\\frac{1}{2}$$"),
+            concat!(
+                "\\a[href=https://a.org]{x} \\img[src=a.png] \\{x^2}\n\n",
+                "\\equation{\n    \\frac{1}{2}\n}\n",
+            ),
+        );
+    }
+
+    /// Parentheses & whitespace would end the parameter value.
+    #[test]
+    fn urls_are_percent_encoded() {
+        let source = "[link](https://en.wikipedia.org/wiki/Rust_(programming_language)) ![a](<my image.png>)";
+        let subscript = markdown_to_subscript(source);
+        assert_eq!(subscript, concat!(
+            "\\a[href=https://en.wikipedia.org/wiki/Rust_%28programming_language%29]{link} ",
+            "\\img[src=my%20image.png]\n",
+        ));
+        let html = crate::codegen::html::Document::from_source(&subscript).render_to_string();
+        assert!(html.contains("href=\"https://en.wikipedia.org/wiki/Rust_%28programming_language%29\""));
+        assert!(html.contains("src=\"my%20image.png\""));
+    }
+
+    #[test]
+    fn code_blocks() {
+        assert_eq!(
+            markdown_to_subscript("```rust\nfn f() {\n```"),
+            "\\code[lang=rust]#{\nfn f() {\n}#\n",
+        );
+    }
+
+    #[test]
+    fn lists() {
+        assert_eq!(
+            markdown_to_subscript("- a\n  - b\n- c\n\n3. x\n4. y"),
+            concat!(
+                "\\ul{\n",
+                "    \\li{\n        a\n        \\ul{\n            \\li{b}\n        }\n    }\n",
+                "    \\li{c}\n",
+                "}\n\n",
+                "\\ol[start=3]{\n    \\li{x}\n    \\li{y}\n}\n",
+            ),
+        );
+    }
+
+    #[test]
+    fn tables() {
+        assert_eq!(
+            markdown_to_subscript("| A | B |\n|---|--:|\n| 1 | 2 |"),
+            "\\table[header align=lr]{\n    A & B \\\\\n    1 & 2\n}\n",
+        );
+    }
+
+    /// The output parses back into the same tree.
+    #[test]
+    fn output_parses() {
+        let source = "# A\n\n- *x* `}`\n\n| a | b |\n|---|---|\n| 1 | 2 |\n\n```\n{\n```";
+        let subscript = markdown_to_subscript(source);
+        let reparsed = crate::frontend::pass::pp_normalize::run_compiler_frontend(&subscript);
+        assert_eq!(print_source(&reparsed), subscript);
+    }
+}
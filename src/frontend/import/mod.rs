@@ -0,0 +1,2 @@
+//! Conversion from other markup languages into Subscript source code.
+pub mod markdown;
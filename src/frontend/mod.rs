@@ -2,8 +2,12 @@ use std::rc::Rc;
 pub mod parser;
 pub mod ast;
 pub mod query;
+pub mod visit;
 pub mod pass;
 pub mod data;
+pub mod diagnostic;
+pub mod printer;
+pub mod import;
 
 use ast::Node;
 
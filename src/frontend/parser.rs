@@ -1,15 +1,17 @@
 //! The parser herein is supposed to meet the following criteria:
 //! * real-time parsing (suitable for IDE syntax highlighting).
 //! * zero-copy parsing (only copying pointers).
+//! * linear time, streaming parsing (the words are lexed on demand).
 //! * fault tolerant parsing; again, so it can be used in IDE/text editors.
 //! Eventually I’d like to support incremental parsing as well. 
 use std::rc::Rc;
 use std::borrow::Cow;
-use std::collections::{HashSet, VecDeque, LinkedList};
+use std::collections::{HashSet, VecDeque};
 use std::iter::FromIterator;
 use std::vec;
 use serde::de::value;
-use unicode_segmentation::UnicodeSegmentation;
+use std::iter::Peekable;
+use unicode_segmentation::{GraphemeIndices, UnicodeSegmentation};
 
 use crate::frontend::data::*;
 use crate::frontend::ast::*;
@@ -72,10 +74,23 @@ impl OpenToken {
     }
 }
 
+/// Tracks whether the parser is about to see the body of some verbatim tag
+/// (see `VERBATIM_TAGS`). The payload is the scope depth of the tag, and for
+/// `AfterIdent`, the fence attached to the identifier (e.g. `\verb#{...}#`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum VerbatimState<'a> {
+    Inactive,
+    AfterIdent(usize, &'a str),
+    InParameters(usize),
+}
+
 #[derive(Debug, Clone)]
 struct PartialBlock<'a> {
     open_token: Ann<OpenToken>,
-    children: LinkedList<Node<'a>>,
+    children: Vec<Node<'a>>,
+    /// Whether this enclosure is (within) some math tag, wherein e.g. `\{`
+    /// is a LaTeX escape rather than the start of inline math.
+    is_math: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -87,7 +102,7 @@ enum Branch<'a> {
 #[derive(Debug, Default)]
 pub struct ParseTree<'a> {
     scopes: VecDeque<PartialBlock<'a>>,
-    finalized: LinkedList<Node<'a>>,
+    finalized: Vec<Node<'a>>,
 }
 
 ///////////////////////////////////////////////////////////////////////////////
@@ -98,13 +113,34 @@ impl<'a> ParseTree<'a> {
     fn add_child_node(&mut self, new_node: Node<'a>) {
         match self.scopes.back_mut() {
             Some(scope) => {
-                scope.children.push_back(new_node);
+                scope.children.push(new_node);
             }
             None => {
-                self.finalized.push_back(new_node);
+                self.finalized.push(new_node);
             }
         }
     }
+    fn in_math_scope(&self) -> bool {
+        self.scopes
+            .back()
+            .map(|x| x.is_math)
+            .unwrap_or(false)
+    }
+    /// Whether the last (non-whitespace) node of the current scope is the
+    /// identifier of some math tag (e.g. `\equation` or `\{`).
+    fn follows_math_ident(&self) -> bool {
+        let children = match self.scopes.back() {
+            Some(scope) => &scope.children,
+            None => &self.finalized,
+        };
+        children
+            .iter()
+            .rev()
+            .find(|x| !x.is_whitespace())
+            .and_then(Node::unwrap_ident)
+            .map(|x| x.data == INLINE_MATH_TAG || BLOCK_MATH_TAGS.contains(&x.data.as_ref()))
+            .unwrap_or(false)
+    }
     fn open_new_enclosure(&mut self, new_enclosure: PartialBlock<'a>) {
         self.scopes.push_back(new_enclosure);
     }
@@ -116,7 +152,7 @@ impl<'a> ParseTree<'a> {
                         Cow::Borrowed(scope.open_token.data.as_str()),
                         Cow::Borrowed(close_word.word),
                     ),
-                    children: scope.children.into_iter().collect()
+                    children: scope.children
                 };
                 let range = {
                     let start = scope.open_token.start();
@@ -144,7 +180,7 @@ impl<'a> ParseTree<'a> {
                         open: Cow::Borrowed(scope.open_token.data.as_str()),
                         close: None
                     },
-                    children: scope.children.into_iter().collect()
+                    children: scope.children
                 };
                 Node::Enclosure(Ann::join(
                     scope.open_token.range(),
@@ -152,43 +188,174 @@ impl<'a> ParseTree<'a> {
                 ))
             });
         finalized.extend(xs);
-        finalized.into_iter().collect()
+        finalized
     }
 }
 
+///////////////////////////////////////////////////////////////////////////////
+// VERBATIM BODIES
+///////////////////////////////////////////////////////////////////////////////
+
+fn is_fence(word: &str) -> bool {
+    !word.is_empty() && word.chars().all(|x| x == '#')
+}
+
+/// Splits some identifier such as `verb##` into the name and its fence.
+fn split_fence(ident: &str) -> (&str, &str) {
+    let name = ident.trim_end_matches('#');
+    (name, &ident[name.len()..])
+}
+
+/// Consumes the body of some verbatim tag, where `start` is the opening fence
+/// (or curly brace) and `open` the curly brace.
+///
+/// Without a fence the body ends at the matching (balanced) closing brace,
+/// otherwise it ends at the first `}` followed by the same number of `#`s.
+fn parse_verbatim_body<'a, I: Iterator<Item=Word<'a>>>(
+    words: &mut Words<'a, I>,
+    start: Word<'a>,
+    open: Word<'a>,
+    fence: &str,
+) -> Node<'a> {
+    let mut text = String::new();
+    let mut text_end = open.range.end;
+    let mut last = open;
+    let mut closed = false;
+    let mut depth = 0usize;
+    while let Some(word) = words.next() {
+        last = word;
+        match word.word {
+            "{" if fence.is_empty() => {
+                depth += 1;
+            }
+            "}" if fence.is_empty() && depth > 0 => {
+                depth -= 1;
+            }
+            "}" if fence.is_empty() => {
+                closed = true;
+                break;
+            }
+            "}" if words.peek().map(|x| x.word == fence).unwrap_or(false) => {
+                last = words.next().unwrap();
+                closed = true;
+                break;
+            }
+            _ => ()
+        }
+        text.push_str(word.word);
+        text_end = word.range.end;
+    }
+    let text_range = CharRange::new(open.range.end, text_end);
+    let children = vec![Node::String(Ann::new(text_range, Cow::Owned(text)))];
+    let range = CharRange::new(start.range.start, last.range.end);
+    let kind = if closed {
+        EnclosureKind::CurlyBrace
+    } else {
+        EnclosureKind::Error {
+            open: Cow::Borrowed("{"),
+            close: None,
+        }
+    };
+    Node::new_enclosure(range, kind, children)
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 // CORE PARSER ENGINE
 ///////////////////////////////////////////////////////////////////////////////
 
+/// The words of some lexer, with a lookahead buffer (i.e. for the whitespace
+/// up to the next non-whitespace word).
+struct Words<'a, I> {
+    words: I,
+    buffer: VecDeque<Word<'a>>,
+}
+
+impl<'a, I: Iterator<Item=Word<'a>>> Words<'a, I> {
+    fn next(&mut self) -> Option<Word<'a>> {
+        self.buffer.pop_front().or_else(|| self.words.next())
+    }
+    fn peek(&mut self) -> Option<Word<'a>> {
+        if self.buffer.is_empty() {
+            self.buffer.push_back(self.words.next()?);
+        }
+        self.buffer.front().copied()
+    }
+    /// The next non-whitespace word, and its offset (i.e. the number of words
+    /// up to & including it).
+    fn peek_non_whitespace(&mut self) -> Option<(usize, Word<'a>)> {
+        let mut offset = 0;
+        loop {
+            if offset == self.buffer.len() {
+                self.buffer.push_back(self.words.next()?);
+            }
+            let word = self.buffer[offset];
+            offset += 1;
+            if !word.is_whitespace() {
+                return Some((offset, word))
+            }
+        }
+    }
+    fn skip(&mut self, count: usize) {
+        for _ in 0..count {
+            self.next();
+        }
+    }
+}
 
 impl<'a> ParseTree<'a> {
-    pub fn parse_words(words: Vec<Word<'a>>) -> Vec<Node<'a>> {
+    pub fn parse_words(words: impl IntoIterator<Item=Word<'a>>) -> Vec<Node<'a>> {
+        let mut words = Words {words: words.into_iter(), buffer: VecDeque::new()};
         let mut parse_tree = ParseTree::default();
-        let mut skip_to: Option<usize> = None;
-        for pos in 0..words.len() {
-            if let Some(start_from) = skip_to {
-                if pos <= start_from {
+        let mut verbatim = VerbatimState::Inactive;
+        while let Some(current) = words.next() {
+            // VERBATIM TAG BODIES
+            if let VerbatimState::AfterIdent(depth, fence) = verbatim {
+                let word = current.word;
+                let is_next_open = words
+                    .peek()
+                    .map(|x| x.word == "{")
+                    .unwrap_or(false);
+                let is_body = match word {
+                    "{" => true,
+                    _ => fence.is_empty() && is_fence(word) && is_next_open,
+                };
+                if is_body && depth == parse_tree.scopes.len() {
+                    let (open, fence) = match word {
+                        "{" => (current, fence),
+                        _ => (words.next().unwrap(), word),
+                    };
+                    let node = parse_verbatim_body(&mut words, current, open, fence);
+                    parse_tree.add_child_node(node);
+                    verbatim = VerbatimState::Inactive;
                     continue;
-                } else {
-                    skip_to = None;
+                }
+                if word == "[" {
+                    verbatim = VerbatimState::InParameters(depth);
+                } else if !current.is_whitespace() {
+                    verbatim = VerbatimState::Inactive;
                 }
             }
-            let forward = |by: usize| {
-                words
-                    .get(pos + by)
-                    .filter(|w| !w.is_whitespace())
-                    .map(|w| (by, w))
-            };
-            let current = &words[pos];
-            let next = {
-                let mut entry = None::<(usize, &Word)>;
-                let words_left = words.len() - pos;
-                for offset in 1..words_left {
-                    assert!(entry.is_none());
-                    entry = forward(offset);
-                    if entry.is_some() {break}
+            // ESCAPE SEQUENCES (E.G. `\}`)
+            if current.word == "\\" {
+                let in_math = parse_tree.in_math_scope();
+                let escape = words
+                    .peek()
+                    .filter(|x| x.word != "{" || in_math)
+                    .and_then(|x| escape_sequence(x.word).map(|escape| (x, escape)));
+                if let Some((next, escape)) = escape {
+                    words.next();
+                    let range = CharRange::new(current.range.start, next.range.end);
+                    parse_tree.add_child_node(Node::String(Ann::new(
+                        range,
+                        Cow::Borrowed(escape),
+                    )));
+                    continue;
                 }
-                entry
+            }
+            // ONLY IDENTIFIERS LOOK PAST THE CURRENT WORD
+            let next = match current.word {
+                "\\" => words.peek_non_whitespace(),
+                _ => None,
             };
             let (mode, consumed) = match_word(
                 current.word,
@@ -196,20 +363,28 @@ impl<'a> ParseTree<'a> {
             );
             match mode {
                 Mode::BeginEnclosure {kind} => {
-                    let start_pos = current.range.start;
                     let new_stack = PartialBlock {
                         open_token: Ann::new(
                             current.range,
                             OpenToken::new(Cow::Borrowed(kind)).unwrap()
                         ),
                         children: Default::default(),
+                        is_math: parse_tree.in_math_scope() || parse_tree.follows_math_ident(),
                     };
                     parse_tree.open_new_enclosure(new_stack);
                 }
                 Mode::EndEnclosure {kind: close_token} => {
-                    parse_tree.close_last_enclosure(current);
+                    parse_tree.close_last_enclosure(&current);
+                    if verbatim == VerbatimState::InParameters(parse_tree.scopes.len()) {
+                        verbatim = VerbatimState::AfterIdent(parse_tree.scopes.len(), "");
+                    }
                 }
                 Mode::Ident(ident) => {
+                    let (name, fence) = split_fence(ident);
+                    if VERBATIM_TAGS.contains(&name) {
+                        verbatim = VerbatimState::AfterIdent(parse_tree.scopes.len(), fence);
+                    }
+                    let ident = if VERBATIM_TAGS.contains(&name) {name} else {ident};
                     let start = current.range.start;
                     let end = next
                         .map(|x| x.1.range.end)
@@ -236,8 +411,7 @@ impl<'a> ParseTree<'a> {
                 ZipperConsumed::Current => (),
                 ZipperConsumed::Right => {
                     assert!(next.is_some());
-                    let offset = next.unwrap().0;
-                    skip_to = Some(pos + offset);
+                    words.skip(next.unwrap().0);
                 }
             }
         }
@@ -254,167 +428,99 @@ impl<'a> ParseTree<'a> {
 
 // MAIN ENTRYPOINT FOR STRING TO PARSER AST 
 pub fn parse_source<'a>(source: &'a str) -> Vec<Node<'a>> {
-    let words = init_words(source, init_characters(source));
-    ParseTree::parse_words(words)
+    ParseTree::parse_words(Lexer::new(source))
 }
 
 
 ///////////////////////////////////////////////////////////////////////////////
-// DEV
+// LEXER
 ///////////////////////////////////////////////////////////////////////////////
 
-fn process_word<'a>(values: Vec<(CharIndex, &'a str)>) -> Vec<Vec<(CharIndex, &'a str)>> {
-    use itertools::Itertools;
-    values
-        .into_iter()
-        .group_by(|(_, char)| {
-            let char: &str = char;
-            match char {
-                "\\" => true,
-                "{" => true,
-                "}" => true,
-                "[" => true,
-                "]" => true,
-                "(" => true,
-                ")" => true,
-                "=" => true,
-                ">" => true,
-                "_" => true,
-                "^" => true,
-                _ => false
-            }
-        })
-        .into_iter()
-        .flat_map(|(key, group)| -> Vec<Vec<(CharIndex, &str)>> {
-            if key == true {
-                group
-                    .into_iter()
-                    .map(|(ix, ch)| {
-                        vec![(ix, ch)]
-                    })
-                    .collect::<Vec<_>>()
-            } else {
-                vec![group.into_iter().collect::<Vec<_>>()]
-            }
-        })
-        .collect_vec()
-}
-
-
-
-#[derive(Debug, Clone)]
-pub struct Character<'a> {
+#[derive(Debug, Clone, Copy)]
+pub struct Word<'a> {
     range: CharRange,
-    char: &'a str,
+    word: &'a str,
 }
 
-impl<'a> Character<'a> {
+impl<'a> Word<'a> {
+    pub fn range(&self) -> CharRange {
+        self.range
+    }
+    pub fn as_str(&self) -> &'a str {
+        self.word
+    }
     pub fn is_whitespace(&self) -> bool {
-        self.char.chars().any(|x| x.is_whitespace())
+        self.word.trim().is_empty()
     }
 }
 
-pub fn init_characters<'a>(source: &'a str) -> Vec<Character<'a>> {
-    use itertools::Itertools;
-    let ending_byte_size = source.len();
-    let words = source
-        .grapheme_indices(true)
-        .enumerate()
-        .map(|(cix, (bix, x))| {
-            let index = CharIndex {
-                byte_index: bix,
-                char_index: cix,
-            };
-            (index, x)
-        })
-        .collect_vec();
-    let mut output = Vec::new();
-    for pos in 0..words.len() {
-        let (start, current) = words[pos];
-        let end = words
-            .get(pos + 1)
-            .map(|(pos, _)| *pos)
-            .unwrap_or_else(|| {
-                CharIndex {
-                    byte_index: ending_byte_size,
-                    char_index: pos + 1
-                }
-            });
-        output.push(Character{
-            range: CharRange{ start, end},
-            char: current
-        });
+/// Whether the given grapheme is a word on its own.
+fn is_separator(char: &str) -> bool {
+    if char.chars().any(|x| x.is_whitespace()) {
+        return true
+    }
+    match char {
+        "\\" => true,
+        "{" => true,
+        "}" => true,
+        "[" => true,
+        "]" => true,
+        "(" => true,
+        ")" => true,
+        "=" => true,
+        ">" => true,
+        "_" => true,
+        "." => true,
+        "^" => true,
+        // INLINE FORMATTING MARKERS (SO THEY MAY BE ESCAPED)
+        "*" => true,
+        "`" => true,
+        _ => false
     }
-    output
 }
 
-#[derive(Debug, Clone)]
-pub struct Word<'a> {
-    range: CharRange,
-    word: &'a str,
+/// Splits the source into words, on demand: whitespace & the tokens of
+/// `is_separator` are (single grapheme) words on their own, and the graphemes
+/// in between form one word. Ranges count graphemes (as `char_index`).
+pub struct Lexer<'a> {
+    source: &'a str,
+    graphemes: Peekable<GraphemeIndices<'a>>,
+    /// The `char_index` of the next grapheme.
+    char_index: usize,
 }
 
-impl<'a> Word<'a> {
-    pub fn is_whitespace(&self) -> bool {
-        self.word.trim().is_empty()
+impl<'a> Lexer<'a> {
+    pub fn new(source: &'a str) -> Self {
+        Lexer {
+            source,
+            graphemes: source.grapheme_indices(true).peekable(),
+            char_index: 0,
+        }
+    }
+    fn next_char(&mut self) -> Option<(CharRange, &'a str)> {
+        let (byte_index, char) = self.graphemes.next()?;
+        let start = CharIndex {byte_index, char_index: self.char_index};
+        self.char_index += 1;
+        let end = CharIndex {
+            byte_index: byte_index + char.len(),
+            char_index: self.char_index,
+        };
+        Some((CharRange {start, end}, char))
     }
 }
 
-pub fn init_words<'a>(source: &'a str, chars: Vec<Character<'a>>) -> Vec<Word<'a>> {
-    use itertools::Itertools;
-    // let mut output = Vec::new();
-    let mut current_word_start = 0usize;
-    chars
-        .into_iter()
-        .group_by(|char| {
-            if char.is_whitespace() {
-                return true
-            }
-            match char.char {
-                "\\" => true,
-                "{" => true,
-                "}" => true,
-                "[" => true,
-                "]" => true,
-                "(" => true,
-                ")" => true,
-                "=" => true,
-                ">" => true,
-                "_" => true,
-                "." => true,
-                "^" => true,
-                _ => false
-            }
-        })
-        .into_iter()
-        .flat_map(|(key, chars)| {
-            let chars = chars.into_iter().collect_vec();
-            if key || chars.len() < 2 {
-                let chars = chars
-                    .into_iter()
-                    .map(|char| {
-                        Word {
-                            range: char.range,
-                            word: char.char,
-                        }
-                    })
-                    .collect_vec();
-                return chars;
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Word<'a>;
+    fn next(&mut self) -> Option<Word<'a>> {
+        let (mut range, char) = self.next_char()?;
+        if !is_separator(char) {
+            while self.graphemes.peek().map(|(_, x)| !is_separator(x)).unwrap_or(false) {
+                range.end = self.next_char().unwrap().0.end;
             }
-            let start = {
-                (&chars[0]).range.start
-            };
-            let end = {
-                (&chars[chars.len() - 1]).range.end
-            };
-            let word = &source[start.byte_index..end.byte_index];
-            let word = Word {
-                range: CharRange{start, end},
-                word,
-            };
-            vec![word]
-        })
-        .collect::<Vec<_>>()
+        }
+        let word = &self.source[range.start.byte_index..range.end.byte_index];
+        Some(Word {range, word})
+    }
 }
 
 fn match_word<'a>(current: &'a str, next: Option<&'a str>) -> (Mode<'a>, ZipperConsumed) {
@@ -455,6 +561,80 @@ fn match_word<'a>(current: &'a str, next: Option<&'a str>) -> (Mode<'a>, ZipperC
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text of the top level string nodes.
+    fn strings(source: &str) -> Vec<String> {
+        parse_source(source)
+            .iter()
+            .filter_map(Node::unwrap_string)
+            .map(|x| x.data.to_string())
+            .collect()
+    }
+
+    /// The block body of the given verbatim tag.
+    fn verbatim_body(source: &str) -> String {
+        let nodes = parse_source(source);
+        match nodes.last() {
+            Some(Node::Enclosure(block)) if block.data.is_curly_brace() => block.data.children
+                .iter()
+                .map(Node::to_string)
+                .collect(),
+            _ => panic!("not a verbatim tag: {:?}", nodes),
+        }
+    }
+
+    #[test]
+    fn escape_sequences() {
+        assert_eq!(strings("a \\} b\\]\\\\"), vec!["a", " ", "\\}", " ", "b", "\\]", "\\\\"]);
+    }
+
+    #[test]
+    fn verbatim_bodies() {
+        assert_eq!(verbatim_body("\\verb{a {b} \\c}"), "a {b} \\c");
+        assert_eq!(verbatim_body("\\code[lang=rust]{fn main() {}}").trim(), "fn main() {}");
+    }
+
+    #[test]
+    fn fenced_verbatim_bodies() {
+        assert_eq!(verbatim_body("\\verb#{x}}#"), "x}");
+        assert_eq!(verbatim_body("\\verb##{a }# b}##"), "a }# b");
+    }
+
+    fn words(source: &str) -> Vec<&str> {
+        Lexer::new(source).map(|x| x.as_str()).collect()
+    }
 
+    #[test]
+    fn lexer_words() {
+        assert_eq!(words("\\h1{Hello world}"), vec!["\\", "h1", "{", "Hello", " ", "world", "}"]);
+        assert_eq!(words("x^2 = a_b.c"), vec!["x", "^", "2", " ", "=", " ", "a", "_", "b", ".", "c"]);
+        assert_eq!(words(""), Vec::<&str>::new());
+    }
 
+    #[test]
+    fn lexer_ranges_count_graphemes() {
+        let ranges = Lexer::new("é👍🏽 ab")
+            .map(|x| (x.range().start.char_index, x.range().end.char_index, x.range().end.byte_index))
+            .collect::<Vec<_>>();
+        assert_eq!(ranges, vec![(0, 2, 10), (2, 3, 11), (3, 5, 13)]);
+    }
+
+    #[test]
+    fn parse_words_matches_parse_source() {
+        let source = "\\note{\\h1{A} [b] (c) \\{x^2}}";
+        let nodes = ParseTree::parse_words(Lexer::new(source));
+        assert_eq!(format!("{:?}", nodes), format!("{:?}", parse_source(source)));
+    }
+
+    #[test]
+    fn unbalanced_enclosures_are_kept_as_written() {
+        let print = |source| crate::frontend::printer::print_source(&parse_source(source));
+        assert_eq!(print("\\note{\\h1{A} [b"), "\\note{\\h1{A} [b");
+        assert_eq!(print("}])"), "}])");
+        assert_eq!(print("\\verb{"), "\\verb{");
+    }
+}
 
@@ -0,0 +1,171 @@
+//! Code listings & literal text (i.e. the verbatim `\code`, `\pre` & `\verb` tags).
+//!
+//! ```text
+//! \code[lang=rust line-numbers highlight=2,4-5]{
+//!     fn main() {
+//!         println!("Hello world");
+//!     }
+//! }
+//! ```
+//!
+//! The `highlight` lines are numbered as displayed, i.e. counting from the
+//! `start` line (if given).
+//!
+//! Use a fenced body (e.g. `\code[lang=c]#{ ... }#`) when the code contains
+//! unbalanced curly braces.
+use std::rc::Rc;
+use std::borrow::Cow;
+use crate::frontend::data::*;
+use crate::frontend::ast::*;
+use crate::codegen::highlight::{self, CodeBlockOptions};
+use crate::codegen::html::escape_html;
+
+/// Removes the leading & trailing blank lines and the common indentation.
+pub(crate) fn normalize_code(code: &str) -> String {
+    let lines = code
+        .lines()
+        .skip_while(|x| x.trim().is_empty())
+        .collect::<Vec<_>>();
+    let end = lines
+        .iter()
+        .rposition(|x| !x.trim().is_empty())
+        .map(|x| x + 1)
+        .unwrap_or(0);
+    let lines = &lines[..end];
+    let indent = lines
+        .iter()
+        .filter(|x| !x.trim().is_empty())
+        .map(|x| x.len() - x.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|x| x.get(indent..).unwrap_or_else(|| x.trim_start()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn code_block_options(tag: &Tag) -> CodeBlockOptions {
+    CodeBlockOptions {
+        language: tag
+            .get_parameter_value("lang")
+            .or_else(|| tag.get_parameter_value("language")),
+        line_numbers: tag.has_parameter("line-numbers") || tag.has_parameter("numbers"),
+        start_line: tag
+            .get_parameter_value("start")
+            .and_then(|x| x.parse::<usize>().ok())
+            .unwrap_or(1),
+        highlighted_lines: tag
+            .get_parameter_value("highlight")
+            .map(|x| highlight::parse_line_ranges(&x))
+            .unwrap_or_default(),
+    }
+}
+
+fn lower_code_tag(mut tag: Tag) -> Node {
+    let raw = std::mem::take(&mut tag.children)
+        .into_iter()
+        .flat_map(Node::unblock)
+        .map(|x| x.to_string())
+        .collect::<String>();
+    if tag.has_name("verb") {
+        // Backslashes are encoded so that later passes don’t mistake the
+        // text for an escape sequence.
+        let text = escape_html(&raw).replace('\\', "&#92;");
+        return Node::String(Ann::join(tag.name.range(), Cow::Owned(text)))
+    }
+    let options = code_block_options(&tag);
+    let is_inline = tag.has_name("code")
+        && options.language.is_none()
+        && !raw.trim().contains('\n');
+    if is_inline {
+        let code = escape_html(raw.trim());
+        let mut node = Tag::new(tag.name.map(|_| "code"), vec![
+            Node::String(Ann::unannotated(Cow::Owned(code)))
+        ]);
+        node.rewrite_rules = tag.rewrite_rules;
+        return Node::Tag(node)
+    }
+    let html = highlight::highlight_to_html(&normalize_code(&raw), &options);
+    let mut node = Tag::new(tag.name.map(|_| "pre"), vec![
+        Node::String(Ann::unannotated(Cow::Owned(html)))
+    ]);
+    node.insert_unannotated_parameter("macro=code");
+    if let Some(language) = options.language.as_ref() {
+        node.insert_unannotated_parameter(&format!("lang={}", language));
+    }
+    if options.line_numbers {
+        node.insert_unannotated_parameter("line-numbers");
+    }
+    Node::Tag(node)
+}
+
+/// Entrypoint.
+pub fn code_pass<'a>(node: Node<'a>) -> Node<'a> {
+    let f = |env: NodeEnvironment<'a>, node: Node<'a>| -> Node<'a> {
+        match node {
+            Node::Tag(tag) if VERBATIM_TAGS.contains(&tag.name()) && env.is_default_env() => {
+                lower_code_tag(tag)
+            }
+            node => node,
+        }
+    };
+    node.transform(NodeEnvironment::default(), Rc::new(f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::pass::pp_normalize::run_compiler_frontend;
+    use crate::frontend::printer::print_source;
+
+    fn lower(source: &str) -> String {
+        let nodes = run_compiler_frontend(source)
+            .into_iter()
+            .map(code_pass)
+            .collect::<Vec<_>>();
+        print_source(&nodes)
+    }
+
+    #[test]
+    fn normalized_code() {
+        assert_eq!(normalize_code("\n\n    a\n      b\n\n    c\n  \n"), "a\n  b\n\nc");
+    }
+
+    #[test]
+    fn inline_code() {
+        assert_eq!(lower("\\code{ a < b }"), "\\code{a &lt; b}");
+    }
+
+    #[test]
+    fn code_blocks() {
+        assert_eq!(
+            lower("\\code[lang=rust line-numbers]{\n    fn f() {}\n}"),
+            concat!(
+                "\\pre[macro=code lang=rust line-numbers]{<code class=\"language-rust\">",
+                "<span class=\"line\"><span class=\"line-number\">1</span>",
+                "<span class=\"hl-keyword\">fn</span> f() {}</span>\n",
+                "</code>}",
+            ),
+        );
+    }
+
+    #[test]
+    fn fenced_bodies_may_be_unbalanced() {
+        assert_eq!(
+            lower("\\code[lang=c]#{ if (x) { }#"),
+            concat!(
+                "\\pre[macro=code lang=c]#{<code class=\"language-c\">",
+                "<span class=\"line\"><span class=\"hl-keyword\">if</span> (x) { </span>\n",
+                "</code>}#",
+            ),
+        );
+    }
+
+    #[test]
+    fn literal_text() {
+        assert_eq!(lower("\\verb{a\\b <c>}"), "a&#92;b &lt;c&gt;");
+        // NOT AN ESCAPE SEQUENCE WITHIN \verb
+        assert_eq!(lower("\\verb{\\\\}"), "&#92;&#92;");
+    }
+}
@@ -0,0 +1,371 @@
+//! Glossary & index generation.
+//!
+//! ```text
+//! A \term[group]{group}{A set with an associative operation, an identity
+//! & inverses.} is ...; the \term[group]{groups} of order two ...
+//! \index{Lagrange's theorem}
+//! ```
+//!
+//! Every `\term` occurrence becomes an anchor (e.g. `term-group-2` for the
+//! second occurrence of `group`), and every `\index` an invisible anchor
+//! (e.g. `index-3`). The glossary (i.e. the defined terms) and the
+//! alphabetical index (i.e. the terms & index entries) link back to every
+//! occurrence.
+//!
+//! Both are appended to the document (see `glossary_pass`), built as nodes
+//! (see `glossary_nodes`). The entries of several documents are combined
+//! into the glossary of every page of a site (see `Settings::glossary` &
+//! `rooted`), or into a chapter of its own (see
+//! `codegen::epub::Book::add_glossary`).
+use std::collections::{BTreeMap, HashMap};
+use crate::frontend::ast::*;
+use crate::codegen::settings::{Settings, Solutions};
+use crate::frontend::data::{EnclosureKind, INLINE_MATH_TAG, BLOCK_MATH_TAGS};
+use crate::frontend::query::{Term, TermKind};
+use crate::frontend::visit::{VisitorMut, walk_tag_mut};
+
+/// A `\term` or `\index` occurrence.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub term: Term,
+    /// The link to the occurrence, e.g. `#term-group-1` or
+    /// `chapter-2.xhtml#term-group-1`.
+    pub href: String,
+}
+
+fn slug(key: &str) -> String {
+    key
+        .to_lowercase()
+        .split(|x: char| !x.is_alphanumeric())
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Assigns the anchors, in document order.
+#[derive(Default)]
+struct Anchors {
+    terms: HashMap<String, usize>,
+    index: usize,
+}
+
+impl Anchors {
+    fn next(&mut self, term: &Term) -> String {
+        match term.kind {
+            TermKind::Term => {
+                let key = slug(&term.key);
+                let counter = self.terms.entry(key.clone()).or_insert(0);
+                *counter += 1;
+                format!("term-{}-{}", key, counter)
+            }
+            TermKind::Index => {
+                self.index += 1;
+                format!("index-{}", self.index)
+            }
+        }
+    }
+}
+
+/// Lowers the `\term` & `\index` tags (outside of math) into anchors, in
+/// document order, i.e. the outer of two nested terms is numbered first.
+#[derive(Default)]
+struct TermLowering {
+    anchors: Anchors,
+    occurrences: Vec<(Term, String)>,
+    /// Skips the `\solution` blocks, which the notes pass removes.
+    strip_solutions: bool,
+}
+
+impl<'a> VisitorMut<'a> for TermLowering {
+    fn visit_tag_mut(&mut self, tag: &mut Tag<'a>) {
+        if tag.name() == INLINE_MATH_TAG || BLOCK_MATH_TAGS.contains(&tag.name()) {
+            return
+        }
+        if self.strip_solutions && tag.has_name("solution") {
+            return
+        }
+        if let Some(term) = Term::from_tag(tag) {
+            let id = self.anchors.next(&term);
+            let children = match term.kind {
+                TermKind::Term => std::mem::take(&mut tag.children)
+                    .into_iter()
+                    .next()
+                    .map(Node::unblock)
+                    .unwrap_or_default(),
+                TermKind::Index => Vec::new(),
+            };
+            let mut span = Tag::new(Ann::join(tag.name.range(), "span"), children);
+            match term.kind {
+                TermKind::Term => span.insert_unannotated_parameter(&format!("term={}", slug(&term.key))),
+                TermKind::Index => span.insert_unannotated_parameter("index-entry"),
+            }
+            span.insert_unannotated_parameter(&format!("id={}", id));
+            *tag = span;
+            self.occurrences.push((term, id));
+        }
+        walk_tag_mut(self, tag);
+    }
+}
+
+/// Lowers the `\term` & `\index` tags into anchors (in place), returning the
+/// occurrences & their anchor ids. The single source of the numbering, so
+/// that the `entries` match the anchors of `term_pass`.
+pub fn lower_terms<'a>(children: &mut [Node<'a>], settings: &Settings) -> Vec<(Term, String)> {
+    let mut lowering = TermLowering {
+        strip_solutions: settings.solutions == Solutions::Strip,
+        ..Default::default()
+    };
+    for child in children.iter_mut() {
+        lowering.visit_node_mut(child);
+    }
+    lowering.occurrences
+}
+
+/// Entrypoint; lowers the `\term` & `\index` tags, and collects their
+/// occurrences (see `glossary_pass`).
+pub fn term_pass<'a>(mut children: Vec<Node<'a>>, settings: &Settings, entries: &mut Vec<Entry>) -> Vec<Node<'a>> {
+    let occurrences = lower_terms(&mut children, settings);
+    entries.extend(to_entries(occurrences, ""));
+    children
+}
+
+fn to_entries(occurrences: Vec<(Term, String)>, file_name: &str) -> Vec<Entry> {
+    occurrences
+        .into_iter()
+        .map(|(term, id)| Entry {term, href: format!("{}#{}", file_name, id)})
+        .collect()
+}
+
+/// The `\term` & `\index` occurrences of the given source; `file_name` is
+/// the compiled document the anchors link to (if not the current one).
+pub fn entries(source: &str, settings: &Settings, file_name: Option<&str>) -> Vec<Entry> {
+    let mut children = crate::frontend::pass::pp_normalize::run_compiler_frontend(source);
+    let occurrences = lower_terms(&mut children, settings);
+    to_entries(occurrences, file_name.unwrap_or(""))
+}
+
+/// The given entries (e.g. of every page of a site), where `root` prefixes
+/// their links (e.g. `../`, see `site::Page::root`).
+pub fn rooted(entries: &[Entry], root: &str) -> Vec<Entry> {
+    entries
+        .iter()
+        .map(|x| Entry {term: x.term.clone(), href: format!("{}{}", root, x.href)})
+        .collect()
+}
+
+fn text(value: &str) -> Node<'static> {
+    Node::unannotated_string(value.to_owned())
+}
+
+/// The given tag, as parsed (i.e. with its children in a curly brace block).
+fn tag(name: &'static str, parameters: &[String], children: Vec<Node<'static>>) -> Node<'static> {
+    let block = Node::unannotated_enclosure(EnclosureKind::CurlyBrace, children);
+    let mut tag = Tag::new(Ann::unannotated(name), vec![block]);
+    for parameter in parameters {
+        tag.insert_unannotated_parameter(parameter);
+    }
+    Node::Tag(tag)
+}
+
+/// The display text or definition of a `Term` (i.e. Subscript source).
+fn fragment(source: &str) -> Vec<Node<'static>> {
+    crate::frontend::pass::pp_normalize::run_compiler_frontend(source)
+        .into_iter()
+        .map(Node::into_owned)
+        .collect()
+}
+
+/// E.g. `\a[href=#term-group-1]{1}, \a[href=#term-group-2]{2}`.
+fn back_links(entries: &[&Entry]) -> Vec<Node<'static>> {
+    let mut links = Vec::new();
+    for (ix, entry) in entries.iter().enumerate() {
+        if ix > 0 {
+            links.push(text(", "));
+        }
+        let href = format!("href={}", entry.href);
+        links.push(tag("a", &[href], vec![text(&(ix + 1).to_string())]));
+    }
+    links
+}
+
+/// The glossary & index sections for the given entries; empty if there are
+/// none. Built as nodes, so the keys & links are never parsed.
+pub fn glossary_nodes(entries: &[Entry]) -> Vec<Node<'static>> {
+    let mut nodes = Vec::new();
+    // GLOSSARY
+    let mut terms: BTreeMap<String, Vec<&Entry>> = BTreeMap::new();
+    for entry in entries.iter().filter(|x| x.term.kind == TermKind::Term) {
+        terms.entry(entry.term.key.to_lowercase()).or_default().push(entry);
+    }
+    if !terms.is_empty() {
+        let mut items = Vec::new();
+        for occurrences in terms.values() {
+            let defining = occurrences
+                .iter()
+                .find(|x| x.term.definition.is_some())
+                .unwrap_or(&occurrences[0]);
+            let id = format!("id=glossary-{}", slug(&defining.term.key));
+            items.push(tag("dt", &[id], fragment(&defining.term.text)));
+            let mut definition = Vec::new();
+            if let Some(x) = defining.term.definition.as_ref() {
+                definition.extend(fragment(x));
+                definition.push(text(" "));
+            }
+            let back_links = back_links(occurrences);
+            definition.push(tag("span", &[String::from("back-links")], back_links));
+            items.push(tag("dd", &[], definition));
+        }
+        nodes.push(tag("h2", &[], vec![text("Glossary")]));
+        nodes.push(tag("dl", &[String::from("id=glossary")], items));
+    }
+    // INDEX
+    let mut index: BTreeMap<String, (Vec<Node>, Vec<&Entry>)> = BTreeMap::new();
+    for entry in entries {
+        let (key, text) = match entry.term.kind {
+            // THE KEY DEFAULTS TO THE DISPLAY TEXT (I.E. SUBSCRIPT SOURCE)
+            TermKind::Term if entry.term.key != entry.term.text => {
+                (entry.term.key.to_lowercase(), vec![text(&entry.term.key)])
+            }
+            _ => (entry.term.text.to_lowercase(), fragment(&entry.term.text)),
+        };
+        index
+            .entry(key)
+            .or_insert_with(|| (text, Vec::new()))
+            .1
+            .push(entry);
+    }
+    if !index.is_empty() {
+        let mut letters: BTreeMap<String, Vec<Node>> = BTreeMap::new();
+        for (key, (mut item, occurrences)) in index {
+            let letter = key.chars().next().map(|x| x.to_uppercase().collect()).unwrap_or_default();
+            item.push(text(" "));
+            item.extend(back_links(&occurrences));
+            letters.entry(letter).or_default().push(tag("li", &[], item));
+        }
+        let items = letters
+            .into_iter()
+            .flat_map(|(letter, items)| vec![
+                tag("dt", &[], vec![text(&letter)]),
+                tag("dd", &[], vec![tag("ul", &[], items)]),
+            ])
+            .collect();
+        nodes.push(tag("h2", &[], vec![text("Index")]));
+        nodes.push(tag("dl", &[String::from("id=index")], items));
+    }
+    nodes
+}
+
+/// Entrypoint; appends the glossary & index of the given entries (i.e. of
+/// the document itself, see `term_pass`, or e.g. of a whole site).
+pub fn glossary_pass<'a>(mut children: Vec<Node<'a>>, entries: &[Entry]) -> Vec<Node<'a>> {
+    children.extend(glossary_nodes(entries));
+    children
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::pass::pp_normalize::run_compiler_frontend;
+    use crate::frontend::printer::print_source;
+
+    fn links(source: &str, settings: &Settings) -> Vec<String> {
+        entries(source, settings, None)
+            .into_iter()
+            .map(|x| x.href)
+            .collect()
+    }
+
+    #[test]
+    fn anchors() {
+        let mut nodes = run_compiler_frontend(
+            "\\term[group]{groups}{A set.} \\index{Lagrange} \\term{group} \\{\\term{x}}",
+        );
+        lower_terms(&mut nodes, &Settings::default());
+        assert_eq!(print_source(&nodes), concat!(
+            "\\span[term=group id=term-group-1]{groups} ",
+            "\\span[index-entry id=index-1] ",
+            "\\span[term=group id=term-group-2]{group} ",
+            "\\{\\term{x}}",
+        ));
+    }
+
+    #[test]
+    fn nested_terms_are_numbered_outside_in() {
+        assert_eq!(
+            links("\\term{a \\term{a}} \\term{a}", &Settings::default()),
+            vec!["#term-a-a-1", "#term-a-1", "#term-a-2"],
+        );
+    }
+
+    #[test]
+    fn term_keys() {
+        let source = "\\term[group]{a} \\term[key = 'group']{b} \\term[id=x]{Groups} \\index[y]{Z}";
+        let keys = entries(source, &Settings::default(), None)
+            .into_iter()
+            .map(|x| x.term.key)
+            .collect::<Vec<_>>();
+        assert_eq!(keys, vec!["group", "group", "Groups", "Z"]);
+    }
+
+    #[test]
+    fn stripped_solutions() {
+        let source = "\\term{a} \\solution{\\term{b}} \\term{b}";
+        let settings = Settings {solutions: Solutions::Strip, ..Settings::default()};
+        assert_eq!(links(source, &settings), vec!["#term-a-1", "#term-b-1"]);
+        assert_eq!(
+            links(source, &Settings::default()),
+            vec!["#term-a-1", "#term-b-1", "#term-b-2"],
+        );
+    }
+
+    #[test]
+    fn glossary_and_index() {
+        let source = "\\term[group]{groups}{A set.} \\index{Lagrange} \\term{Group} \\index{abel}";
+        let glossary = glossary_nodes(&entries(source, &Settings::default(), None));
+        assert_eq!(print_source(&glossary), concat!(
+            "\\h2{Glossary}\\dl[id=glossary]{",
+            "\\dt[id=glossary-group]{groups}",
+            "\\dd{A set. \\span[back-links]{\\a[href=#term-group-1]{1}, \\a[href=#term-group-2]{2}}}",
+            "}",
+            "\\h2{Index}\\dl[id=index]{",
+            "\\dt{A}\\dd{\\ul{\\li{abel \\a[href=#index-2]{1}}}}",
+            "\\dt{G}\\dd{\\ul{",
+            "\\li{group \\a[href=#term-group-1]{1}, \\a[href=#term-group-2]{2}}",
+            "}}",
+            "\\dt{L}\\dd{\\ul{\\li{Lagrange \\a[href=#index-1]{1}}}}",
+            "}",
+        ));
+        assert!(glossary_nodes(&[]).is_empty());
+    }
+
+    #[test]
+    fn every_backend_appends_the_glossary() {
+        let source = "\\p{A \\term{word}{Some \\b{text}.}.}";
+        let html = crate::codegen::html::Document::from_source(source).render_to_string();
+        assert!(html.contains("<dt id=\"glossary-word\">word</dt>"));
+        assert!(html.contains("<dd>Some <b>text</b>. <span back-links=\"\">"));
+        let markdown = crate::codegen::markdown::Document::from_source(source).render_to_string();
+        assert!(markdown.contains("Glossary"));
+        let slides = crate::codegen::slides::Document::from_source(source).render_to_string();
+        assert!(slides.contains("<a href=\"#term-word-1\">1</a>"));
+        let settings = Settings {glossary: Some(Vec::new()), ..Settings::default()};
+        let html = crate::compiler::Compiler::new().settings(settings).compile(source).render_to_string();
+        assert!(!html.contains("Glossary"));
+    }
+
+    #[test]
+    fn entries_of_other_pages() {
+        let entries = entries(
+            "\\term{x (y) [z]} \\term[key=k]{a} \\index{[b]}",
+            &Settings::default(),
+            Some("my notes/a.html"),
+        );
+        let settings = Settings {glossary: Some(rooted(&entries, "../")), ..Settings::default()};
+        let html = crate::compiler::Compiler::new().settings(settings).compile("x").render_to_string();
+        assert!(html.contains("<dt id=\"glossary-x-y-z\">x (y) [z]</dt>"));
+        assert!(html.contains("<li>x (y) [z] <a href=\"../my notes/a.html#term-x-y-z-1\">1</a></li>"));
+        assert!(html.contains("<li>k <a href=\"../my notes/a.html#term-k-1\">1</a></li>"));
+        assert!(html.contains("<li>[b] <a href=\"../my notes/a.html#index-1\">1</a></li>"));
+        assert!(!html.contains("href=\"#term"));
+    }
+}
@@ -12,71 +12,81 @@ use std::convert::TryFrom;
 use either::Either;
 use crate::frontend::data::*;
 use crate::frontend::ast::*;
+use crate::frontend::visit::{Visitor, VisitorMut, walk_tag, walk_tag_mut};
+use crate::codegen::settings::Settings;
 
 ///////////////////////////////////////////////////////////////////////////////
 // TABLE OF CONTENTS
 ///////////////////////////////////////////////////////////////////////////////
 
-fn generate_toc_heading_id_from_child_nodes<'a>(children: &Vec<Node<'a>>) -> String {
+/// The `id` of the heading with the given (compiled) children.
+pub fn heading_id<'a>(children: &[Node<'a>]) -> String {
+    generate_toc_heading_id_from_child_nodes(children)
+}
+
+fn generate_toc_heading_id_from_child_nodes<'a>(children: &[Node<'a>]) -> String {
     use pct_str::PctStr;
     let contents = generate_toc_heading_title_from_child_nodes(children);
     let pct_str = PctStr::new(&contents).unwrap();
     pct_str.as_str().to_owned()
 }
 
-fn generate_toc_heading_title_from_child_nodes<'a>(children: &Vec<Node<'a>>) -> String {
+fn generate_toc_heading_title_from_child_nodes<'a>(children: &[Node<'a>]) -> String {
     children.iter()
         .map(Node::to_string)
         .collect::<Vec<_>>()
         .join("")
 }
 
-fn get_headings<'a>(node: &Node<'a>) -> Vec<(Vec<String>, String, String, String)> {
-    let headings = Rc::new(RefCell::new(Vec::new()));
-    let f = {
-        let headings = headings.clone();
-        move |env: NodeEnvironment<'a>, node: Node<'a>| {
-            match &node {
-                Node::Tag(tag) if tag.is_heading_node() => {
-                    let id = generate_toc_heading_id_from_child_nodes(&tag.children);
-                    let parents = env.parents
-                        .into_iter()
-                        .map(|x| x.to_string())
-                        .collect::<Vec<_>>()
-                        .clone();
-                    let name = tag.name.data.to_string();
-                    let text = tag.children
-                        .iter()
-                        .flat_map(|x| x.clone().unblock())
-                        .map(|x| x.to_string())
-                        .collect::<Vec<_>>()
-                        .join("");
-                    headings.borrow_mut().push((parents, name, text, id));
-                }
-                _ => ()
-            }
-            node
-        }
-    };
-    let _ = node.clone().transform(
-        NodeEnvironment::default(),
-        Rc::new(f),
-    );
-    let info = headings
-        .clone()
-        .borrow()
+/// The text of the first heading, if any (e.g. for the `title` element).
+pub(crate) fn document_title<'a>(nodes: &[Node<'a>]) -> Option<String> {
+    nodes
+        .iter()
+        .filter_map(Node::unwrap_tag)
+        .find(|x| x.is_heading_node())
+        .map(|x| generate_toc_heading_title_from_child_nodes(&x.children))
+}
+
+/// The `(name, text, id)` of some heading.
+type TocEntry = (String, String, String);
+
+fn toc_entry<'a>(name: &str, children: &[Node<'a>]) -> TocEntry {
+    let id = generate_toc_heading_id_from_child_nodes(children);
+    let text = children
         .iter()
-        .map(|x| {
-            x.clone()
+        .flat_map(|x| match x.unwrap_curly_brace() {
+            Some(children) => children.iter().map(Node::to_string).collect(),
+            None => vec![x.to_string()],
         })
-        .collect::<Vec<_>>();
-    info
+        .collect::<Vec<_>>()
+        .join("");
+    (name.to_owned(), text, id)
+}
+
+fn get_headings<'a>(nodes: &[Node<'a>]) -> Vec<TocEntry> {
+    #[derive(Default)]
+    struct Headings {
+        headings: Vec<TocEntry>,
+    }
+    impl<'n, 'a: 'n> Visitor<'n, 'a> for Headings {
+        fn visit_tag(&mut self, tag: &'n Tag<'a>) {
+            if tag.is_heading_node() {
+                self.headings.push(toc_entry(tag.name(), &tag.children));
+            }
+            walk_tag(self, tag);
+        }
+    }
+    let mut headings = Headings::default();
+    for node in nodes {
+        headings.visit_node(node);
+    }
+    headings.headings
 }
 
-pub(crate) fn generate_table_of_contents_tree<'a>(input: &Node<'a>) -> Node<'a> {
-    let children = get_headings(input)
+fn table_of_contents_tree<'a>(headings: Vec<TocEntry>) -> Node<'a> {
+    let children = headings
         .into_iter()
-        .map(|(parents, ty, contents, id)| {
+        .map(|(ty, contents, id)| {
             let mut a = Tag::new(
                 Ann::unannotated("a"),
                 vec![Node::unannotated_string(contents)]
@@ -105,23 +115,28 @@ pub(crate) fn generate_table_of_contents_tree<'a>(input: &Node<'a>) -> Node<'a>
     node
 }
 
-pub fn annotate_heading_nodes<'a>(input: Node<'a>) -> Node<'a> {
-    let f = |env: NodeEnvironment, node: Node<'a>| -> Node<'a> {
-        match node {
-            Node::Tag(mut tag) if tag.is_heading_node() => {
+pub fn generate_table_of_contents_tree<'a>(nodes: &[Node<'a>]) -> Node<'a> {
+    table_of_contents_tree(get_headings(nodes))
+}
+
+/// Adds the `id`s of the headings (see `heading_id`).
+pub fn annotate_heading_nodes<'a>(mut input: Node<'a>) -> Node<'a> {
+    struct Annotate;
+    impl<'a> VisitorMut<'a> for Annotate {
+        fn visit_tag_mut(&mut self, tag: &mut Tag<'a>) {
+            walk_tag_mut(self, tag);
+            if tag.is_heading_node() {
                 let id = generate_toc_heading_id_from_child_nodes(&tag.children);
-                tag.insert_unannotated_parameter(
-                    &format!("id={}", id)
-                );
-                Node::Tag(tag)
+                tag.insert_unannotated_parameter(&format!("id={}", id));
             }
-            x => x,
         }
-    };
-    input.transform(NodeEnvironment::default(), Rc::new(f))
+    }
+    Annotate.visit_node_mut(&mut input);
+    input
 }
 
 
+
 ///////////////////////////////////////////////////////////////////////////////
 // WHERE TAG PROCESSING
 ///////////////////////////////////////////////////////////////////////////////
@@ -154,12 +169,38 @@ fn match_and_apply_rewrite_rule<'a>(
 }
 
 
+/// Expands the `\!where` rewrite rules of the given tag.
+pub(crate) fn apply_rewrite_rules<'a>(tag: Tag<'a>) -> Tag<'a> {
+    let mut children = tag.children;
+    for RewriteRule{from, to} in tag.rewrite_rules {
+        let from = from.unwrap_curly_brace();
+        let to = to.unwrap_curly_brace();
+        match (from, to) {
+            (Some(from), Some(to)) => {
+                children = match_and_apply_rewrite_rule(
+                    from.clone(),
+                    to.clone(),
+                    children,
+                );
+            }
+            _ => ()
+        }
+    }
+    Tag {
+        name: tag.name,
+        parameters: tag.parameters,
+        children,
+        rewrite_rules: Vec::new(),
+    }
+}
+
+
 ///////////////////////////////////////////////////////////////////////////////
 // AST-TO-AST PASSES
 ///////////////////////////////////////////////////////////////////////////////
 
 /// All compiler passes for same scope children.
-fn child_list_passes<'a>(children: Vec<Node<'a>>) -> Vec<Node<'a>> {
+pub(crate) fn child_list_passes<'a>(children: Vec<Node<'a>>) -> Vec<Node<'a>> {
     // APPLY AFTER REMOVING ALL TOKENS
     fn merge_text_content<'a>(xs: Vec<Node<'a>>) -> Vec<Node<'a>> {
         let mut results = Vec::new();
@@ -187,32 +228,18 @@ fn child_list_passes<'a>(children: Vec<Node<'a>>) -> Vec<Node<'a>> {
 }
 
 /// All node to node passes.
-fn node_passes<'a>(node: Node<'a>) -> Node<'a> {
-    fn apply_rewrite_rules<'a>(tag: Tag<'a>) -> Tag<'a> {
-        let mut children = tag.children;
-        for RewriteRule{from, to} in tag.rewrite_rules {
-            let from = from.unwrap_curly_brace();
-            let to = to.unwrap_curly_brace();
-            match (from, to) {
-                (Some(from), Some(to)) => {
-                    children = match_and_apply_rewrite_rule(
-                        from.clone(),
-                        to.clone(),
-                        children,
-                    );
-                }
-                _ => ()
-            }
-        }
-        Tag {
-            name: tag.name,
-            parameters: tag.parameters,
-            children,
-            rewrite_rules: Vec::new(),
-        }
-    }
+pub(crate) fn node_passes<'a>(node: Node<'a>) -> Node<'a> {
     fn process_tags<'a>(env: NodeEnvironment, mut tag: Tag<'a>) -> Tag<'a> {
         let name: &str = &(tag.name.data);
+        // E.g. `\figure{\img[src=...]}{caption}`; the last block is the caption.
+        if name == "figure" && env.is_default_env() && tag.children.len() > 1 {
+            let caption = tag.children.pop().unwrap();
+            let caption = Tag::new(
+                Ann::unannotated("figcaption"),
+                caption.unblock(),
+            );
+            tag.children.push(Node::Tag(caption));
+        }
         // DON'T DO THIS IN A MATH ENV
         if env.is_default_env() {
             // Apply this after any multi-argument specific tag processing.
@@ -236,7 +263,7 @@ fn node_passes<'a>(node: Node<'a>) -> Node<'a> {
                     let x: &str = &x;
                     let x = x.split_once("=").map(|x| x.1);
                     if let Some(x) = x {
-                        return x.parse::<f32>().ok()
+                        return x.trim().trim_end_matches("px").parse::<f32>().ok()
                     }
                     None
                 });
@@ -245,7 +272,7 @@ fn node_passes<'a>(node: Node<'a>) -> Node<'a> {
                     "style='width:{}px;'",
                     width
                 ));
-            } else {
+            } else if tag.get_parameter("width").is_some() {
                 println!(
                     "[WARNING!] invalid width tag; given {:?}",
                     tag.get_parameter("width")
@@ -253,9 +280,17 @@ fn node_passes<'a>(node: Node<'a>) -> Node<'a> {
             }
         }
         else if name == "layout" {
-            tag.name = Ann::unannotated(Cow::Borrowed("div"));
+            tag.name = Ann::join(tag.name.range(), Cow::Borrowed("div"));
             tag.insert_unannotated_parameter("macro=layout");
         }
+        else if name == "pagebreak" {
+            tag.name = Ann::unannotated(Cow::Borrowed("div"));
+            tag.insert_unannotated_parameter("macro=pagebreak");
+        }
+        else if name == "slide" {
+            tag.name = Ann::unannotated(Cow::Borrowed("section"));
+            tag.insert_unannotated_parameter("macro=slide");
+        }
         tag
     }
     let f = |env: NodeEnvironment, node: Node<'a>| -> Node<'a> {
@@ -266,6 +301,13 @@ fn node_passes<'a>(node: Node<'a>) -> Node<'a> {
                 Node::Tag(tag)
             }
             node @ Node::Enclosure(_) => node,
+            // ESCAPE SEQUENCES ARE PASSED THROUGH TO LATEX IN MATH MODE
+            Node::String(txt) if env.is_default_env() => {
+                match unescape(&txt.data) {
+                    Some(literal) => Node::String(txt.map(|_| Cow::Borrowed(literal))),
+                    None => Node::String(txt),
+                }
+            }
             node @ Node::String(_) => node,
             node @ Node::Ident(_) => node,
             node @ Node::InvalidToken(_) => node,
@@ -274,25 +316,61 @@ fn node_passes<'a>(node: Node<'a>) -> Node<'a> {
     node.transform(NodeEnvironment::default(), Rc::new(f))
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::pass::pp_normalize::run_compiler_frontend;
+    use crate::frontend::printer::print_source;
 
-///////////////////////////////////////////////////////////////////////////////
-// AST TO CODEGEN
-///////////////////////////////////////////////////////////////////////////////
-
-/// Internal
-pub fn html_canonicalization<'a>(nodes: Vec<Node<'a>>) -> Vec<Node<'a>> {
-    fn passes<'a>(children: Vec<Node<'a>>) -> Vec<Node<'a>> {
-        let children = children
+    fn node_passes_source(source: &str) -> String {
+        let nodes = run_compiler_frontend(source)
             .into_iter()
             .map(node_passes)
-            .collect();
-        child_list_passes(children)
+            .collect::<Vec<_>>();
+        print_source(&nodes)
     }
-    let result = passes(nodes);
-    let result = result
-        .into_iter()
-        .map(crate::frontend::pass::math::latex_pass)
-        .collect::<Vec<_>>();
-    result
-}
 
+    #[test]
+    fn figure_caption() {
+        assert_eq!(
+            node_passes_source("\\figure{\\img[src=a.png]}{A \\b{plot}}"),
+            "\\figure{\\img[src=a.png]\\figcaption{A \\b{plot}}}",
+        );
+        // WITHOUT A CAPTION
+        assert_eq!(
+            node_passes_source("\\figure{\\img[src=a.png]}"),
+            "\\figure{\\img[src=a.png]}",
+        );
+    }
+
+    #[test]
+    fn escape_sequences_outside_of_math() {
+        assert_eq!(node_passes_source("a \\} \\] b"), "a } ] b");
+        assert_eq!(node_passes_source("a \\\\ b"), "a \\ b");
+        // PASSED THROUGH TO LATEX
+        assert_eq!(node_passes_source("\\equation{\\{x\\}}"), "\\equation{\\{x\\}}");
+        assert_eq!(node_passes_source("\\equation{a \\\\ b}"), "\\equation{a \\\\ b}");
+    }
+
+    #[test]
+    fn headings_and_table_of_contents() {
+        let source = "\\h1{A} \\p{x} \\h2{B \\b{c}} \\note{\\h3{D}}";
+        let nodes = run_compiler_frontend(source);
+        let toc = generate_table_of_contents_tree(&nodes);
+        assert_eq!(print_source(&[toc]), concat!(
+            "\\ul[id=toc]{",
+            "\\li[type=h1]{\\a[href=#{A}]{A}}",
+            "\\li[type=h2]{\\a[href=#{B \\b{c}}]{B \\b{c}}}",
+            "\\li[type=h3]{\\a[href=#{D}]{D}}",
+            "}",
+        ));
+        let annotated = nodes
+            .into_iter()
+            .map(annotate_heading_nodes)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            print_source(&annotated),
+            "\\h1[id={A}]{A} \\p{x} \\h2[id={B \\b{c}}]{B \\b{c}} \\note{\\h3[id={D}]{D}}",
+        );
+    }
+}
@@ -0,0 +1,296 @@
+//! Image asset resolution.
+//!
+//! Local `\img[src=...]` paths are resolved relative to the source file,
+//! raster images are copied (under a content hash) into the output directory
+//! and annotated with their intrinsic dimensions, and SVG files are inlined
+//! into the document. Missing or unreadable files are reported as diagnostics.
+use std::cell::RefCell;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::borrow::Cow;
+use crate::frontend::data::*;
+use crate::frontend::ast::*;
+use crate::frontend::diagnostic::Diagnostic;
+use crate::codegen::settings::Settings;
+
+/// Subdirectory (of the output directory) that local assets are copied into.
+pub static ASSETS_DIR_NAME: &str = "assets";
+
+pub(crate) fn is_remote_path(src: &str) -> bool {
+    ["http://", "https://", "data:", "//"]
+        .iter()
+        .any(|x| src.starts_with(x))
+}
+
+/// 64-bit FNV-1a; stable across compiler versions, unlike `DefaultHasher`.
+pub(crate) fn content_hash(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+fn parse_pixels(value: &str) -> Option<f32> {
+    value
+        .trim()
+        .trim_end_matches("px")
+        .parse::<f32>()
+        .ok()
+}
+
+/// Removes the XML prolog & doctype so the payload can be embedded in HTML.
+fn svg_payload(contents: &str) -> Option<String> {
+    let start = contents.find("<svg")?;
+    Some(contents[start..].trim_end().to_owned())
+}
+
+/// The file name of the copied asset (i.e. `{stem}-{hash}.{ext}`).
+pub(crate) fn asset_file_name(path: &Path, bytes: &[u8]) -> String {
+    let stem = path
+        .file_stem()
+        .and_then(|x| x.to_str())
+        .unwrap_or("image");
+    match path.extension().and_then(|x| x.to_str()) {
+        Some(ext) => format!("{}-{:016x}.{}", stem, content_hash(bytes), ext),
+        None => format!("{}-{:016x}", stem, content_hash(bytes)),
+    }
+}
+
+/// Copies the asset into `{output_dir}/assets/{stem}-{hash}.{ext}` and returns
+/// the new path relative to the output directory.
+fn copy_asset(path: &Path, bytes: &[u8], output_dir: &Path) -> std::io::Result<String> {
+    let file_name = asset_file_name(path, bytes);
+    let assets_dir = output_dir.join(ASSETS_DIR_NAME);
+    std::fs::create_dir_all(&assets_dir)?;
+    let target = assets_dir.join(&file_name);
+    if !target.exists() {
+        std::fs::write(&target, bytes)?;
+    }
+    Ok(format!("{}/{}", ASSETS_DIR_NAME, file_name))
+}
+
+fn resolve_image<'a>(
+    mut tag: Tag<'a>,
+    settings: &Settings,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Node<'a> {
+    let range = tag.name.range();
+    let src = match tag.get_parameter_value("src") {
+        Some(src) => src,
+        None => {
+            diagnostics.push(Diagnostic::warning(range, "image is missing a `src` parameter"));
+            return Node::Tag(tag)
+        }
+    };
+    let source_dir = match settings.source_dir.as_ref() {
+        Some(dir) if !is_remote_path(&src) => dir,
+        _ => return Node::Tag(tag),
+    };
+    let path: PathBuf = source_dir.join(&src);
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(error) => {
+            diagnostics.push(Diagnostic::error(range, format!(
+                "image file {:?} could not be read: {}",
+                path,
+                error,
+            )));
+            return Node::Tag(tag)
+        }
+    };
+    let is_svg = path
+        .extension()
+        .map(|x| x.eq_ignore_ascii_case("svg"))
+        .unwrap_or(false);
+    if is_svg {
+        let payload = std::str::from_utf8(&bytes)
+            .ok()
+            .and_then(svg_payload);
+        match payload {
+            Some(payload) => {
+                let mut svg = Tag::new(
+                    Ann::join(range, INLINE_SVG_TAG),
+                    vec![Node::unannotated_string(payload)],
+                );
+                if tag.has_parameter("inline") {
+                    svg.insert_unannotated_parameter("inline");
+                }
+                return Node::Tag(svg)
+            }
+            None => {
+                diagnostics.push(Diagnostic::error(range, format!(
+                    "{:?} is not a valid SVG file",
+                    path,
+                )));
+                return Node::Tag(tag)
+            }
+        }
+    }
+    match imagesize::blob_size(&bytes) {
+        Ok(size) if !tag.has_parameter("height") => {
+            let width = tag
+                .get_parameter_value("width")
+                .and_then(|x| parse_pixels(&x));
+            let (width, height) = match width {
+                Some(width) => {
+                    let height = width * size.height as f32 / size.width.max(1) as f32;
+                    (width.round() as usize, height.round() as usize)
+                }
+                None => (size.width, size.height),
+            };
+            tag.remove_parameter("width");
+            tag.insert_unannotated_parameter(&format!("width={}", width));
+            tag.insert_unannotated_parameter(&format!("height={}", height));
+        }
+        Ok(_) => (),
+        Err(_) => {
+            diagnostics.push(Diagnostic::warning(range, format!(
+                "could not read the dimensions of {:?}",
+                path,
+            )));
+        }
+    }
+    if let Some(output_dir) = settings.output_dir.as_ref() {
+        match copy_asset(&path, &bytes, output_dir) {
+            Ok(new_src) => {
+                tag.remove_parameter("src");
+                tag.insert_unannotated_parameter(&format!("src={}", new_src));
+            }
+            Err(error) => {
+                diagnostics.push(Diagnostic::error(range, format!(
+                    "failed to copy {:?} into the output directory: {}",
+                    path,
+                    error,
+                )));
+            }
+        }
+    }
+    if !tag.has_parameter("loading") {
+        tag.insert_unannotated_parameter("loading=lazy");
+    }
+    Node::Tag(tag)
+}
+
+/// Entrypoint. Ensure that `Node` is first canonicalized.
+pub fn resolve_images<'a>(
+    node: Node<'a>,
+    settings: &Settings,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Node<'a> {
+    let f = |env: NodeEnvironment<'a>, node: Node<'a>| -> Node<'a> {
+        match node {
+            Node::Tag(tag) if tag.has_name("img") && env.is_default_env() => {
+                resolve_image(tag, settings, diagnostics)
+            }
+            node => node,
+        }
+    };
+    node.transform_mut(NodeEnvironment::default(), Rc::new(RefCell::new(f)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::scratch_dir;
+    use crate::frontend::pass::pp_normalize::run_compiler_frontend;
+
+    /// A PNG header (i.e. enough for the dimensions) of the given size.
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut bytes = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".to_vec();
+        bytes.extend_from_slice(&width.to_be_bytes());
+        bytes.extend_from_slice(&height.to_be_bytes());
+        bytes.extend_from_slice(&[8, 6, 0, 0, 0]);
+        bytes
+    }
+
+    fn resolve<'a>(source: &'a str, settings: &Settings) -> (Tag<'a>, Vec<Diagnostic>) {
+        let mut diagnostics = Vec::new();
+        let node = run_compiler_frontend(source)
+            .into_iter()
+            .map(|x| resolve_images(x, settings, &mut diagnostics))
+            .find_map(|x| x.unwrap_tag().cloned())
+            .unwrap();
+        (node, diagnostics)
+    }
+
+    #[test]
+    fn remote_images_are_left_as_is() {
+        let settings = Settings {
+            source_dir: Some(scratch_dir("images", "remote")),
+            ..Settings::default()
+        };
+        let (tag, diagnostics) = resolve("\\img[src=https://example.com/a.png]", &settings);
+        assert!(diagnostics.is_empty());
+        assert_eq!(tag.get_parameter_value("src").as_deref(), Some("https://example.com/a.png"));
+        assert!(!tag.has_parameter("loading"));
+    }
+
+    #[test]
+    fn missing_source_is_a_warning() {
+        let (_, diagnostics) = resolve("\\img[alt=x]", &Settings::default());
+        assert_eq!(diagnostics.len(), 1);
+        assert!(!diagnostics[0].is_error());
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        let settings = Settings {
+            source_dir: Some(scratch_dir("images", "missing")),
+            ..Settings::default()
+        };
+        let (tag, diagnostics) = resolve("\\img[src=nope.png]", &settings);
+        assert_eq!(diagnostics.len(), 1);
+        assert!(diagnostics[0].is_error());
+        assert_eq!(tag.get_parameter_value("src").as_deref(), Some("nope.png"));
+    }
+
+    #[test]
+    fn raster_images_are_sized_and_copied() {
+        let dir = scratch_dir("images", "raster");
+        let bytes = png(400, 200);
+        std::fs::write(dir.join("plot.png"), &bytes).unwrap();
+        let settings = Settings {
+            source_dir: Some(dir.clone()),
+            output_dir: Some(dir.join("out")),
+            ..Settings::default()
+        };
+        let (tag, diagnostics) = resolve("\\img[src=plot.png width=100px]", &settings);
+        assert!(diagnostics.is_empty(), "{:?}", diagnostics);
+        assert_eq!(tag.get_parameter_value("width").as_deref(), Some("100"));
+        assert_eq!(tag.get_parameter_value("height").as_deref(), Some("50"));
+        assert_eq!(tag.get_parameter_value("loading").as_deref(), Some("lazy"));
+        let src = tag.get_parameter_value("src").unwrap();
+        assert_eq!(src, format!("assets/{}", asset_file_name(Path::new("plot.png"), &bytes)));
+        assert_eq!(std::fs::read(dir.join("out").join(&src)).unwrap(), bytes);
+    }
+
+    #[test]
+    fn svg_images_are_inlined() {
+        let dir = scratch_dir("images", "svg");
+        let svg = "<?xml version=\"1.0\"?>\n<!DOCTYPE svg>\n<svg width=\"1\"></svg>\n";
+        std::fs::write(dir.join("icon.svg"), svg).unwrap();
+        let settings = Settings {
+            source_dir: Some(dir),
+            ..Settings::default()
+        };
+        let (tag, diagnostics) = resolve("\\img[src=icon.svg inline]", &settings);
+        assert!(diagnostics.is_empty());
+        assert_eq!(tag.name(), INLINE_SVG_TAG);
+        assert!(tag.has_parameter("inline"));
+        assert_eq!(tag.children[0].to_string(), "<svg width=\"1\"></svg>");
+    }
+
+    #[test]
+    fn asset_names_are_content_addressed() {
+        assert_eq!(content_hash(b""), 0xcbf29ce484222325);
+        assert_eq!(content_hash(b"a"), 0xaf63dc4c8601ec8c);
+        assert_eq!(
+            asset_file_name(Path::new("dir/plot.png"), b"a"),
+            "plot-af63dc4c8601ec8c.png",
+        );
+        assert!(is_remote_path("//cdn.example.com/a.png"));
+        assert!(!is_remote_path("images/a.png"));
+    }
+}
@@ -0,0 +1,284 @@
+//! Inline formatting shorthand for prose.
+//!
+//! ```text
+//! **bold**, *italic* or _italic_, `code` and [link text](https://example.com)
+//! ```
+//!
+//! Markers must hug the text they enclose (`a * b` is left as is), and `*` &
+//! `_` only count at word boundaries (so `snake_case_name` is left as is). Use
+//! `\*`, `` \` ``, `\_` & `\[` for the literal characters. Math environments and
+//! verbatim tags are never touched.
+//!
+//! Opt-in (`--inline-format`, or `inline-format` in the `[passes]` of
+//! `subscript.toml`), so that existing documents compile unchanged.
+use std::rc::Rc;
+use crate::frontend::data::*;
+use crate::frontend::ast::*;
+
+/// Characters that may start some shorthand.
+const MARKERS: &[char] = &['*', '_', '`'];
+
+/// Some character of text, or any other (opaque) node.
+pub(crate) enum Piece<'a> {
+    Char(char),
+    Node(Node<'a>),
+}
+
+impl<'a> Piece<'a> {
+    fn is_char(&self, value: char) -> bool {
+        match self {
+            Piece::Char(x) => *x == value,
+            _ => false,
+        }
+    }
+    fn is_whitespace(&self) -> bool {
+        match self {
+            Piece::Char(x) => x.is_whitespace(),
+            _ => false,
+        }
+    }
+    fn is_alphanumeric(&self) -> bool {
+        match self {
+            Piece::Char(x) => x.is_alphanumeric(),
+            _ => false,
+        }
+    }
+}
+
+/// Escape sequences (e.g. `\*`) are kept as opaque nodes, so they never match
+/// some marker.
+pub(crate) fn into_pieces(children: Vec<Node>) -> Vec<Piece> {
+    let mut pieces = Vec::new();
+    for node in children {
+        match node {
+            Node::String(txt) if unescape(&txt.data).is_none() => {
+                pieces.extend(txt.data.chars().map(Piece::Char));
+            }
+            node => pieces.push(Piece::Node(node)),
+        }
+    }
+    pieces
+}
+
+pub(crate) fn from_pieces(pieces: Vec<Piece>) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    let mut text = String::new();
+    for piece in pieces {
+        match piece {
+            Piece::Char(x) => text.push(x),
+            Piece::Node(node) => {
+                if !text.is_empty() {
+                    nodes.push(Node::unannotated_string(std::mem::take(&mut text)));
+                }
+                nodes.push(node);
+            }
+        }
+    }
+    if !text.is_empty() {
+        nodes.push(Node::unannotated_string(text));
+    }
+    nodes
+}
+
+/// The end of the emphasis delimited by `width` copies of `marker` that
+/// starts at `start` (if any).
+fn find_emphasis(pieces: &[Piece], start: usize, marker: char, width: usize) -> Option<usize> {
+    let is_delimiter = |ix: usize| (ix..ix + width).all(|ix| {
+        pieces.get(ix).map(|x| x.is_char(marker)).unwrap_or(false)
+    });
+    let is_boundary = |ix: Option<&Piece>| {
+        ix.map(|x| !x.is_alphanumeric() && !x.is_char(marker)).unwrap_or(true)
+    };
+    let opens = is_delimiter(start)
+        && is_boundary(start.checked_sub(1).and_then(|ix| pieces.get(ix)))
+        && pieces
+            .get(start + width)
+            .map(|x| !x.is_whitespace() && !x.is_char(marker))
+            .unwrap_or(false);
+    if !opens {
+        return None
+    }
+    (start + width + 1..pieces.len()).find(|ix| {
+        is_delimiter(*ix)
+            && !pieces[ix - 1].is_whitespace()
+            && !pieces[ix - 1].is_char(marker)
+            && is_boundary(pieces.get(ix + width))
+    })
+}
+
+/// The end of the inline code span that starts at `start` (if any).
+fn find_code(pieces: &[Piece], start: usize) -> Option<usize> {
+    let end = (start + 1..pieces.len())
+        .take_while(|ix| matches!(pieces[*ix], Piece::Char(x) if x != '\n'))
+        .find(|ix| pieces[*ix].is_char('`'))?;
+    if end == start + 1 {
+        return None
+    }
+    Some(end)
+}
+
+/// The URL of the link, for `[text](url)` enclosures.
+fn link_target(text: &Piece, target: Option<&Piece>) -> Option<String> {
+    let is_text = match text {
+        Piece::Node(Node::Enclosure(x)) => x.data.is_square_parens(),
+        _ => false,
+    };
+    let url = match target {
+        Some(Piece::Node(node @ Node::Enclosure(x))) if x.data.is_parens() => {
+            let url = node.to_string();
+            url[1..url.len() - 1].trim().to_owned()
+        }
+        _ => return None,
+    };
+    if !is_text || url.is_empty() || url.contains(char::is_whitespace) {
+        return None
+    }
+    Some(url)
+}
+
+fn format_pieces(mut pieces: Vec<Piece>) -> Vec<Node> {
+    let mut ix = 0;
+    while ix < pieces.len() {
+        let emphasis = if pieces[ix].is_char('*') {
+            find_emphasis(&pieces, ix, '*', 2)
+                .map(|end| ("b", 2, end))
+                .or_else(|| find_emphasis(&pieces, ix, '*', 1).map(|end| ("i", 1, end)))
+        } else if pieces[ix].is_char('_') {
+            find_emphasis(&pieces, ix, '_', 1).map(|end| ("i", 1, end))
+        } else {
+            None
+        };
+        if let Some((name, width, end)) = emphasis {
+            let rest = pieces.split_off(end + width);
+            let inner = pieces.split_off(ix + width);
+            pieces.truncate(ix);
+            let inner = format_pieces(inner.into_iter().take(end - ix - width).collect());
+            let mut nodes = from_pieces(pieces);
+            nodes.push(Node::unannotated_tag(name, inner));
+            nodes.extend(format_pieces(rest));
+            return nodes
+        }
+        if pieces[ix].is_char('`') {
+            if let Some(end) = find_code(&pieces, ix) {
+                let rest = pieces.split_off(end + 1);
+                let inner = pieces.split_off(ix + 1);
+                pieces.truncate(ix);
+                // LOWERED BY THE CODE PASS
+                let code = from_pieces(inner.into_iter().take(end - ix - 1).collect());
+                let mut nodes = from_pieces(pieces);
+                nodes.push(Node::unannotated_tag("code", code));
+                nodes.extend(format_pieces(rest));
+                return nodes
+            }
+        }
+        if let Some(url) = link_target(&pieces[ix], pieces.get(ix + 1)) {
+            let rest = pieces.split_off(ix + 2);
+            let text = match pieces.remove(ix) {
+                Piece::Node(Node::Enclosure(x)) => x.data.children,
+                _ => unreachable!(),
+            };
+            pieces.truncate(ix);
+            let mut link = Tag::new(Ann::unannotated("a"), text);
+            link.insert_unannotated_parameter(&format!("href={}", url));
+            let mut nodes = from_pieces(pieces);
+            nodes.push(Node::Tag(link));
+            nodes.extend(format_pieces(rest));
+            return nodes
+        }
+        ix += 1;
+    }
+    from_pieces(pieces)
+}
+
+fn has_shorthand(children: &[Node]) -> bool {
+    let has_marker = children
+        .iter()
+        .filter_map(Node::unwrap_string)
+        .any(|x| x.data.contains(MARKERS));
+    let has_link = children.windows(2).any(|xs| {
+        match (&xs[0], &xs[1]) {
+            (Node::Enclosure(x), Node::Enclosure(y)) => {
+                x.data.is_square_parens() && y.data.is_parens()
+            }
+            _ => false,
+        }
+    });
+    has_marker || has_link
+}
+
+fn format_children(children: Vec<Node>) -> Vec<Node> {
+    if !has_shorthand(&children) {
+        return children
+    }
+    format_pieces(into_pieces(children))
+}
+
+/// Entrypoint. Must run before the verbatim tags are lowered and before the
+/// escape sequences are converted into literal text.
+pub fn inline_format_pass<'a>(children: Vec<Node<'a>>) -> Vec<Node<'a>> {
+    let f = |env: NodeEnvironment<'a>, node: Node<'a>| -> Node<'a> {
+        let is_verbatim = env.parents
+            .iter()
+            .any(|x| VERBATIM_TAGS.contains(&x.as_ref()));
+        if env.is_math_env() || is_verbatim {
+            return node
+        }
+        match node {
+            Node::Tag(mut tag) => {
+                tag.children = format_children(tag.children);
+                Node::Tag(tag)
+            }
+            Node::Enclosure(mut node) if !node.data.is_error() => {
+                node.data.children = format_children(node.data.children);
+                Node::Enclosure(node)
+            }
+            node => node,
+        }
+    };
+    Node::new_fragment(children)
+        .transform(NodeEnvironment::default(), Rc::new(f))
+        .into_fragment()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::pass::pp_normalize::run_compiler_frontend;
+    use crate::frontend::printer::print_source;
+    use crate::codegen::settings::Passes;
+
+    fn format(source: &str) -> String {
+        print_source(&inline_format_pass(run_compiler_frontend(source)))
+    }
+
+    #[test]
+    fn shorthand() {
+        assert_eq!(
+            format("**bold**, *italic*, _italic_, `a*b` and [text](https://example.com)"),
+            "\\b{bold}, \\i{italic}, \\i{italic}, \\code{a*b} and \\a[href=https://example.com]{text}",
+        );
+    }
+
+    #[test]
+    fn markers_must_hug_the_text() {
+        assert_eq!(format("a * b * c"), "a * b * c");
+        assert_eq!(format("snake_case_name"), "snake_case_name");
+    }
+
+    #[test]
+    fn escapes_math_and_verbatim_are_left_as_is() {
+        for source in &["\\*not\\*", "\\equation{*x*}", "\\verb{*x*}", "\\code{_x_}"] {
+            assert_eq!(&format(source), source);
+        }
+    }
+
+    #[test]
+    fn nested() {
+        assert_eq!(format("**a *b* c** \\note{*d*}"), "\\b{a \\i{b} c} \\note{\\i{d}}");
+    }
+
+    #[test]
+    fn opt_in() {
+        assert!(!Passes::default().inline_format);
+    }
+}
@@ -0,0 +1,273 @@
+//! Validation & lowering of `\layout` grids.
+//!
+//! ```text
+//! \layout[cols=3 align=center collapse=small boxed]{
+//!     \note[span=2]{...}
+//!     \note[align=end]{...}
+//! }
+//! ```
+//!
+//! * `cols`: the number of columns (1 to `MAX_COLUMNS`); without it the
+//!   children are stacked.
+//! * `align`: the vertical alignment of the children (`start`, `center`,
+//!   `end` or `stretch`); children may override it with their own `align`.
+//! * `gap`: the space between the children, in pixels.
+//! * `collapse`: the viewport width below which the layout becomes a single
+//!   column, i.e. `small`, `medium` (the default), `large` or `never` (see
+//!   `BREAKPOINTS`).
+//! * `boxed`: draws a box around every child; children may also be boxed
+//!   individually.
+//! * `span` & `row-span` (on the children): the number of columns & rows the
+//!   child covers.
+//!
+//! Invalid values are reported and ignored. Run after the HTML
+//! canonicalization, i.e. on `div[macro=layout]`.
+use std::cell::RefCell;
+use std::rc::Rc;
+use crate::frontend::ast::*;
+use crate::frontend::diagnostic::Diagnostic;
+
+/// The maximum number of columns.
+pub static MAX_COLUMNS: usize = 12;
+
+/// The `collapse` values, i.e. below 600, 900 & 1200 pixels, or never (see
+/// the `[macro=layout]` rules of `styles/macros.scss`).
+pub static BREAKPOINTS: &[&str] = &["small", "medium", "large", "never"];
+
+/// The `align` values.
+pub static ALIGNMENTS: &[&str] = &["start", "center", "end", "stretch"];
+
+fn is_layout(tag: &Tag) -> bool {
+    tag.has_name("div") && tag
+        .get_parameter_value("macro")
+        .map(|x| x.trim_matches('\'') == "layout")
+        .unwrap_or(false)
+}
+
+fn parameter_value(tag: &Tag, key: &str) -> Option<String> {
+    tag.get_parameter_value(key)
+        .map(|x| x.trim_matches(|x| x == '\'' || x == '"').to_owned())
+}
+
+/// Appends the given declaration to the `style` parameter.
+fn add_style(tag: &mut Tag, declaration: &str) {
+    let style = parameter_value(tag, "style").unwrap_or_default();
+    tag.remove_parameter("style");
+    tag.insert_unannotated_parameter(&format!("style='{}{};'", style, declaration));
+}
+
+/// Parses some count parameter (e.g. `cols` or `span`), if present.
+fn count_parameter(
+    tag: &Tag,
+    key: &str,
+    max: usize,
+    range: Option<CharRange>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<usize> {
+    let value = parameter_value(tag, key)?;
+    match value.parse::<usize>() {
+        Ok(count) if (1..=max).contains(&count) => Some(count),
+        _ => {
+            diagnostics.push(Diagnostic::warning(range, format!(
+                "invalid `{}` value `{}`; expected a number from 1 to {}",
+                key,
+                value,
+                max,
+            )));
+            None
+        }
+    }
+}
+
+fn alignment_parameter(
+    tag: &Tag,
+    range: Option<CharRange>,
+    diagnostics: &mut Vec<Diagnostic>,
+) -> Option<&'static str> {
+    let value = parameter_value(tag, "align")?;
+    let alignment = ALIGNMENTS.iter().find(|x| **x == value).copied();
+    if alignment.is_none() {
+        diagnostics.push(Diagnostic::warning(range, format!(
+            "invalid `align` value `{}`; expected one of {}",
+            value,
+            ALIGNMENTS.join(", "),
+        )));
+    }
+    alignment
+}
+
+fn lower_child(
+    child: &mut Tag,
+    columns: Option<usize>,
+    boxed: bool,
+    range: Option<CharRange>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let range = child.name.range().or(range);
+    let max_span = columns.unwrap_or(1);
+    let span = count_parameter(child, "span", MAX_COLUMNS, range, diagnostics);
+    child.remove_parameter("span");
+    if let Some(span) = span {
+        if span > max_span {
+            diagnostics.push(Diagnostic::warning(range, format!(
+                "`span={}` exceeds the {} column(s) of the layout",
+                span,
+                max_span,
+            )));
+        }
+        let span = span.min(max_span);
+        child.insert_unannotated_parameter(&format!("span={}", span));
+        add_style(child, &format!("--layout-span:{}", span));
+    }
+    let row_span = count_parameter(child, "row-span", MAX_COLUMNS, range, diagnostics);
+    child.remove_parameter("row-span");
+    if let Some(row_span) = row_span {
+        child.insert_unannotated_parameter(&format!("row-span={}", row_span));
+        add_style(child, &format!("--layout-row-span:{}", row_span));
+    }
+    if let Some(alignment) = alignment_parameter(child, range, diagnostics) {
+        add_style(child, &format!("align-self:{}", alignment));
+    }
+    child.remove_parameter("align");
+    if boxed || child.has_parameter("boxed") {
+        child.remove_parameter("boxed");
+        child.insert_unannotated_parameter("boxed-child");
+    }
+}
+
+fn lower_layout<'a>(mut tag: Tag<'a>, diagnostics: &mut Vec<Diagnostic>) -> Tag<'a> {
+    let range = tag.name.range();
+    let columns = count_parameter(&tag, "cols", MAX_COLUMNS, range, diagnostics);
+    tag.remove_parameter("cols");
+    if let Some(columns) = columns {
+        tag.insert_unannotated_parameter(&format!("cols={}", columns));
+        add_style(&mut tag, &format!("--layout-cols:{}", columns));
+    }
+    let collapse = parameter_value(&tag, "collapse").unwrap_or_else(|| String::from("medium"));
+    let collapse = match BREAKPOINTS.iter().find(|x| **x == collapse) {
+        Some(name) => name,
+        None => {
+            diagnostics.push(Diagnostic::warning(range, format!(
+                "invalid `collapse` value `{}`; expected one of {}",
+                collapse,
+                BREAKPOINTS.join(", "),
+            )));
+            "medium"
+        }
+    };
+    tag.remove_parameter("collapse");
+    tag.insert_unannotated_parameter(&format!("collapse={}", collapse));
+    if let Some(alignment) = alignment_parameter(&tag, range, diagnostics) {
+        add_style(&mut tag, &format!("align-items:{}", alignment));
+    }
+    tag.remove_parameter("align");
+    if let Some(gap) = parameter_value(&tag, "gap") {
+        match gap.trim_end_matches("px").parse::<f32>() {
+            Ok(gap) if gap >= 0.0 => add_style(&mut tag, &format!("gap:{}px", gap)),
+            _ => diagnostics.push(Diagnostic::warning(range, format!(
+                "invalid `gap` value `{}`; expected a number of pixels",
+                gap,
+            ))),
+        }
+        tag.remove_parameter("gap");
+    }
+    let boxed = tag.has_parameter("boxed");
+    for child in tag.children.iter_mut() {
+        if let Some(child) = child.unwrap_tag_mut() {
+            lower_child(child, columns, boxed, range, diagnostics);
+        }
+    }
+    tag
+}
+
+/// Entrypoint.
+pub fn layout_pass<'a>(node: Node<'a>, diagnostics: &mut Vec<Diagnostic>) -> Node<'a> {
+    let f = |env: NodeEnvironment<'a>, node: Node<'a>| -> Node<'a> {
+        match node {
+            Node::Tag(tag) if is_layout(&tag) && env.is_default_env() => {
+                Node::Tag(lower_layout(tag, diagnostics))
+            }
+            node => node,
+        }
+    };
+    node.transform_mut(NodeEnvironment::default(), Rc::new(RefCell::new(f)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::pass::html_normalize::node_passes;
+    use crate::frontend::pass::pp_normalize::run_compiler_frontend;
+    use crate::frontend::printer::print_source;
+
+    /// The lowered source & the diagnostics.
+    fn lower(source: &str) -> (String, Vec<String>) {
+        let mut diagnostics = Vec::new();
+        let nodes = run_compiler_frontend(source)
+            .into_iter()
+            .map(node_passes)
+            .map(|x| layout_pass(x, &mut diagnostics))
+            .collect::<Vec<_>>();
+        let messages = diagnostics.into_iter().map(|x| x.message).collect();
+        (print_source(&nodes), messages)
+    }
+
+    #[test]
+    fn grid() {
+        let (output, diagnostics) = lower(
+            "\\layout[cols=3 align=center gap=8px boxed]{\\note[span=2]{a}\\note[align=end]{b}}"
+        );
+        assert_eq!(output, concat!(
+            "\\div[boxed macro=layout cols=3 collapse=medium ",
+            "style='--layout-cols:3;align-items:center;gap:8px;']{",
+            "\\div[macro=note span=2 style='--layout-span:2;' boxed-child]{a}",
+            "\\div[macro=note style='align-self:end;' boxed-child]{b}",
+            "}",
+        ));
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn stacked() {
+        let (output, diagnostics) = lower("\\layout{\\note{a}}");
+        assert_eq!(output, "\\div[macro=layout collapse=medium]{\\div[macro=note]{a}}");
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn invalid_values_are_reported_and_ignored() {
+        let (output, diagnostics) = lower(
+            "\\layout[cols=13 collapse=tiny align=top gap=wide]{\\note[span=0 row-span=x]{a}}"
+        );
+        assert_eq!(output, "\\div[macro=layout collapse=medium]{\\div[macro=note]{a}}");
+        assert_eq!(diagnostics, vec![
+            "invalid `cols` value `13`; expected a number from 1 to 12",
+            "invalid `collapse` value `tiny`; expected one of small, medium, large, never",
+            "invalid `align` value `top`; expected one of start, center, end, stretch",
+            "invalid `gap` value `wide`; expected a number of pixels",
+            "invalid `span` value `0`; expected a number from 1 to 12",
+            "invalid `row-span` value `x`; expected a number from 1 to 12",
+        ]);
+    }
+
+    #[test]
+    fn spans_are_clamped_to_the_columns() {
+        let (output, diagnostics) = lower("\\layout[cols=2]{\\note[span=3 row-span=2]{a}}");
+        assert_eq!(output, concat!(
+            "\\div[macro=layout cols=2 style='--layout-cols:2;' collapse=medium]{",
+            "\\div[macro=note span=2 row-span=2 style='--layout-span:2;--layout-row-span:2;']{a}",
+            "}",
+        ));
+        assert_eq!(diagnostics, vec!["`span=3` exceeds the 2 column(s) of the layout"]);
+    }
+
+    #[test]
+    fn diagnostics_point_at_the_tag() {
+        let mut diagnostics = Vec::new();
+        let source = "\\p{a}\n\\layout[cols=x]{}";
+        for node in run_compiler_frontend(source).into_iter().map(node_passes) {
+            layout_pass(node, &mut diagnostics);
+        }
+        assert_eq!(diagnostics[0].line_column(source), Some((2, 1)));
+    }
+}
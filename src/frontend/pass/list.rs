@@ -0,0 +1,323 @@
+//! Lightweight list syntax.
+//!
+//! ```text
+//! \list{
+//!     - First item
+//!         - Nested item (by indentation)
+//!     - Second item
+//! }
+//! \list{
+//!     3. Ordered lists start at the first number
+//!     4. Fourth
+//! }
+//! \list{
+//!     : Term
+//!         The definition of the term.
+//! }
+//! ```
+//!
+//! The same marker lines may also be used directly within `\ul`, `\ol` and
+//! `\dl` blocks (that don’t already contain `\li`, `\dt` or `\dd` tags).
+use std::rc::Rc;
+use std::borrow::Cow;
+use crate::frontend::data::*;
+use crate::frontend::ast::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Marker {
+    Bullet,
+    Ordered(usize),
+    Term,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Line<'a> {
+    indent: usize,
+    marker: Option<Marker>,
+    nodes: Vec<Node<'a>>,
+}
+
+fn indent_width(whitespace: &str) -> usize {
+    whitespace
+        .chars()
+        .map(|x| if x == '\t' {4} else {1})
+        .sum()
+}
+
+/// Splits the given nodes into (non-blank) lines, recording the indentation of each.
+fn into_lines(children: Vec<Node>) -> Vec<Line> {
+    let mut lines = vec![Line::default()];
+    let mut at_line_start = true;
+    for node in children {
+        match node {
+            Node::String(txt) => {
+                for (ix, part) in txt.data.split('\n').enumerate() {
+                    let line = if ix > 0 {
+                        lines.push(Line::default());
+                        at_line_start = true;
+                        lines.last_mut().unwrap()
+                    } else {
+                        lines.last_mut().unwrap()
+                    };
+                    let mut part = part;
+                    if at_line_start {
+                        let trimmed = part.trim_start();
+                        line.indent += indent_width(&part[..part.len() - trimmed.len()]);
+                        part = trimmed;
+                        at_line_start = part.is_empty();
+                    }
+                    if part.is_empty() {
+                        continue;
+                    }
+                    // MERGE ADJACENT TEXT (E.G. `3` & `.`) SO MARKERS CAN BE DETECTED
+                    match line.nodes.last_mut().and_then(Node::unwrap_string_mut) {
+                        Some(last) => {
+                            *last = Ann::unannotated(Cow::Owned(format!("{}{}", last.data, part)));
+                        }
+                        None => {
+                            line.nodes.push(Node::unannotated_string(part.to_owned()));
+                        }
+                    }
+                }
+            }
+            node => {
+                at_line_start = false;
+                lines.last_mut().unwrap().nodes.push(node);
+            }
+        }
+    }
+    lines
+        .into_iter()
+        .filter(|x| x.nodes.iter().any(|x| !x.is_whitespace()))
+        .map(split_marker)
+        .collect()
+}
+
+/// Detects & removes the list marker (e.g. `-`, `3.` or `:`) at the start of the line.
+fn split_marker(mut line: Line) -> Line {
+    let first = match line.nodes.first().and_then(Node::unwrap_string) {
+        Some(x) => x.data.to_string(),
+        None => return line,
+    };
+    let ordered_prefix = first
+        .find(|x: char| !x.is_ascii_digit())
+        .filter(|ix| *ix > 0)
+        .filter(|ix| first[*ix..].starts_with(['.', ')'].as_ref()));
+    let (marker, rest) = if let Some(ix) = ordered_prefix {
+        let number = first[..ix].parse::<usize>().unwrap_or(1);
+        (Marker::Ordered(number), &first[ix + 1..])
+    } else if first.starts_with(['-', '*', '•'].as_ref()) {
+        let marker_len = first.chars().next().unwrap().len_utf8();
+        (Marker::Bullet, &first[marker_len..])
+    } else if let Some(rest) = first.strip_prefix(':') {
+        (Marker::Term, rest)
+    } else {
+        return line
+    };
+    if !(rest.is_empty() || rest.starts_with(char::is_whitespace)) {
+        return line
+    }
+    let rest = rest.trim_start().to_owned();
+    line.marker = Some(marker);
+    if rest.is_empty() {
+        line.nodes.remove(0);
+    } else {
+        line.nodes[0] = Node::unannotated_string(rest);
+    }
+    line
+}
+
+fn join_lines(lines: Vec<Line>) -> Vec<Node> {
+    let mut nodes = Vec::new();
+    for (ix, line) in lines.into_iter().enumerate() {
+        if ix > 0 {
+            nodes.push(Node::unannotated_str("\n"));
+        }
+        nodes.extend(line.nodes);
+    }
+    nodes
+}
+
+struct Item<'a> {
+    marker: Marker,
+    line: Vec<Node<'a>>,
+    continuation: Vec<Line<'a>>,
+}
+
+/// Builds the list (or lists) from the given lines; leading lines without a
+/// marker are returned as is.
+fn build_list<'a>(lines: Vec<Line<'a>>, list_name: Option<&'a str>) -> Vec<Node<'a>> {
+    let first_item = match lines.iter().position(|x| x.marker.is_some()) {
+        Some(ix) => ix,
+        None => return join_lines(lines),
+    };
+    let mut lines = lines;
+    let items_lines = lines.split_off(first_item);
+    let mut nodes = join_lines(lines);
+    let base = items_lines[0].indent;
+    let mut items: Vec<Item> = Vec::new();
+    for line in items_lines {
+        match line.marker {
+            Some(marker) if line.indent <= base || items.is_empty() => {
+                items.push(Item {marker, line: line.nodes, continuation: Vec::new()});
+            }
+            _ => items.last_mut().unwrap().continuation.push(line),
+        }
+    }
+    let kind = items[0].marker;
+    let name = list_name.unwrap_or(match kind {
+        Marker::Bullet => "ul",
+        Marker::Ordered(_) => "ol",
+        Marker::Term => "dl",
+    });
+    let mut children = Vec::new();
+    for Item {marker, line, mut continuation} in items {
+        let nested_start = continuation
+            .iter()
+            .position(|x| x.marker.is_some())
+            .unwrap_or(continuation.len());
+        let nested = continuation.split_off(nested_start);
+        let mut body = join_lines(continuation);
+        if !nested.is_empty() {
+            body.extend(build_list(nested, None));
+        }
+        if marker == Marker::Term || name == "dl" {
+            children.push(Node::unannotated_tag("dt", line));
+            if !body.is_empty() {
+                children.push(Node::unannotated_tag("dd", body));
+            }
+        } else {
+            let mut contents = line;
+            if !body.is_empty() {
+                contents.push(Node::unannotated_str("\n"));
+                contents.extend(body);
+            }
+            children.push(Node::unannotated_tag("li", contents));
+        }
+    }
+    let mut list = Tag::new(Ann::unannotated(name), children);
+    match kind {
+        Marker::Ordered(start) if start != 1 && name == "ol" => {
+            list.insert_unannotated_parameter(&format!("start={}", start));
+        }
+        _ => ()
+    }
+    nodes.push(Node::Tag(list));
+    nodes
+}
+
+fn is_list_tag(tag: &Tag) -> bool {
+    let has_items = tag.children
+        .iter()
+        .any(|x| ["li", "dt", "dd"].iter().any(|name| x.is_named_block(name)));
+    let is_candidate = ["list", "ul", "ol", "dl"]
+        .iter()
+        .any(|name| tag.has_name(name));
+    is_candidate && !has_items
+}
+
+fn lower_list_tag(tag: Tag) -> Node {
+    let list_name = match tag.name() {
+        "ul" => Some("ul"),
+        "ol" => Some("ol"),
+        "dl" => Some("dl"),
+        _ => None,
+    };
+    let lines = into_lines(tag.children
+        .iter()
+        .cloned()
+        .flat_map(Node::unblock)
+        .collect());
+    if !lines.iter().any(|x| x.marker.is_some()) {
+        return match list_name {
+            Some(_) => Node::Tag(tag),
+            None => Node::new_fragment(join_lines(lines)),
+        }
+    }
+    let mut nodes = build_list(lines, list_name);
+    // CARRY OVER THE PARAMETERS OF THE ORIGINAL TAG
+    if let Some(list) = nodes.last_mut().and_then(Node::unwrap_tag_mut) {
+        list.name = Ann::join(tag.name.range(), list.name.data.clone());
+        for parameter in tag.parameters.unwrap_or_default() {
+            list.parameters.get_or_insert_with(Vec::new).push(parameter);
+        }
+        list.rewrite_rules = tag.rewrite_rules;
+    }
+    match nodes.len() {
+        1 => nodes.pop().unwrap(),
+        _ => Node::new_fragment(nodes),
+    }
+}
+
+/// Entrypoint. Run after escape sequences are converted to literal text.
+pub fn list_pass<'a>(node: Node<'a>) -> Node<'a> {
+    let f = |env: NodeEnvironment<'a>, node: Node<'a>| -> Node<'a> {
+        match node {
+            Node::Tag(tag) if is_list_tag(&tag) && env.is_default_env() => {
+                lower_list_tag(tag)
+            }
+            node => node,
+        }
+    };
+    node.transform(NodeEnvironment::default(), Rc::new(f))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::pass::pp_normalize::run_compiler_frontend;
+    use crate::frontend::pass::html_normalize::{node_passes, child_list_passes};
+    use crate::frontend::printer::print_source;
+
+    /// After the `html-tags` pass, i.e. with the adjacent text merged.
+    fn lower(source: &str) -> String {
+        let nodes = run_compiler_frontend(source)
+            .into_iter()
+            .map(node_passes)
+            .collect();
+        let nodes = child_list_passes(nodes)
+            .into_iter()
+            .map(list_pass)
+            .collect::<Vec<_>>();
+        print_source(&nodes)
+    }
+
+    #[test]
+    fn bullets() {
+        assert_eq!(lower("\\list{\n    - a\n    * b \\b{c}\n}"), "\\ul{\\li{a}\\li{b \\b{c}}}");
+    }
+
+    #[test]
+    fn nesting_by_indentation() {
+        assert_eq!(
+            lower("\\list{\n    - a\n        - b\n        - c\n    - d\n}"),
+            "\\ul{\\li{a\n\\ul{\\li{b}\\li{c}}}\\li{d}}",
+        );
+    }
+
+    #[test]
+    fn ordered_start() {
+        assert_eq!(lower("\\list{\n    3. c\n    4\\) d\n}"), "\\ol[start=3]{\\li{c}\\li{d}}");
+    }
+
+    #[test]
+    fn definitions() {
+        assert_eq!(
+            lower("\\list{\n    : Term\n        The definition.\n}"),
+            "\\dl{\\dt{Term}\\dd{The definition.}}",
+        );
+    }
+
+    #[test]
+    fn markers_need_a_space() {
+        assert_eq!(lower("\\ul{\n    -1 is negative\n}"), "\\ul{\n    -1 is negative\n}");
+        // A `\list` WITHOUT ANY ITEMS IS UNWRAPPED
+        assert_eq!(lower("\\list{\n    3.14\n}"), "3.14");
+    }
+
+    #[test]
+    fn explicit_items_are_left_as_is() {
+        let source = "\\ul{\\li{- a}}";
+        assert_eq!(lower(source), source);
+    }
+}
@@ -16,6 +16,8 @@ use crate::frontend::data::{
     INLINE_MATH_TAG,
 };
 use crate::frontend::ast::{Ann, Node, NodeEnvironment, Tag};
+use crate::frontend::visit::{VisitorMut, walk_node_mut};
+use crate::codegen::settings::MathMacro;
 
 pub static LATEX_ENVIRONMENT_NAME_LIST: &'static [&'static str] = &[
     "equation",
@@ -76,56 +78,61 @@ fn to_valid_latex_math<'a>(node: Node<'a>) -> Node<'a> {
 }
 
 
+/// The LaTeX source code of the children of some math tag (i.e. `\equation`
+/// or inline math), without the surrounding delimiters.
+pub fn to_latex_source(children: Vec<Node>) -> String {
+    children
+        .into_iter()
+        .flat_map(Node::unblock)
+        .map(to_valid_latex_math)
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join("")
+}
+
+/// Replaces the math tags with the output of `render` (given the LaTeX
+/// source, and whether it’s display math).
+fn render_math<'a>(mut node: Node<'a>, render: &dyn Fn(String, bool) -> String) -> Node<'a> {
+    struct RenderMath<'r> {
+        render: &'r dyn Fn(String, bool) -> String,
+    }
+    impl<'r, 'a> VisitorMut<'a> for RenderMath<'r> {
+        fn visit_node_mut(&mut self, node: &mut Node<'a>) {
+            let display = match node {
+                Node::Tag(tag) if tag.has_name("equation") => true,
+                Node::Tag(tag) if tag.has_name(INLINE_MATH_TAG) => false,
+                node => return walk_node_mut(self, node),
+            };
+            let children = node
+                .unwrap_tag_mut()
+                .map(|x| std::mem::take(&mut x.children))
+                .unwrap_or_default();
+            let latex = (self.render)(to_latex_source(children), display);
+            *node = Node::String(Ann::unannotated(Cow::Owned(latex)));
+        }
+    }
+    RenderMath {render}.visit_node_mut(&mut node);
+    node
+}
+
 /// Entrypoint.
 pub fn latex_pass<'a>(node: Node<'a>) -> Node<'a> {
-    match node {
-        Node::Tag(tag) if tag.has_name("equation") => {
-            let node = tag.children
-                .into_iter()
-                .flat_map(Node::unblock)
-                .map(to_valid_latex_math)
-                .map(|x| x.to_string())
-                .collect::<Vec<_>>()
-                .join("");
+    render_math(node, &|latex, display| {
+        if display {
             let start = "\\begin{equation}\\begin{split}";
             let end = "\\end{split}\\end{equation}";
-            Node::String(Ann::unannotated(Cow::Owned(format!(
-                "\\[{}{}{}\\]",
-                start,
-                node,
-                end,
-            ))))
+            format!("\\[{}{}{}\\]", start, latex, end)
+        } else {
+            format!("\\({}\\)", latex)
         }
-        Node::Tag(tag) if tag.has_name(INLINE_MATH_TAG) => {
-            let node = tag.children
-                .into_iter()
-                .flat_map(Node::unblock)
-                .map(to_valid_latex_math)
-                .map(|x| x.to_string())
-                .collect::<Vec<_>>()
-                .join("");
-            Node::String(Ann::unannotated(Cow::Owned(format!(
-                "\\({}\\)",
-                node,
-            ))))
-        }
-        Node::Tag(mut tag) => {
-            tag.children = tag.children
-                .into_iter()
-                .map(latex_pass)
-                .collect();
-            Node::Tag(tag)
-        }
-        Node::Enclosure(mut block) => {
-            block.data.children = block.data.children
-                .into_iter()
-                .map(latex_pass)
-                .collect();
-            Node::Enclosure(block)
-        }
-        node @ Node::Ident(_) => node,
-        node @ Node::String(_) => node,
-        node @ Node::InvalidToken(_) => node,
-    }
+    })
+}
+
+/// Entrypoint for targets without MathJax (e.g. EPUB); renders math as MathML.
+/// The given macros are expanded in addition to the built-in ones.
+pub fn mathml_pass<'a>(node: Node<'a>, macros: &[MathMacro]) -> Node<'a> {
+    render_math(node, &|latex, display| {
+        crate::codegen::mathml::latex_to_mathml_with(&latex, display, macros)
+    })
 }
 
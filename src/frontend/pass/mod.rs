@@ -2,3 +2,14 @@ pub mod to_html;
 pub mod pp_normalize;
 pub mod math;
 pub mod html_normalize;
+pub mod images;
+pub mod code;
+pub mod table;
+pub mod list;
+pub mod paragraph;
+pub mod inline_format;
+pub mod typography;
+pub mod layout;
+pub mod notes;
+pub mod glossary;
+pub mod tags;
@@ -0,0 +1,428 @@
+//! Typed notes, i.e. theorems, lemmas, definitions, proofs, examples,
+//! exercises & warnings.
+//!
+//! ```text
+//! \theorem[title='Pythagoras' id=pythagoras]{...}
+//! \proof{...}
+//! \note[kind=example]{...}
+//! ```
+//!
+//! Each becomes a `\note[kind=...]`, prefixed with a label (e.g. “Theorem 2
+//! (Pythagoras).”). Numbered kinds are counted per kind in document order
+//! (unless `unnumbered`), and get an `id` (e.g. `theorem-2`, unless given) so
+//! that they can be linked to. Proofs end with a QED marker.
+//!
+//! A `\solution` following (or else within) an exercise is linked to it
+//! (i.e. `exercise-2` & `exercise-2-solution`), and depending on
+//! `Settings::solutions` either collapsed into a `details` block, removed, or
+//! moved into a “Solutions” section at the end of the document:
+//!
+//! ```text
+//! \exercise{Show that ...}
+//! \solution{...}
+//! ```
+//!
+//! Run before the tag rewriting of `html_normalize`, which turns the notes
+//! into `div[macro=note]`.
+use std::borrow::Cow;
+use std::collections::HashMap;
+use crate::frontend::ast::*;
+use crate::frontend::data::{INLINE_MATH_TAG, BLOCK_MATH_TAGS};
+use crate::frontend::visit::{VisitorMut, walk_node_mut, walk_tag_mut};
+use crate::codegen::settings::{Settings, Solutions};
+
+pub struct NoteKind {
+    /// The tag name & `kind` value.
+    pub name: &'static str,
+    pub label: &'static str,
+    pub numbered: bool,
+}
+
+pub static NOTE_KINDS: &[NoteKind] = &[
+    NoteKind {name: "theorem", label: "Theorem", numbered: true},
+    NoteKind {name: "lemma", label: "Lemma", numbered: true},
+    NoteKind {name: "definition", label: "Definition", numbered: true},
+    NoteKind {name: "proof", label: "Proof", numbered: false},
+    NoteKind {name: "example", label: "Example", numbered: true},
+    NoteKind {name: "exercise", label: "Exercise", numbered: true},
+    NoteKind {name: "warning", label: "Warning", numbered: false},
+];
+
+/// The end of proof marker.
+pub static QED_MARKER: &str = "∎";
+
+fn parameter_value(tag: &Tag, key: &str) -> Option<String> {
+    tag.get_parameter_value(key)
+        .map(|x| x.trim_matches(|x| x == '\'' || x == '"').to_owned())
+}
+
+/// The kind of the given tag, i.e. `\theorem{...}` or
+/// `\note[kind=theorem]{...}`.
+pub fn note_kind(tag: &Tag) -> Option<&'static NoteKind> {
+    let name = if tag.has_name("note") {
+        parameter_value(tag, "kind")?
+    } else {
+        tag.name().to_owned()
+    };
+    NOTE_KINDS.iter().find(|x| x.name == name)
+}
+
+/// E.g. “Theorem 2 (Pythagoras).”, linking to the given id.
+fn label<'a>(prefix: Option<&str>, text: String, id: Option<&str>, title: Option<String>) -> Node<'a> {
+    let text = Node::unannotated_string(text);
+    let mut label = Vec::new();
+    if let Some(prefix) = prefix {
+        label.push(Node::unannotated_string(prefix.to_owned()));
+    }
+    match id {
+        Some(id) => {
+            let mut link = Tag::new(Ann::unannotated("a"), vec![text]);
+            link.insert_unannotated_parameter(&format!("href=#{}", id));
+            label.push(Node::Tag(link));
+        }
+        None => label.push(text),
+    }
+    if let Some(title) = title {
+        label.push(Node::unannotated_string(format!(" ({})", title)));
+    }
+    label.push(Node::unannotated_str("."));
+    let mut label = Tag::new(Ann::unannotated("span"), label);
+    label.insert_unannotated_parameter("note-label");
+    Node::Tag(label)
+}
+
+/// The label text & id of the lowered note.
+struct Lowered<'a> {
+    tag: Tag<'a>,
+    text: String,
+    id: Option<String>,
+}
+
+fn lower_note<'a>(
+    mut tag: Tag<'a>,
+    kind: &NoteKind,
+    counters: &mut HashMap<&'static str, usize>,
+) -> Lowered<'a> {
+    let numbered = kind.numbered && !tag.has_parameter("unnumbered");
+    let number = if numbered {
+        let counter = counters.entry(kind.name).or_insert(0);
+        *counter += 1;
+        Some(*counter)
+    } else {
+        None
+    };
+    let id = parameter_value(&tag, "id")
+        .or_else(|| number.map(|x| format!("{}-{}", kind.name, x)));
+    let title = parameter_value(&tag, "title");
+    for key in &["kind", "id", "title", "unnumbered"] {
+        tag.remove_parameter(key);
+    }
+    tag.name = Ann::join(tag.name.range(), Cow::Borrowed("note"));
+    tag.insert_unannotated_parameter(&format!("kind={}", kind.name));
+    if let Some(id) = id.as_ref() {
+        tag.insert_unannotated_parameter(&format!("id={}", id));
+    }
+    let text = match number {
+        Some(number) => format!("{} {}", kind.label, number),
+        None => kind.label.to_owned(),
+    };
+    let label = label(None, text.clone(), id.as_deref(), title);
+    let mut children = with_label(tag.children, label);
+    if kind.name == "proof" {
+        let mut qed = Tag::new(
+            Ann::unannotated("span"),
+            vec![Node::unannotated_str(QED_MARKER)],
+        );
+        qed.insert_unannotated_parameter("qed");
+        children.push(Node::unannotated_str(" "));
+        children.push(Node::Tag(qed));
+    }
+    tag.children = children;
+    Lowered {tag, text, id}
+}
+
+/// The given children, prefixed with the label; runs into the first
+/// paragraph (if any).
+fn with_label<'a>(children: Vec<Node<'a>>, label: Node<'a>) -> Vec<Node<'a>> {
+    let label = vec![label, Node::unannotated_str(" ")];
+    let mut children = children
+        .into_iter()
+        .flat_map(Node::unblock)
+        .skip_while(Node::is_whitespace)
+        .collect::<Vec<_>>();
+    if let Some(first) = children.first_mut().and_then(Node::unwrap_string_mut) {
+        *first = Ann::join(first.range(), Cow::Owned(first.data.trim_start().to_owned()));
+    }
+    match children.first_mut().and_then(Node::unwrap_tag_mut) {
+        Some(first) if first.has_name("p") => {
+            let rest = std::mem::take(&mut first.children)
+                .into_iter()
+                .flat_map(Node::unblock);
+            first.children = label.into_iter().chain(rest).collect();
+        }
+        _ => {
+            children.splice(0..0, label);
+        }
+    }
+    children
+}
+
+/// `exercise` is the label text & id of the exercise it belongs to (if any).
+fn lower_solution<'a>(
+    mut tag: Tag<'a>,
+    exercise: Option<(String, Option<String>)>,
+    solutions: Solutions,
+) -> Tag<'a> {
+    let (prefix, text, exercise_id) = match exercise {
+        Some((text, id)) => (Some("Solution to "), text, id),
+        None => (None, String::from("Solution"), None),
+    };
+    let exercise_id = exercise_id.as_deref();
+    let id = parameter_value(&tag, "id")
+        .or_else(|| exercise_id.map(|x| format!("{}-solution", x)));
+    tag.parameters = None;
+    if let Some(id) = id.as_ref() {
+        tag.insert_unannotated_parameter(&format!("id={}", id));
+    }
+    if solutions == Solutions::Appendix {
+        tag.name = Ann::join(tag.name.range(), Cow::Borrowed("note"));
+        tag.insert_unannotated_parameter("kind=solution");
+        let label = label(prefix, text, exercise_id, None);
+        tag.children = with_label(tag.children, label);
+        return tag
+    }
+    tag.name = Ann::join(tag.name.range(), Cow::Borrowed("details"));
+    tag.insert_unannotated_parameter("macro=solution");
+    let mut summary = prefix
+        .map(|x| Node::unannotated_string(x.to_owned()))
+        .into_iter()
+        .collect::<Vec<_>>();
+    match exercise_id {
+        Some(id) => {
+            let mut link = Tag::new(Ann::unannotated("a"), vec![Node::unannotated_string(text)]);
+            link.insert_unannotated_parameter(&format!("href=#{}", id));
+            summary.push(Node::Tag(link));
+        }
+        None => summary.push(Node::unannotated_string(text)),
+    }
+    let summary = Tag::new(Ann::unannotated("summary"), summary);
+    tag.children = std::iter::once(Node::Tag(summary))
+        .chain(tag.children.into_iter().flat_map(Node::unblock))
+        .collect();
+    tag
+}
+
+/// Lowers the notes in document (i.e. pre-) order, so that a solution within
+/// an exercise can be linked to it.
+struct Notes<'s, 'a> {
+    settings: &'s Settings,
+    counters: HashMap<&'static str, usize>,
+    /// The label text & id of the last exercise not followed by a solution
+    /// yet.
+    preceding: Option<(String, Option<String>)>,
+    /// The exercises enclosing the current node, innermost last.
+    enclosing: Vec<(String, Option<String>)>,
+    appendix: Vec<Node<'a>>,
+}
+
+impl<'s, 'a> VisitorMut<'a> for Notes<'s, 'a> {
+    fn visit_node_mut(&mut self, node: &mut Node<'a>) {
+        let tag = match node {
+            Node::Tag(tag) if tag.has_name("solution") => tag,
+            _ => return walk_node_mut(self, node),
+        };
+        let solutions = self.settings.solutions;
+        if solutions == Solutions::Strip {
+            *node = Node::new_fragment(Vec::new());
+            return
+        }
+        // THE PRECEDING EXERCISE, OR ELSE THE ONE IT’S WITHIN
+        let exercise = self.preceding
+            .take()
+            .or_else(|| self.enclosing.last().cloned());
+        let tag = std::mem::replace(tag, Tag::new(Ann::unannotated("solution"), Vec::new()));
+        let mut tag = lower_solution(tag, exercise, solutions);
+        walk_tag_mut(self, &mut tag);
+        match solutions {
+            Solutions::Appendix => {
+                self.appendix.push(Node::Tag(tag));
+                *node = Node::new_fragment(Vec::new());
+            }
+            _ => *node = Node::Tag(tag),
+        }
+    }
+    fn visit_tag_mut(&mut self, tag: &mut Tag<'a>) {
+        if tag.name() == INLINE_MATH_TAG || BLOCK_MATH_TAGS.contains(&tag.name()) {
+            return
+        }
+        let kind = match note_kind(tag) {
+            Some(kind) => kind,
+            None => return walk_tag_mut(self, tag),
+        };
+        let placeholder = Tag::new(Ann::unannotated("note"), Vec::new());
+        let lowered = lower_note(std::mem::replace(tag, placeholder), kind, &mut self.counters);
+        *tag = lowered.tag;
+        if kind.name != "exercise" {
+            return walk_tag_mut(self, tag)
+        }
+        let exercise = (lowered.text, lowered.id);
+        self.preceding = None;
+        self.enclosing.push(exercise.clone());
+        walk_tag_mut(self, tag);
+        self.enclosing.pop();
+        self.preceding = Some(exercise);
+    }
+}
+
+/// Entrypoint.
+pub fn notes_pass<'a>(mut children: Vec<Node<'a>>, settings: &Settings) -> Vec<Node<'a>> {
+    let mut notes = Notes {
+        settings,
+        counters: HashMap::new(),
+        preceding: None,
+        enclosing: Vec::new(),
+        appendix: Vec::new(),
+    };
+    for child in children.iter_mut() {
+        notes.visit_node_mut(child);
+    }
+    let appendix = notes.appendix;
+    if !appendix.is_empty() {
+        let heading = Tag::new(
+            Ann::unannotated("h2"),
+            vec![Node::unannotated_str("Solutions")],
+        );
+        let mut section = Tag::new(Ann::unannotated("section"), appendix);
+        section.insert_unannotated_parameter("id=solutions");
+        children.push(Node::Tag(heading));
+        children.push(Node::Tag(section));
+    }
+    children
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::pass::pp_normalize::run_compiler_frontend;
+    use crate::frontend::printer::print_source;
+
+    fn lower_with(source: &str, settings: &Settings) -> String {
+        print_source(&notes_pass(run_compiler_frontend(source), settings))
+    }
+
+    fn lower(source: &str) -> String {
+        lower_with(source, &Settings::default())
+    }
+
+    #[test]
+    fn numbered_per_kind() {
+        assert_eq!(lower("\\theorem{a} \\lemma{b} \\note[kind=theorem]{c}"), concat!(
+            "\\note[kind=theorem id=theorem-1]{\\span[note-label]{\\a[href=#theorem-1]{Theorem 1}.} a} ",
+            "\\note[kind=lemma id=lemma-1]{\\span[note-label]{\\a[href=#lemma-1]{Lemma 1}.} b} ",
+            "\\note[kind=theorem id=theorem-2]{\\span[note-label]{\\a[href=#theorem-2]{Theorem 2}.} c}",
+        ));
+    }
+
+    #[test]
+    fn ids_titles_and_unnumbered_notes() {
+        let source = "\\definition[id=def title='Group']{a} \\example[unnumbered]{b} \\warning{c}";
+        assert_eq!(lower(source), concat!(
+            "\\note[kind=definition id=def]{",
+            "\\span[note-label]{\\a[href=#def]{Definition 1} (Group).} a} ",
+            "\\note[kind=example]{\\span[note-label]{Example.} b} ",
+            "\\note[kind=warning]{\\span[note-label]{Warning.} c}",
+        ));
+    }
+
+    #[test]
+    fn labels_run_into_the_first_paragraph() {
+        assert_eq!(lower("\\theorem{\\p{First.} \\p{Second.}}"), concat!(
+            "\\note[kind=theorem id=theorem-1]{",
+            "\\p{\\span[note-label]{\\a[href=#theorem-1]{Theorem 1}.} First.} \\p{Second.}",
+            "}",
+        ));
+    }
+
+    #[test]
+    fn proofs_end_with_a_qed_marker() {
+        assert_eq!(
+            lower("\\proof{Trivial.}"),
+            "\\note[kind=proof]{\\span[note-label]{Proof.} Trivial. \\span[qed]{∎}}",
+        );
+    }
+
+    #[test]
+    fn other_notes_and_math_are_left_as_is() {
+        let source = "\\note{a} \\note[kind=aside]{b} \\equation{\\theorem}";
+        assert_eq!(lower(source), source);
+    }
+
+    #[test]
+    fn links_to_notes_in_markdown() {
+        let source = "\\theorem[title=A]{x} See \\a[href=#theorem-1]{Theorem 1}.";
+        let output = crate::codegen::markdown::Document::from_source(source);
+        // In-document links are rendered as their text.
+        assert_eq!(output.render_to_string(), "> Theorem 1 (A). x\n\nSee Theorem 1.\n");
+    }
+
+    #[test]
+    fn hidden_solutions() {
+        assert_eq!(lower("\\exercise{Show it.} \\solution{Done.}"), concat!(
+            "\\note[kind=exercise id=exercise-1]{",
+            "\\span[note-label]{\\a[href=#exercise-1]{Exercise 1}.} Show it.} ",
+            "\\details[id=exercise-1-solution macro=solution]{",
+            "\\summary{Solution to \\a[href=#exercise-1]{Exercise 1}}Done.}",
+        ));
+    }
+
+    #[test]
+    fn stripped_solutions() {
+        let settings = Settings {solutions: Solutions::Strip, ..Settings::default()};
+        assert_eq!(lower_with("\\exercise{Show it.} \\solution{Done.}", &settings), concat!(
+            "\\note[kind=exercise id=exercise-1]{",
+            "\\span[note-label]{\\a[href=#exercise-1]{Exercise 1}.} Show it.} ",
+        ));
+    }
+
+    #[test]
+    fn appendix_solutions() {
+        let settings = Settings {solutions: Solutions::Appendix, ..Settings::default()};
+        let source = "\\exercise{A.} \\solution{a} \\exercise{B.} \\solution[id=s]{b}";
+        assert_eq!(lower_with(source, &settings), concat!(
+            "\\note[kind=exercise id=exercise-1]{",
+            "\\span[note-label]{\\a[href=#exercise-1]{Exercise 1}.} A.}  ",
+            "\\note[kind=exercise id=exercise-2]{",
+            "\\span[note-label]{\\a[href=#exercise-2]{Exercise 2}.} B.} ",
+            "\\h2{Solutions}\\section[id=solutions]{",
+            "\\note[id=exercise-1-solution kind=solution]{",
+            "\\span[note-label]{Solution to \\a[href=#exercise-1]{Exercise 1}.} a}",
+            "\\note[id=s kind=solution]{",
+            "\\span[note-label]{Solution to \\a[href=#exercise-2]{Exercise 2}.} b}",
+            "}",
+        ));
+    }
+
+    #[test]
+    fn nested_solutions() {
+        assert_eq!(lower("\\exercise{A. \\solution{a}}"), concat!(
+            "\\note[kind=exercise id=exercise-1]{",
+            "\\span[note-label]{\\a[href=#exercise-1]{Exercise 1}.} A. ",
+            "\\details[id=exercise-1-solution macro=solution]{",
+            "\\summary{Solution to \\a[href=#exercise-1]{Exercise 1}}a}",
+            "}",
+        ));
+    }
+
+    #[test]
+    fn solutions_are_linked_to_one_exercise() {
+        let source = "\\exercise{A.} \\solution{a} \\solution{b} \\solution{c}";
+        assert_eq!(lower(source), concat!(
+            "\\note[kind=exercise id=exercise-1]{",
+            "\\span[note-label]{\\a[href=#exercise-1]{Exercise 1}.} A.} ",
+            "\\details[id=exercise-1-solution macro=solution]{",
+            "\\summary{Solution to \\a[href=#exercise-1]{Exercise 1}}a} ",
+            "\\details[macro=solution]{\\summary{Solution}b} ",
+            "\\details[macro=solution]{\\summary{Solution}c}",
+        ));
+    }
+}
@@ -0,0 +1,193 @@
+//! Paragraph inference.
+//!
+//! Text at the top level of the document and directly within the `\note`,
+//! `\layout`, `\section` & `\solution` blocks is split on blank lines into `p` elements.
+//! Inline tags (e.g. `\b` or `\{...}` math) stay within their paragraph, while
+//! block level tags (see `BLOCK_LEVEL_TAGS`) end it. Runs without any text
+//! (e.g. a sequence of `\img` tags) are left as is.
+//!
+//! Opt-in (see `Passes::paragraphs`, `--paragraphs` or `paragraphs = true` in
+//! `subscript.toml`), since it changes the output of existing documents.
+//!
+//! ```text
+//! \note[no-paragraphs]{
+//!     Opt out for the contents of this block.
+//! }
+//! ```
+use std::rc::Rc;
+use std::borrow::Cow;
+use crate::frontend::data::*;
+use crate::frontend::ast::*;
+
+/// The parameter for opting out of paragraph inference.
+pub static NO_PARAGRAPHS_PARAMETER: &str = "no-paragraphs";
+
+fn is_paragraph_container(tag: &Tag) -> bool {
+    let is_macro = |name: &str| {
+        tag.get_parameter_value("macro")
+            .map(|x| x.trim_matches('\'') == name)
+            .unwrap_or(false)
+    };
+    tag.has_name("section") || is_macro("note") || is_macro("layout") || is_macro("solution")
+}
+
+fn is_block_level(node: &Node) -> bool {
+    match node {
+        Node::Tag(tag) => BLOCK_LEVEL_TAGS.contains(&tag.name()),
+        _ => false,
+    }
+}
+
+/// Splits the text on blank lines (i.e. lines containing only whitespace).
+fn split_blank_lines(text: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut start = 0;
+    let mut offset = 0;
+    let mut in_break = false;
+    for (ix, line) in text.split_inclusive('\n').enumerate() {
+        // THE FIRST SEGMENT CONTINUES THE LINE OF THE PRECEDING NODE
+        let is_blank = ix > 0 && line.ends_with('\n') && line.trim().is_empty();
+        if is_blank {
+            if !in_break {
+                parts.push(&text[start..offset]);
+            }
+            start = offset + line.len();
+        }
+        in_break = is_blank;
+        offset += line.len();
+    }
+    parts.push(&text[start..]);
+    parts
+}
+
+/// Wraps the pending run in a paragraph, unless it doesn’t contain any text.
+fn flush<'a>(run: &mut Vec<Node<'a>>, results: &mut Vec<Node<'a>>) {
+    let mut run = std::mem::take(run);
+    let has_text = run
+        .iter()
+        .any(|x| x.unwrap_string().is_some() && !x.is_whitespace());
+    if !has_text {
+        results.extend(run);
+        return
+    }
+    while run.first().map(Node::is_whitespace).unwrap_or(false) {
+        results.push(run.remove(0));
+    }
+    let mut trailing = Vec::new();
+    while run.last().map(Node::is_whitespace).unwrap_or(false) {
+        trailing.insert(0, run.pop().unwrap());
+    }
+    // KEEP THE SURROUNDING WHITESPACE (I.E. INDENTATION) OUTSIDE OF THE PARAGRAPH
+    if let Some(first) = run.first_mut().and_then(Node::unwrap_string_mut) {
+        let trimmed = first.data.trim_start().to_owned();
+        let whitespace = first.data[..first.data.len() - trimmed.len()].to_owned();
+        *first = Ann::join(first.range(), Cow::Owned(trimmed));
+        if !whitespace.is_empty() {
+            results.push(Node::unannotated_string(whitespace));
+        }
+    }
+    if let Some(last) = run.last_mut().and_then(Node::unwrap_string_mut) {
+        let trimmed = last.data.trim_end().to_owned();
+        let whitespace = last.data[trimmed.len()..].to_owned();
+        *last = Ann::join(last.range(), Cow::Owned(trimmed));
+        if !whitespace.is_empty() {
+            trailing.insert(0, Node::unannotated_string(whitespace));
+        }
+    }
+    results.push(Node::unannotated_tag("p", run));
+    results.extend(trailing);
+}
+
+/// Groups the given (merged) children into paragraphs.
+pub fn infer_paragraphs<'a>(children: Vec<Node<'a>>) -> Vec<Node<'a>> {
+    let mut results = Vec::new();
+    let mut run = Vec::new();
+    for node in children.into_iter().flat_map(Node::into_fragment) {
+        match node {
+            Node::String(txt) => {
+                let range = txt.range();
+                for (ix, part) in split_blank_lines(&txt.data).into_iter().enumerate() {
+                    if ix > 0 {
+                        flush(&mut run, &mut results);
+                        results.push(Node::unannotated_str("\n\n"));
+                    }
+                    if !part.is_empty() {
+                        run.push(Node::String(Ann::join(range, Cow::Owned(part.to_owned()))));
+                    }
+                }
+            }
+            node if is_block_level(&node) => {
+                flush(&mut run, &mut results);
+                results.push(node);
+            }
+            node => run.push(node),
+        }
+    }
+    flush(&mut run, &mut results);
+    results
+}
+
+/// Entrypoint. Run after the text content has been merged, for the top level
+/// children of the document.
+pub fn paragraph_pass<'a>(children: Vec<Node<'a>>) -> Vec<Node<'a>> {
+    let f = |env: NodeEnvironment<'a>, node: Node<'a>| -> Node<'a> {
+        match node {
+            Node::Tag(mut tag) if is_paragraph_container(&tag) && env.is_default_env() => {
+                if tag.has_parameter(NO_PARAGRAPHS_PARAMETER) {
+                    tag.remove_parameter(NO_PARAGRAPHS_PARAMETER);
+                } else {
+                    tag.children = infer_paragraphs(tag.children);
+                }
+                Node::Tag(tag)
+            }
+            node => node,
+        }
+    };
+    let children = Node::new_fragment(children)
+        .transform(NodeEnvironment::default(), Rc::new(f))
+        .into_fragment();
+    infer_paragraphs(children)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::pass::pp_normalize::run_compiler_frontend;
+    use crate::frontend::pass::html_normalize::{node_passes, child_list_passes};
+    use crate::frontend::printer::print_source;
+
+    /// After the `html-tags` pass, i.e. with the adjacent text merged.
+    fn infer(source: &str) -> String {
+        let nodes = run_compiler_frontend(source)
+            .into_iter()
+            .map(node_passes)
+            .collect();
+        print_source(&paragraph_pass(child_list_passes(nodes)))
+    }
+
+    #[test]
+    fn blank_lines() {
+        assert_eq!(split_blank_lines("a\n \nb\n\n\nc"), vec!["a\n", "b\n", "c"]);
+    }
+
+    #[test]
+    fn top_level_text() {
+        assert_eq!(infer("a\n\nb \\b{c}\n"), "\\p{a}\n\n\n\\p{b \\b{c}}\n");
+    }
+
+    #[test]
+    fn block_tags_end_paragraphs() {
+        assert_eq!(infer("a\n\\h2{T}\nb"), "\\p{a}\n\\h2{T}\n\\p{b}");
+    }
+
+    #[test]
+    fn containers() {
+        assert_eq!(infer("\\note{x\n\ny}"), "\\div[macro=note]{\\p{x}\n\n\n\\p{y}}");
+        assert_eq!(infer("\\note[no-paragraphs]{x\n\ny}"), "\\div[macro=note]{x\n\ny}");
+    }
+
+    #[test]
+    fn runs_without_text() {
+        assert_eq!(infer("\\img[src=a.png] \\img[src=b.png]"), "\\img[src=a.png] \\img[src=b.png]");
+    }
+}
@@ -99,6 +99,15 @@ pub fn to_unnormalized_backend_ir<'a>(children: Vec<Node<'a>>) -> Vec<Node<'a>>
                 );
                 Some(new_node)
             }
+            Node::Ident(node) if VOID_SUBSCRIPT_TAGS.contains(&node.data.as_ref()) => {
+                let new_node = Node::Tag(Tag {
+                    name: node,
+                    parameters: None,
+                    children: Vec::new(),
+                    rewrite_rules: Vec::new(),
+                });
+                Some(new_node)
+            }
             Node::Ident(node) => {
                 let new_node = Node::Ident(node);
                 Some(new_node)
@@ -179,16 +188,51 @@ pub fn block_level_normalize<'a>(children: Vec<Node<'a>>) -> Vec<Node<'a>> {
     results
 }
 
+/// Splits on whitespace, except within quoted values (e.g. `title='A Title'`)
+/// and around the `=` of some `key = value` parameter.
+fn split_parameters(source: &str) -> Vec<String> {
+    let mut results: Vec<String> = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    for c in source.chars() {
+        match (quote, c) {
+            (None, '=') if current.is_empty() && !results.is_empty() => {
+                current = results.pop().unwrap();
+                current.push(c);
+            }
+            (None, c) if c.is_whitespace() && current.ends_with('=') => (),
+            (None, '\'' | '"') if current.is_empty() || current.ends_with('=') => {
+                quote = Some(c);
+                current.push(c);
+            }
+            (Some(q), c) if c == q => {
+                quote = None;
+                current.push(c);
+            }
+            (None, c) if c.is_whitespace() => {
+                if !current.is_empty() {
+                    results.push(std::mem::take(&mut current));
+                }
+            }
+            (_, c) => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        results.push(current);
+    }
+    results
+}
+
 pub fn parameter_level_normalize_pass(node: Node) -> Node {
     fn go(parameters: Vec<Node>) -> Vec<Node> {
-        parameters
+        let parameters = parameters
             .iter()
             .filter_map(Node::get_string)
-            .map(|x| x.data)
+            .map(|x| unescape(&x.data).map(Cow::Borrowed).unwrap_or(x.data))
             .collect::<Vec<_>>()
-            .join("")
-            .split_whitespace()
-            .map(ToOwned::to_owned)
+            .join("");
+        split_parameters(&parameters)
+            .into_iter()
             .map(|x| Node::String(Ann::unannotated(Cow::Owned(x))))
             .collect::<Vec<_>>()
     }
@@ -218,3 +262,22 @@ pub fn run_compiler_frontend<'a>(source: &'a str) -> Vec<Node<'a>> {
     // DONE
     node.into_fragment()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parameters() {
+        assert_eq!(
+            split_parameters("header  kind = theorem title='A Title' id= x"),
+            vec!["header", "kind=theorem", "title='A Title'", "id=x"],
+        );
+    }
+
+    #[test]
+    fn void_tags() {
+        let nodes = run_compiler_frontend("a \\pagebreak b \\other");
+        assert_eq!(crate::frontend::printer::print_source(&nodes), "a \\pagebreak{} b \\other");
+    }
+}
@@ -0,0 +1,310 @@
+//! Tables with a compact row & column syntax.
+//!
+//! ```text
+//! \table[header align=lcr]{
+//!     Name   & Symbol & Unit \\
+//!     Charge & \{q}   & C    \\
+//!     Energy & \{U}   & J
+//! }{Caption text}
+//! ```
+//!
+//! Columns are separated by a standalone `&` (i.e. surrounded by whitespace,
+//! so that e.g. `&nbsp;` is kept) and rows by `\\` (as in `\equation`). The
+//! `header` parameter turns the first row (or `header=N` rows) into header
+//! cells, `align` takes one of `l`, `c` or `r` per column, and an optional
+//! trailing block becomes the (numbered) caption. Tables written with explicit
+//! `\tr` rows are left untouched.
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::borrow::Cow;
+use crate::frontend::data::*;
+use crate::frontend::ast::*;
+
+/// Backend agnostic table model.
+#[derive(Debug, Clone, Default)]
+pub struct Table<'a> {
+    pub header_rows: usize,
+    pub alignment: Vec<Alignment>,
+    pub rows: Vec<Vec<Vec<Node<'a>>>>,
+    pub caption: Option<Vec<Node<'a>>>,
+    pub number: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Alignment {
+    Left,
+    Center,
+    Right,
+}
+
+impl Alignment {
+    pub fn from_char(x: char) -> Option<Self> {
+        match x {
+            'l' => Some(Alignment::Left),
+            'c' => Some(Alignment::Center),
+            'r' => Some(Alignment::Right),
+            _ => None,
+        }
+    }
+    pub fn as_css(&self) -> &'static str {
+        match self {
+            Alignment::Left => "left",
+            Alignment::Center => "center",
+            Alignment::Right => "right",
+        }
+    }
+}
+
+fn is_row_break(node: &Node) -> bool {
+    node.unwrap_string()
+        .map(|x| x.data == "\\\\")
+        .unwrap_or(false)
+}
+
+/// A standalone `&`, so that e.g. `&nbsp;` or `AT&T` isn’t split.
+fn is_column_break(node: &Node) -> bool {
+    node.unwrap_string()
+        .map(|x| x.data.trim() == "&")
+        .unwrap_or(false)
+}
+
+fn trim_cell(mut cell: Vec<Node>) -> Vec<Node> {
+    while cell.first().map(Node::is_whitespace).unwrap_or(false) {
+        cell.remove(0);
+    }
+    while cell.last().map(Node::is_whitespace).unwrap_or(false) {
+        cell.pop();
+    }
+    cell
+}
+
+/// Splits the (unblocked) body of some table into rows of cells.
+fn parse_rows(body: Vec<Node>) -> Vec<Vec<Vec<Node>>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut cell = Vec::new();
+    for node in body {
+        match node {
+            node if is_row_break(&node) => {
+                row.push(trim_cell(std::mem::take(&mut cell)));
+                rows.push(std::mem::take(&mut row));
+            }
+            node if is_column_break(&node) => {
+                row.push(trim_cell(std::mem::take(&mut cell)));
+            }
+            node => cell.push(node),
+        }
+    }
+    row.push(trim_cell(cell));
+    rows.push(row);
+    rows.into_iter()
+        .filter(|row| row.iter().any(|cell| !cell.is_empty()))
+        .collect()
+}
+
+fn is_compact_table(tag: &Tag) -> bool {
+    let has_html_rows = tag.children
+        .iter()
+        .flat_map(|x| x.unwrap_curly_brace().cloned().unwrap_or_default())
+        .any(|x| {
+            ["tr", "thead", "tbody", "tfoot", "caption"]
+                .iter()
+                .any(|name| x.is_named_block(name))
+        });
+    tag.has_name("table") && !has_html_rows
+}
+
+impl<'a> Table<'a> {
+    pub fn from_tag(tag: &Tag<'a>) -> Self {
+        let mut blocks = tag.children
+            .iter()
+            .filter(|x| !x.is_whitespace())
+            .cloned()
+            .map(Node::unblock)
+            .collect::<Vec<_>>();
+        let caption = if blocks.len() > 1 {blocks.pop()} else {None};
+        let body = blocks.into_iter().flatten().collect::<Vec<_>>();
+        let header_rows = match tag.get_parameter_value("header") {
+            Some(x) => x.parse::<usize>().unwrap_or(1),
+            None if tag.has_parameter("header") => 1,
+            None => 0,
+        };
+        let alignment = tag
+            .get_parameter_value("align")
+            .map(|x| x.chars().filter_map(Alignment::from_char).collect())
+            .unwrap_or_default();
+        Table {
+            header_rows,
+            alignment,
+            rows: parse_rows(body),
+            caption,
+            number: None,
+        }
+    }
+    pub fn columns(&self) -> usize {
+        self.rows
+            .iter()
+            .map(Vec::len)
+            .max()
+            .unwrap_or(0)
+    }
+    /// The id used for linking to a numbered table.
+    pub fn id(&self) -> Option<String> {
+        self.number.map(|x| format!("table-{}", x))
+    }
+    /// Lowers the table to the HTML `table` element (in the frontend AST).
+    pub fn to_html_tag(self, name: Ann<Atom<'a>>) -> Tag<'a> {
+        let columns = self.columns();
+        let id = self.id();
+        let Table {header_rows, alignment, rows, caption, number} = self;
+        let to_row = |row: Vec<Vec<Node<'a>>>, cell_name: &'static str| -> Node<'a> {
+            let mut row = row;
+            row.resize(columns, Vec::new());
+            let cells = row
+                .into_iter()
+                .enumerate()
+                .map(|(ix, cell)| {
+                    let mut cell = Tag::new(Ann::unannotated(cell_name), cell);
+                    if let Some(align) = alignment.get(ix) {
+                        cell.insert_unannotated_parameter(&format!(
+                            "style='text-align:{};'",
+                            align.as_css(),
+                        ));
+                    }
+                    Node::Tag(cell)
+                })
+                .collect();
+            Node::unannotated_tag("tr", cells)
+        };
+        let mut rows = rows.into_iter();
+        let head = rows
+            .by_ref()
+            .take(header_rows)
+            .map(|row| to_row(row, "th"))
+            .collect::<Vec<_>>();
+        let body = rows
+            .map(|row| to_row(row, "td"))
+            .collect::<Vec<_>>();
+        let mut children = Vec::new();
+        if let Some(caption) = caption {
+            let mut contents = Vec::new();
+            if let Some(number) = number {
+                let mut label = Tag::new(
+                    Ann::unannotated("span"),
+                    vec![Node::unannotated_string(format!("Table {}.", number))],
+                );
+                label.insert_unannotated_parameter("macro=caption-label");
+                contents.push(Node::Tag(label));
+                contents.push(Node::unannotated_str(" "));
+            }
+            contents.extend(caption);
+            children.push(Node::unannotated_tag("caption", contents));
+        }
+        if !head.is_empty() {
+            children.push(Node::unannotated_tag("thead", head));
+        }
+        children.push(Node::unannotated_tag("tbody", body));
+        let mut tag = Tag {
+            name: name.map(|_| Cow::Borrowed("table")),
+            parameters: None,
+            children,
+            rewrite_rules: Vec::new(),
+        };
+        tag.insert_unannotated_parameter("macro=table");
+        if let Some(id) = id {
+            tag.insert_unannotated_parameter(&format!("id={}", id));
+        }
+        tag
+    }
+}
+
+/// Entrypoint. Must run before the escape sequences (i.e. the `\\` row
+/// breaks) are converted into literal text.
+pub fn table_pass<'a>(children: Vec<Node<'a>>) -> Vec<Node<'a>> {
+    let mut counter = 0;
+    let f = |env: NodeEnvironment<'a>, node: Node<'a>| -> Node<'a> {
+        match node {
+            Node::Tag(tag) if is_compact_table(&tag) && env.is_default_env() => {
+                let mut table = Table::from_tag(&tag);
+                if table.caption.is_some() {
+                    counter += 1;
+                    table.number = Some(counter);
+                }
+                let mut lowered = table.to_html_tag(tag.name.clone());
+                for parameter in tag.parameters.unwrap_or_default() {
+                    let is_table_option = parameter
+                        .unwrap_string()
+                        .map(|x| {
+                            let key = x.data.split('=').next().unwrap_or("").trim();
+                            key == "header" || key == "align"
+                        })
+                        .unwrap_or(false);
+                    if !is_table_option {
+                        lowered.parameters.get_or_insert_with(Vec::new).push(parameter);
+                    }
+                }
+                lowered.rewrite_rules = tag.rewrite_rules;
+                Node::Tag(lowered)
+            }
+            node => node,
+        }
+    };
+    Node::new_fragment(children)
+        .transform_mut(NodeEnvironment::default(), Rc::new(RefCell::new(f)))
+        .into_fragment()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::pass::pp_normalize::run_compiler_frontend;
+    use crate::frontend::printer::print_source;
+
+    fn lower(source: &str) -> String {
+        print_source(&table_pass(run_compiler_frontend(source)))
+    }
+
+    #[test]
+    fn header_alignment_and_caption() {
+        assert_eq!(
+            lower("\\table[header align=lr]{A & B \\\\ 1 & 2}{Units}"),
+            concat!(
+                "\\table[macro=table id=table-1]{",
+                "\\caption{\\span[macro=caption-label]{Table 1.} Units}",
+                "\\thead{\\tr{\\th[style='text-align:left;']{A}\\th[style='text-align:right;']{B}}}",
+                "\\tbody{\\tr{\\td[style='text-align:left;']{1}\\td[style='text-align:right;']{2}}}",
+                "}",
+            ),
+        );
+    }
+
+    #[test]
+    fn captioned_tables_are_numbered() {
+        let output = lower("\\table{a}{First} \\table{b} \\table{c}{Second}");
+        assert!(output.contains("\\table[macro=table id=table-1]{\\caption{\\span[macro=caption-label]{Table 1.} First}"));
+        assert!(output.contains("\\table[macro=table]{\\tbody{\\tr{\\td{b}}}}"));
+        assert!(output.contains("\\table[macro=table id=table-2]{\\caption{\\span[macro=caption-label]{Table 2.} Second}"));
+    }
+
+    #[test]
+    fn only_standalone_ampersands_separate_cells() {
+        assert_eq!(
+            lower("\\table{AT&T & &nbsp; & x &amp; y}"),
+            "\\table[macro=table]{\\tbody{\\tr{\\td{AT&T}\\td{&nbsp;}\\td{x &amp; y}}}}",
+        );
+    }
+
+    #[test]
+    fn spaced_options() {
+        assert_eq!(
+            lower("\\table[header = 2 id=t]{a \\\\ b \\\\ c}"),
+            "\\table[macro=table id=t]{\\thead{\\tr{\\th{a}}\\tr{\\th{b}}}\\tbody{\\tr{\\td{c}}}}",
+        );
+    }
+
+    #[test]
+    fn explicit_rows_are_left_as_is() {
+        let source = "\\table{\\tr{\\td{a & b}}}";
+        assert_eq!(lower(source), source);
+    }
+}
@@ -0,0 +1,91 @@
+//! Reports unknown tags.
+//!
+//! Tags other than the Subscript & HTML tags (see `ALL_SUBSCRIPT_TAGS` &
+//! `ALLOWED_HTML_TAGS`) are likely typos, or math outside of `\{...}` &
+//! `\equation`. Custom tags (e.g. for a stylesheet) are allowed with the
+//! `allowed-tags` setting (see `Settings::allowed_tags`). The contents of
+//! math & verbatim tags aren’t checked.
+use crate::frontend::ast::*;
+use crate::frontend::data::*;
+use crate::frontend::diagnostic::Diagnostic;
+use crate::frontend::visit::{Visitor, walk_tag};
+use crate::codegen::settings::Settings;
+
+pub fn is_allowed_tag(name: &str, settings: &Settings) -> bool {
+    ALL_SUBSCRIPT_TAGS.contains(&name)
+        || ALLOWED_HTML_TAGS.contains(&name)
+        || HEADING_TAG_NAMES.contains(name)
+        || settings.allowed_tags.iter().any(|x| x == name)
+}
+
+struct UnknownTags<'s> {
+    settings: &'s Settings,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl<'n, 'a: 'n, 's> Visitor<'n, 'a> for UnknownTags<'s> {
+    fn visit_tag(&mut self, tag: &'n Tag<'a>) {
+        let name = tag.name();
+        if name == INLINE_MATH_TAG || BLOCK_MATH_TAGS.contains(&name) || VERBATIM_TAGS.contains(&name) {
+            return
+        }
+        if !is_allowed_tag(name, self.settings) {
+            let message = format!("unknown tag `\\{}`; see the `allowed-tags` setting", name);
+            self.diagnostics.push(Diagnostic::warning(tag.name.range(), message));
+        }
+        walk_tag(self, tag);
+    }
+}
+
+/// Entrypoint; run before the passes that introduce tags of their own.
+pub fn unknown_tags(nodes: &[Node], settings: &Settings) -> Vec<Diagnostic> {
+    let mut visitor = UnknownTags {settings, diagnostics: Vec::new()};
+    for node in nodes {
+        visitor.visit_node(node);
+    }
+    visitor.diagnostics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::pass::pp_normalize::run_compiler_frontend;
+
+    fn messages(source: &str, settings: &Settings) -> Vec<String> {
+        unknown_tags(&run_compiler_frontend(source), settings)
+            .into_iter()
+            .map(|x| x.message)
+            .collect()
+    }
+
+    #[test]
+    fn known_tags() {
+        let source = "\\h2{A} \\note{\\b{b} \\img[src=a.png]} \\term{c} \\nav{\\a[href=x]{d}}";
+        assert!(messages(source, &Settings::default()).is_empty());
+    }
+
+    #[test]
+    fn math_and_verbatim_are_skipped() {
+        let source = "\\{\\mathrm{x}} \\equation{\\frac{1}{2}} \\verb{\\foo} \\code{\\bar{}}";
+        assert!(messages(source, &Settings::default()).is_empty());
+    }
+
+    #[test]
+    fn unknown_tags_are_reported() {
+        let source = "\\note{\\mathrm{x} \\kbd-key{A}}";
+        assert_eq!(messages(source, &Settings::default()), vec![
+            "unknown tag `\\mathrm`; see the `allowed-tags` setting",
+            "unknown tag `\\kbd-key`; see the `allowed-tags` setting",
+        ]);
+        let diagnostics = unknown_tags(&run_compiler_frontend(source), &Settings::default());
+        assert_eq!(diagnostics[0].line_column(source), Some((1, 7)));
+    }
+
+    #[test]
+    fn allowed_tags() {
+        let settings = Settings {allowed_tags: vec![String::from("kbd-key")], ..Settings::default()};
+        assert_eq!(messages("\\kbd-key{A} \\kdb-key{B}", &settings), vec![
+            "unknown tag `\\kdb-key`; see the `allowed-tags` setting",
+        ]);
+    }
+}
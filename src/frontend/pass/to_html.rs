@@ -10,7 +10,7 @@ use crate::frontend::pass;
 use crate::codegen::html;
 
 /// Ensure that `Node` is first canonicalized!
-/// - I.e. make sure the inputs have been passes through the `html_canonicalization` function.
+/// - I.e. make sure the inputs have been passes through the passes of `compiler::Compiler`.
 pub(crate) fn node_to_html<'a>(node: Node<'a>) -> html::Node<'a> {
     fn enclosure<'a>(
         start: &'a str,
@@ -66,6 +66,19 @@ pub(crate) fn node_to_html<'a>(node: Node<'a>) -> html::Node<'a> {
             .collect::<HashMap<_, _>>()
     }
     match node {
+        Node::Tag(node) if node.has_name(INLINE_SVG_TAG) => {
+            let kind = if node.has_parameter("inline") {
+                LayoutKind::Inline
+            } else {
+                LayoutKind::Block
+            };
+            let payload = node.children
+                .iter()
+                .map(Node::to_string)
+                .collect::<Vec<_>>()
+                .join("");
+            html::Node::Image(html::Image::Svg {kind, payload})
+        }
         Node::Tag(node) => {
             html::Node::Element(html::Element {
                 name: Text(node.name.data),
@@ -0,0 +1,342 @@
+//! Opt-in smart typography (see `Settings::typography`).
+//!
+//! * Straight quotes become curly quotes (per `Settings::language`).
+//! * `--` & `---` become en & em dashes, and `...` an ellipsis.
+//! * Non-breaking spaces are inserted after one and two letter words, and
+//!   between numbers & their units (e.g. `5 kg`).
+//! * Long words within notes (e.g. `\theorem` or `\solution`) get soft
+//!   hyphens (i.e. `&shy;`) as hyphenation hints for narrow columns.
+//!
+//! Math environments and verbatim tags are never touched; escape sequences
+//! (e.g. `\\`) are left as is.
+use std::rc::Rc;
+use crate::frontend::data::*;
+use crate::frontend::ast::*;
+use crate::frontend::pass::inline_format::{Piece, into_pieces, from_pieces};
+use crate::frontend::pass::notes::NOTE_KINDS;
+use crate::codegen::settings::Settings;
+
+const NO_BREAK_SPACE: char = '\u{a0}';
+const SOFT_HYPHEN: char = '\u{ad}';
+/// Words shorter than this are never hyphenated.
+const HYPHENATION_MIN_LENGTH: usize = 10;
+
+static UNITS: &[&str] = &[
+    "%", "°", "°C", "°F", "K",
+    "mg", "g", "kg", "t",
+    "nm", "µm", "mm", "cm", "m", "km",
+    "ns", "µs", "ms", "s", "min", "h",
+    "mL", "L",
+    "Hz", "kHz", "MHz", "GHz",
+    "mV", "V", "kV", "mA", "A", "W", "kW", "MW", "Ω", "kΩ", "C", "F", "T",
+    "N", "J", "kJ", "Pa", "kPa", "mol",
+    "B", "KB", "MB", "GB", "TB",
+    "km/h", "m/s",
+];
+
+/// Opening & closing quotation marks.
+struct Quotes {
+    double: (&'static str, &'static str),
+    single: (&'static str, &'static str),
+}
+
+impl Quotes {
+    fn for_language(language: Option<&str>) -> Self {
+        let language = language
+            .and_then(|x| x.split(['-', '_']).next())
+            .unwrap_or("en")
+            .to_lowercase();
+        match language.as_str() {
+            "de" | "cs" | "sk" => Quotes {
+                double: ("„", "“"),
+                single: ("‚", "‘"),
+            },
+            "fr" => Quotes {
+                double: ("«\u{a0}", "\u{a0}»"),
+                single: ("‹\u{a0}", "\u{a0}›"),
+            },
+            "es" | "it" | "pt" | "ru" => Quotes {
+                double: ("«", "»"),
+                single: ("“", "”"),
+            },
+            _ => Quotes {
+                double: ("“", "”"),
+                single: ("‘", "’"),
+            },
+        }
+    }
+}
+
+fn char_at(pieces: &[Piece], ix: usize) -> Option<char> {
+    match pieces.get(ix) {
+        Some(Piece::Char(x)) => Some(*x),
+        _ => None,
+    }
+}
+
+fn is_char_run(pieces: &[Piece], ix: usize, value: char, count: usize) -> bool {
+    (ix..ix + count).all(|ix| char_at(pieces, ix) == Some(value))
+}
+
+/// Whether a quote following the given piece opens a quotation.
+fn is_opening_context(previous: Option<&Piece>) -> bool {
+    match previous {
+        None => true,
+        Some(Piece::Char(x)) => x.is_whitespace() || "([{–—/“‘„‚«‹".contains(*x),
+        Some(Piece::Node(_)) => false,
+    }
+}
+
+fn push_str(output: &mut Vec<Piece>, value: &str) {
+    output.extend(value.chars().map(Piece::Char));
+}
+
+/// Quotes, dashes & ellipses.
+fn replace_punctuation<'a>(mut pieces: Vec<Piece<'a>>, quotes: &Quotes) -> Vec<Piece<'a>> {
+    let mut output: Vec<Piece> = Vec::with_capacity(pieces.len());
+    let mut in_single_quotes = false;
+    let mut ix = 0;
+    while ix < pieces.len() {
+        let current = char_at(&pieces, ix);
+        if current == Some('-') && is_char_run(&pieces, ix, '-', 3) {
+            output.push(Piece::Char('—'));
+            ix += 3;
+            continue;
+        }
+        if current == Some('-') && is_char_run(&pieces, ix, '-', 2) {
+            output.push(Piece::Char('–'));
+            ix += 2;
+            continue;
+        }
+        if current == Some('.') && is_char_run(&pieces, ix, '.', 3) {
+            output.push(Piece::Char('…'));
+            ix += 3;
+            continue;
+        }
+        let is_opening = is_opening_context(output.last());
+        match current {
+            Some('"') if is_opening => push_str(&mut output, quotes.double.0),
+            Some('"') => push_str(&mut output, quotes.double.1),
+            Some('\'') => {
+                let follows_word = output
+                    .last()
+                    .map(|x| matches!(x, Piece::Char(x) if x.is_alphanumeric()))
+                    .unwrap_or(false);
+                let precedes_word = char_at(&pieces, ix + 1)
+                    .map(char::is_alphanumeric)
+                    .unwrap_or(false);
+                // E.G. `it's`, OR `students'` OUTSIDE OF SOME SINGLE QUOTATION
+                let is_apostrophe = follows_word && (precedes_word || !in_single_quotes);
+                if is_apostrophe {
+                    output.push(Piece::Char('’'));
+                } else if is_opening {
+                    push_str(&mut output, quotes.single.0);
+                    in_single_quotes = true;
+                } else {
+                    push_str(&mut output, quotes.single.1);
+                    in_single_quotes = false;
+                }
+            }
+            _ => output.push(std::mem::replace(&mut pieces[ix], Piece::Char(' '))),
+        }
+        ix += 1;
+    }
+    output
+}
+
+/// The word (i.e. run of letters, digits & unit symbols) starting at `ix`.
+fn word_after(pieces: &[Piece], ix: usize) -> String {
+    (ix..pieces.len())
+        .map_while(|ix| char_at(pieces, ix))
+        .take_while(|x| x.is_alphanumeric() || "%°Ωµ/".contains(*x))
+        .collect()
+}
+
+/// The word (i.e. run of letters & digits) ending just before `ix`, and
+/// whether it starts at a word boundary.
+fn word_before(pieces: &[Piece], ix: usize) -> (String, bool) {
+    let start = (0..ix)
+        .rev()
+        .take_while(|ix| char_at(pieces, *ix).map(char::is_alphanumeric).unwrap_or(false))
+        .last()
+        .unwrap_or(ix);
+    let word = (start..ix).filter_map(|ix| char_at(pieces, ix)).collect();
+    let is_boundary = start == 0 || char_at(pieces, start - 1)
+        .map(|x| x.is_whitespace() || "([“‘„‚«‹".contains(x))
+        .unwrap_or(false);
+    (word, is_boundary)
+}
+
+fn insert_no_break_spaces(mut pieces: Vec<Piece>) -> Vec<Piece> {
+    for ix in 0..pieces.len() {
+        if char_at(&pieces, ix) != Some(' ') {
+            continue;
+        }
+        let (before, is_boundary) = word_before(&pieces, ix);
+        let is_short_word = (1..=2).contains(&before.chars().count())
+            && before.chars().all(char::is_alphabetic)
+            && is_boundary;
+        let is_unit = !before.is_empty()
+            && before.chars().all(|x| x.is_ascii_digit())
+            && UNITS.contains(&word_after(&pieces, ix + 1).as_str());
+        if is_short_word || is_unit {
+            pieces[ix] = Piece::Char(NO_BREAK_SPACE);
+        }
+    }
+    pieces
+}
+
+fn is_vowel(x: char) -> bool {
+    "aeiouyAEIOUYáéíóúàèìòùäëïöüâêîôû".contains(x)
+}
+
+/// Inserts soft hyphens into long words, before consonant-vowel pairs that
+/// follow some vowel (e.g. `compu-ta-tion`).
+fn insert_soft_hyphens(pieces: Vec<Piece>) -> Vec<Piece> {
+    let mut output = Vec::with_capacity(pieces.len());
+    let mut word = Vec::new();
+    let flush = |word: &mut Vec<char>, output: &mut Vec<Piece>| {
+        let length = word.len();
+        let mut last = 0;
+        for (ix, x) in word.iter().enumerate() {
+            let is_break = length >= HYPHENATION_MIN_LENGTH
+                && ix >= 3
+                && ix + 3 <= length
+                && ix - last >= 2
+                && is_vowel(word[ix - 1])
+                && !is_vowel(*x)
+                && is_vowel(word[ix + 1]);
+            if is_break {
+                output.push(Piece::Char(SOFT_HYPHEN));
+                last = ix;
+            }
+            output.push(Piece::Char(*x));
+        }
+        word.clear();
+    };
+    for piece in pieces {
+        match piece {
+            Piece::Char(x) if x.is_alphabetic() => word.push(x),
+            piece => {
+                flush(&mut word, &mut output);
+                output.push(piece);
+            }
+        }
+    }
+    flush(&mut word, &mut output);
+    output
+}
+
+fn format_children<'a>(children: Vec<Node<'a>>, quotes: &Quotes, hyphenate: bool) -> Vec<Node<'a>> {
+    let has_text = children
+        .iter()
+        .filter_map(Node::unwrap_string)
+        .any(|x| unescape(&x.data).is_none());
+    if !has_text {
+        return children
+    }
+    let pieces = replace_punctuation(into_pieces(children), quotes);
+    let pieces = insert_no_break_spaces(pieces);
+    let pieces = if hyphenate {insert_soft_hyphens(pieces)} else {pieces};
+    from_pieces(pieces)
+}
+
+/// Whether the given tag is some note, i.e. `\note`, `\solution` or any of
+/// the `NOTE_KINDS` (this pass runs before the notes are lowered).
+fn is_note(name: &str) -> bool {
+    name == "note" || name == "solution" || NOTE_KINDS.iter().any(|x| x.name == name)
+}
+
+/// Entrypoint. Must run before the verbatim tags are lowered and before the
+/// escape sequences are converted into literal text.
+pub fn typography_pass<'a>(children: Vec<Node<'a>>, settings: &Settings) -> Vec<Node<'a>> {
+    if !settings.typography {
+        return children
+    }
+    let quotes = Rc::new(Quotes::for_language(settings.language.as_deref()));
+    let f = move |env: NodeEnvironment<'a>, node: Node<'a>| -> Node<'a> {
+        let is_verbatim = env.parents
+            .iter()
+            .any(|x| VERBATIM_TAGS.contains(&x.as_ref()) || x == INLINE_SVG_TAG);
+        if env.is_math_env() || is_verbatim {
+            return node
+        }
+        let hyphenate = env.parents.iter().any(|x| is_note(x));
+        match node {
+            Node::Tag(mut tag) => {
+                tag.children = format_children(tag.children, &quotes, hyphenate);
+                Node::Tag(tag)
+            }
+            Node::Enclosure(mut node) if !node.data.is_error() => {
+                node.data.children = format_children(node.data.children, &quotes, hyphenate);
+                Node::Enclosure(node)
+            }
+            node => node,
+        }
+    };
+    Node::new_fragment(children)
+        .transform(NodeEnvironment::default(), Rc::new(f))
+        .into_fragment()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::pass::pp_normalize::run_compiler_frontend;
+    use crate::frontend::printer::print_source;
+
+    fn format_with(source: &str, language: Option<&str>) -> String {
+        let settings = Settings {
+            typography: true,
+            language: language.map(ToOwned::to_owned),
+            ..Settings::default()
+        };
+        print_source(&typography_pass(run_compiler_frontend(source), &settings))
+    }
+
+    fn format(source: &str) -> String {
+        format_with(source, None)
+    }
+
+    #[test]
+    fn opt_in() {
+        let source = "\"a\" -- b";
+        let nodes = typography_pass(run_compiler_frontend(source), &Settings::default());
+        assert_eq!(print_source(&nodes), source);
+    }
+
+    #[test]
+    fn quotes() {
+        assert_eq!(format("\"Hello,\" she said, 'it's'"), "“Hello,” she said, ‘it’s’");
+        assert_eq!(format_with("\"Hallo\" 'x'", Some("de-AT")), "„Hallo“ ‚x‘");
+    }
+
+    #[test]
+    fn dashes_and_ellipses() {
+        assert_eq!(format("1990--2000 --- wait..."), "1990–2000 — wait…");
+    }
+
+    #[test]
+    fn no_break_spaces() {
+        assert_eq!(
+            format("Take a look at 5 kg and 10 people"),
+            "Take a\u{a0}look at\u{a0}5\u{a0}kg\u{a0}and 10 people",
+        );
+    }
+
+    #[test]
+    fn soft_hyphens_within_notes() {
+        let source = "representation \\theorem{representation} \\solution{representation}";
+        assert_eq!(
+            format(source),
+            "representation \\theorem{repre\u{ad}senta\u{ad}tion} \\solution{repre\u{ad}senta\u{ad}tion}",
+        );
+    }
+
+    #[test]
+    fn math_and_verbatim_are_left_as_is() {
+        for source in &["\\equation{a -- \"b\"}", "\\code{a -- \"b\"}", "\\verb{...}"] {
+            assert_eq!(&format(source), source);
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Prints the frontend AST back into Subscript source code.
+//!
+//! Unlike `Node::to_string` this keeps the tag parameters, prints inline math
+//! as `\{...}`, and fences verbatim bodies that contain unbalanced curly
+//! braces (e.g. `\code#{...}#`), so the output parses back into the same tree.
+use crate::frontend::data::*;
+use crate::frontend::ast::*;
+
+/// The shortest fence that allows the given verbatim body to be written
+/// literally (or an empty string if no fence is needed).
+pub fn verbatim_fence(body: &str) -> String {
+    let mut depth = 0isize;
+    let mut balanced = true;
+    for char in body.chars() {
+        match char {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            _ => (),
+        }
+        balanced = balanced && depth >= 0;
+    }
+    if balanced && depth == 0 {
+        return String::new()
+    }
+    let mut fence = String::from("#");
+    while body.contains(&format!("}}{}", fence)) {
+        fence.push('#');
+    }
+    fence
+}
+
+fn print_parameters(tag: &Tag, output: &mut String) {
+    let parameters = tag.parameters
+        .iter()
+        .flatten()
+        .map(Node::to_string)
+        .collect::<Vec<_>>();
+    if !parameters.is_empty() {
+        output.push('[');
+        output.push_str(&parameters.join(" "));
+        output.push(']');
+    }
+}
+
+fn print_tag(tag: &Tag, output: &mut String) {
+    if tag.has_name(INLINE_MATH_TAG) {
+        output.push_str("\\{");
+        for child in tag.children.iter() {
+            match child.unwrap_curly_brace() {
+                Some(children) => print_nodes(children, output),
+                None => print_node(child, output),
+            }
+        }
+        output.push('}');
+        return
+    }
+    output.push('\\');
+    output.push_str(tag.name());
+    if VERBATIM_TAGS.contains(&tag.name()) {
+        let body = tag.children
+            .iter()
+            .map(|x| match x.unwrap_curly_brace() {
+                Some(children) => children.iter().map(Node::to_string).collect(),
+                None => x.to_string(),
+            })
+            .collect::<String>();
+        let fence = verbatim_fence(&body);
+        print_parameters(tag, output);
+        output.push_str(&fence);
+        output.push('{');
+        output.push_str(&body);
+        output.push('}');
+        output.push_str(&fence);
+        return
+    }
+    print_parameters(tag, output);
+    if tag.children.is_empty() && tag.parameters.is_some() {
+        return
+    }
+    let is_blocks = !tag.children.is_empty() && tag.children
+        .iter()
+        .all(|x| x.unwrap_curly_brace().is_some());
+    if is_blocks {
+        print_nodes(&tag.children, output);
+    } else {
+        output.push('{');
+        print_nodes(&tag.children, output);
+        output.push('}');
+    }
+}
+
+fn print_node(node: &Node, output: &mut String) {
+    match node {
+        Node::Tag(tag) => print_tag(tag, output),
+        Node::Enclosure(Ann{data, ..}) => {
+            let (open, close) = match &data.kind {
+                EnclosureKind::CurlyBrace => ("{", "}"),
+                EnclosureKind::SquareParen => ("[", "]"),
+                EnclosureKind::Parens => ("(", ")"),
+                EnclosureKind::Fragment => ("", ""),
+                EnclosureKind::Error{..} => {
+                    output.push_str(&node.to_string());
+                    return
+                }
+            };
+            output.push_str(open);
+            print_nodes(&data.children, output);
+            output.push_str(close);
+        }
+        node => output.push_str(&node.to_string()),
+    }
+}
+
+fn print_nodes(nodes: &[Node], output: &mut String) {
+    for node in nodes {
+        print_node(node, output);
+    }
+}
+
+/// Entrypoint.
+pub fn print_source(nodes: &[Node]) -> String {
+    let mut output = String::new();
+    print_nodes(nodes, &mut output);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::pass::pp_normalize::run_compiler_frontend;
+
+    #[test]
+    fn fences() {
+        assert_eq!(verbatim_fence("a {b} c"), "");
+        assert_eq!(verbatim_fence("a } {"), "#");
+        assert_eq!(verbatim_fence("{ }# {"), "##");
+    }
+
+    #[test]
+    fn round_trip() {
+        let sources = [
+            "\\h1{Title} \\note[kind=theorem title='A B']{x \\b{y}}",
+            "a \\{x^2} b \\equation{\\frac{1}{2}}",
+            "\\code[lang=c]#{ if (x) { }#",
+            "\\figure{\\img[src=a.png]}{Caption}",
+            "\\img[src=a.png]",
+        ];
+        for source in sources.iter() {
+            assert_eq!(&print_source(&run_compiler_frontend(source)), source);
+        }
+    }
+}
@@ -10,9 +10,12 @@ use crate::frontend::data::{
     Enclosure,
     EnclosureKind,
     INLINE_MATH_TAG,
+    BLOCK_MATH_TAGS,
     RewriteRule,
 };
-use crate::frontend::ast::{Node, Tag};
+use std::cell::RefCell;
+use crate::frontend::ast::{Node, NodeEnvironment, Tag};
+use crate::frontend::visit::{Visitor, walk_tag};
 
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -46,32 +49,21 @@ pub struct Heading {
 }
 
 pub fn query_heading_nodes<'a>(node: &Node<'a>) -> Vec<Heading> {
-    pub fn go<'a>(node: &Node<'a>) -> Vec<Tag<'a>> {
-        match node {
-            Node::Tag(tag) if tag.has_name("h1") => vec![tag.clone()],
-            Node::Tag(tag) if tag.has_name("h2") => vec![tag.clone()],
-            Node::Tag(tag) if tag.has_name("h3") => vec![tag.clone()],
-            Node::Tag(tag) if tag.has_name("h4") => vec![tag.clone()],
-            Node::Tag(tag) if tag.has_name("h5") => vec![tag.clone()],
-            Node::Tag(tag) if tag.has_name("h6") => vec![tag.clone()],
-            Node::Tag(node) => {
-                node.children
-                    .iter()
-                    .flat_map(go)
-                    .collect::<Vec<_>>()
-            }
-            Node::Enclosure(node) => {
-                node.data.children
-                    .iter()
-                    .flat_map(go)
-                    .collect::<Vec<_>>()
+    struct Headings<'n, 'a> {
+        tags: Vec<&'n Tag<'a>>,
+    }
+    impl<'n, 'a: 'n> Visitor<'n, 'a> for Headings<'n, 'a> {
+        fn visit_tag(&mut self, tag: &'n Tag<'a>) {
+            if tag.is_heading_node() {
+                self.tags.push(tag);
+            } else {
+                walk_tag(self, tag);
             }
-            Node::Ident(..) => Vec::new(),
-            Node::String(..) => Vec::new(),
-            Node::InvalidToken(..) => Vec::new(),
         }
     }
-    go(node)
+    let mut headings = Headings {tags: Vec::new()};
+    headings.visit_node(node);
+    headings.tags
         .into_iter()
         .map(|tag| {
             let kind = HeadingKind::from_str(tag.name()).unwrap();
@@ -84,3 +76,76 @@ pub fn query_heading_nodes<'a>(node: &Node<'a>) -> Vec<Heading> {
         })
         .collect::<Vec<_>>()
 }
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TermKind {
+    /// `\term[key]{display}`, optionally followed by a `{definition}` block.
+    Term,
+    /// `\index{entry}`
+    Index,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Term {
+    pub kind: TermKind,
+    /// The glossary key (e.g. `group` for `\term[group]{groups}`), which
+    /// defaults to the display text; the entry for `\index`.
+    pub key: String,
+    /// The display text, in Subscript syntax (see `printer::print_source`).
+    pub text: String,
+    /// The definition, in Subscript syntax.
+    pub definition: Option<String>,
+}
+
+/// The given node with the nested `\term` & `\index` tags replaced by their
+/// display text, so that printing it doesn’t duplicate their anchors.
+fn without_terms<'a>(node: Node<'a>) -> Node<'a> {
+    let f = |_: NodeEnvironment<'a>, node: Node<'a>| -> Node<'a> {
+        match node {
+            Node::Tag(tag) if tag.has_name("term") => {
+                let text = tag.children
+                    .into_iter()
+                    .next()
+                    .map(Node::unblock)
+                    .unwrap_or_default();
+                Node::new_fragment(text)
+            }
+            Node::Tag(tag) if tag.has_name("index") => Node::new_fragment(Vec::new()),
+            node => node,
+        }
+    };
+    node.transform_mut(NodeEnvironment::default(), Rc::new(RefCell::new(f)))
+}
+
+impl Term {
+    pub fn from_tag<'a>(tag: &Tag<'a>) -> Option<Term> {
+        let kind = match tag.name() {
+            "term" => TermKind::Term,
+            "index" => TermKind::Index,
+            _ => return None,
+        };
+        let mut blocks = tag.children
+            .iter()
+            .cloned()
+            .map(|x| crate::frontend::printer::print_source(&without_terms(x).unblock()));
+        let text = blocks.next()?.trim().to_owned();
+        let definition = blocks
+            .next()
+            .map(|x| x.trim().to_owned())
+            .filter(|x| !x.is_empty() && kind == TermKind::Term);
+        // I.E. `\term[key=group]` OR `\term[group]`
+        let positional = || tag.parameters
+            .as_ref()?
+            .iter()
+            .filter_map(Node::unwrap_string)
+            .map(|x| x.data.trim())
+            .find(|x| !x.is_empty() && !x.contains('='))
+            .map(ToOwned::to_owned);
+        let key = tag.get_parameter_value("key")
+            .or_else(positional)
+            .map(|x| x.trim_matches(|x| x == '\'' || x == '"').to_owned())
+            .filter(|x| !x.is_empty() && kind == TermKind::Term)
+            .unwrap_or_else(|| text.clone());
+        Some(Term{kind, key, text, definition})
+    }
+}
@@ -0,0 +1,385 @@
+//! Read-only (`Visitor`) & in place (`VisitorMut`) traversals of the
+//! frontend AST, and a selector based query API on top of them.
+//!
+//! The visitor methods default to visiting the children (see the `walk_*`
+//! functions); override them to collect data, and call the corresponding
+//! `walk_*` function to keep descending. The selectors are CSS like, where
+//! blocks (i.e. enclosures) are transparent:
+//!
+//! ```text
+//! h2                   every h2
+//! note h2              every h2 within a note
+//! note > h2            every h2 directly within a note
+//! note[kind=theorem]   every theorem note
+//! *[id]                every tag with an id parameter
+//! ```
+use crate::frontend::data::{Atom, Enclosure};
+use crate::frontend::ast::{Ann, Node, Tag};
+
+/// A traversal of a tree borrowed for `'n`, e.g. to collect references into
+/// it.
+pub trait Visitor<'n, 'a: 'n> {
+    fn visit_node(&mut self, node: &'n Node<'a>) {
+        walk_node(self, node)
+    }
+    fn visit_tag(&mut self, tag: &'n Tag<'a>) {
+        walk_tag(self, tag)
+    }
+    fn visit_enclosure(&mut self, block: &'n Ann<Enclosure<'a, Node<'a>>>) {
+        walk_enclosure(self, block)
+    }
+    fn visit_ident(&mut self, _ident: &'n Ann<Atom<'a>>) {}
+    fn visit_string(&mut self, _text: &'n Ann<Atom<'a>>) {}
+    fn visit_invalid_token(&mut self, _token: &'n Ann<Atom<'a>>) {}
+}
+
+pub fn walk_node<'n, 'a: 'n, V: Visitor<'n, 'a> + ?Sized>(visitor: &mut V, node: &'n Node<'a>) {
+    match node {
+        Node::Tag(tag) => visitor.visit_tag(tag),
+        Node::Enclosure(block) => visitor.visit_enclosure(block),
+        Node::Ident(ident) => visitor.visit_ident(ident),
+        Node::String(text) => visitor.visit_string(text),
+        Node::InvalidToken(token) => visitor.visit_invalid_token(token),
+    }
+}
+
+/// Visits the children & rewrite rules (but not the parameters).
+pub fn walk_tag<'n, 'a: 'n, V: Visitor<'n, 'a> + ?Sized>(visitor: &mut V, tag: &'n Tag<'a>) {
+    for child in tag.children.iter() {
+        visitor.visit_node(child);
+    }
+    for rule in tag.rewrite_rules.iter() {
+        visitor.visit_node(&rule.from);
+        visitor.visit_node(&rule.to);
+    }
+}
+
+pub fn walk_enclosure<'n, 'a: 'n, V: Visitor<'n, 'a> + ?Sized>(
+    visitor: &mut V,
+    block: &'n Ann<Enclosure<'a, Node<'a>>>,
+) {
+    for child in block.data.children.iter() {
+        visitor.visit_node(child);
+    }
+}
+
+/// A traversal that may modify (or replace) the nodes in place.
+pub trait VisitorMut<'a> {
+    fn visit_node_mut(&mut self, node: &mut Node<'a>) {
+        walk_node_mut(self, node)
+    }
+    fn visit_tag_mut(&mut self, tag: &mut Tag<'a>) {
+        walk_tag_mut(self, tag)
+    }
+    fn visit_enclosure_mut(&mut self, block: &mut Ann<Enclosure<'a, Node<'a>>>) {
+        walk_enclosure_mut(self, block)
+    }
+    fn visit_ident_mut(&mut self, _ident: &mut Ann<Atom<'a>>) {}
+    fn visit_string_mut(&mut self, _text: &mut Ann<Atom<'a>>) {}
+    fn visit_invalid_token_mut(&mut self, _token: &mut Ann<Atom<'a>>) {}
+}
+
+pub fn walk_node_mut<'a, V: VisitorMut<'a> + ?Sized>(visitor: &mut V, node: &mut Node<'a>) {
+    match node {
+        Node::Tag(tag) => visitor.visit_tag_mut(tag),
+        Node::Enclosure(block) => visitor.visit_enclosure_mut(block),
+        Node::Ident(ident) => visitor.visit_ident_mut(ident),
+        Node::String(text) => visitor.visit_string_mut(text),
+        Node::InvalidToken(token) => visitor.visit_invalid_token_mut(token),
+    }
+}
+
+/// Visits the children & rewrite rules (but not the parameters).
+pub fn walk_tag_mut<'a, V: VisitorMut<'a> + ?Sized>(visitor: &mut V, tag: &mut Tag<'a>) {
+    for child in tag.children.iter_mut() {
+        visitor.visit_node_mut(child);
+    }
+    for rule in tag.rewrite_rules.iter_mut() {
+        visitor.visit_node_mut(&mut rule.from);
+        visitor.visit_node_mut(&mut rule.to);
+    }
+}
+
+pub fn walk_enclosure_mut<'a, V: VisitorMut<'a> + ?Sized>(
+    visitor: &mut V,
+    block: &mut Ann<Enclosure<'a, Node<'a>>>,
+) {
+    for child in block.data.children.iter_mut() {
+        visitor.visit_node_mut(child);
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+// QUERIES
+///////////////////////////////////////////////////////////////////////////////
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Combinator {
+    Descendant,
+    Child,
+}
+
+/// E.g. `note[kind=theorem]`.
+#[derive(Debug, Clone)]
+struct Compound {
+    /// `None` for `*`.
+    name: Option<String>,
+    /// I.e. `[key]` or `[key=value]`.
+    parameters: Vec<(String, Option<String>)>,
+}
+
+impl Compound {
+    fn parse(source: &str) -> Option<Compound> {
+        let (name, mut rest) = match source.find('[') {
+            Some(ix) => source.split_at(ix),
+            None => (source, ""),
+        };
+        let name = match name {
+            "*" | "" => None,
+            name if name.chars().all(|x| x.is_alphanumeric() || x == '-' || x == '!') => {
+                Some(name.to_owned())
+            }
+            _ => return None,
+        };
+        let mut parameters = Vec::new();
+        while !rest.is_empty() {
+            let end = rest.find(']')?;
+            let parameter = rest.get(1..end).filter(|_| rest.starts_with('['))?;
+            let parameter = match parameter.split_once('=') {
+                Some((key, value)) => {
+                    let value = value.trim().trim_matches(|x| x == '\'' || x == '"');
+                    (key.trim().to_owned(), Some(value.to_owned()))
+                }
+                None => (parameter.trim().to_owned(), None),
+            };
+            if parameter.0.is_empty() {
+                return None
+            }
+            parameters.push(parameter);
+            rest = &rest[end + 1..];
+        }
+        if name.is_none() && parameters.is_empty() && source != "*" {
+            return None
+        }
+        Some(Compound {name, parameters})
+    }
+    fn matches(&self, tag: &Tag) -> bool {
+        let unquote = |x: String| x.trim_matches(|x| x == '\'' || x == '"').to_owned();
+        self.name.as_ref().map(|x| tag.has_name(x)).unwrap_or(true) &&
+        self.parameters.iter().all(|(key, value)| match value {
+            Some(value) => tag.get_parameter_value(key).map(unquote).as_ref() == Some(value),
+            None => tag.has_parameter(key),
+        })
+    }
+}
+
+/// A parsed selector (see the module documentation).
+#[derive(Debug, Clone)]
+pub struct Selector {
+    /// The combinator relates each compound to the previous one.
+    steps: Vec<(Combinator, Compound)>,
+}
+
+/// Splits the selector into compounds & `>` combinators, keeping the
+/// parameters (e.g. `[kind = 'a b']`) intact.
+fn tokens(source: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_brackets = false;
+    for x in source.chars() {
+        match x {
+            '[' => in_brackets = true,
+            ']' => in_brackets = false,
+            _ => (),
+        }
+        match x {
+            '>' if !in_brackets => {
+                tokens.push(std::mem::take(&mut current));
+                tokens.push(String::from(">"));
+            }
+            x if x.is_whitespace() && !in_brackets => {
+                tokens.push(std::mem::take(&mut current));
+            }
+            x => current.push(x),
+        }
+    }
+    tokens.push(current);
+    tokens.retain(|x| !x.is_empty());
+    tokens
+}
+
+impl Selector {
+    pub fn parse(source: &str) -> Option<Selector> {
+        let mut steps = Vec::new();
+        let mut combinator = Combinator::Descendant;
+        for token in tokens(source) {
+            if token == ">" {
+                if steps.is_empty() || combinator == Combinator::Child {
+                    return None
+                }
+                combinator = Combinator::Child;
+                continue;
+            }
+            steps.push((combinator, Compound::parse(&token)?));
+            combinator = Combinator::Descendant;
+        }
+        if steps.is_empty() || combinator == Combinator::Child {
+            return None
+        }
+        Some(Selector {steps})
+    }
+    /// Whether the given tag (within the given parents, outermost first)
+    /// matches.
+    pub fn matches(&self, tag: &Tag, parents: &[&Tag]) -> bool {
+        /// Whether the `steps` match within the `parents`, where the last step
+        /// relates to the child of the `parents` by the given combinator.
+        fn go(steps: &[(Combinator, Compound)], combinator: Combinator, parents: &[&Tag]) -> bool {
+            let ((next, compound), steps) = match steps.split_last() {
+                Some(x) => x,
+                None => return true,
+            };
+            match combinator {
+                Combinator::Child => match parents.split_last() {
+                    Some((parent, parents)) => compound.matches(parent) && go(steps, *next, parents),
+                    None => false,
+                },
+                Combinator::Descendant => (0..parents.len())
+                    .rev()
+                    .any(|ix| compound.matches(parents[ix]) && go(steps, *next, &parents[..ix])),
+            }
+        }
+        let ((combinator, compound), steps) = self.steps.split_last().unwrap();
+        compound.matches(tag) && go(steps, *combinator, parents)
+    }
+}
+
+/// A tag matching some selector.
+#[derive(Debug, Clone)]
+pub struct Match<'n, 'a> {
+    pub tag: &'n Tag<'a>,
+    /// The enclosing tags, outermost first.
+    pub parents: Vec<&'n Tag<'a>>,
+}
+
+struct Query<'s, 'n, 'a> {
+    selector: &'s Selector,
+    parents: Vec<&'n Tag<'a>>,
+    matches: Vec<Match<'n, 'a>>,
+}
+
+impl<'s, 'n, 'a: 'n> Visitor<'n, 'a> for Query<'s, 'n, 'a> {
+    fn visit_tag(&mut self, tag: &'n Tag<'a>) {
+        if self.selector.matches(tag, &self.parents) {
+            self.matches.push(Match {tag, parents: self.parents.clone()});
+        }
+        self.parents.push(tag);
+        walk_tag(self, tag);
+        self.parents.pop();
+    }
+}
+
+/// The tags matching the given selector, in document order.
+pub fn query<'n, 'a: 'n>(nodes: &'n [Node<'a>], selector: &Selector) -> Vec<Match<'n, 'a>> {
+    let mut query = Query {selector, parents: Vec::new(), matches: Vec::new()};
+    for node in nodes {
+        query.visit_node(node);
+    }
+    query.matches
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::pass::pp_normalize::run_compiler_frontend;
+    use crate::frontend::printer::print_source;
+
+    /// The matches of the given selector, printed.
+    fn select(source: &str, selector: &str) -> Vec<String> {
+        let nodes = run_compiler_frontend(source);
+        let selector = Selector::parse(selector).unwrap();
+        query(&nodes, &selector)
+            .into_iter()
+            .map(|x| print_source(&[Node::Tag(x.tag.clone())]))
+            .collect()
+    }
+
+    static SOURCE: &str = concat!(
+        "\\h2{A} ",
+        "\\note[kind=theorem id=t]{\\h2{B} \\div{\\h2{C}}} ",
+        "\\note[kind='lemma']{\\h3{D}}",
+    );
+
+    #[test]
+    fn parse() {
+        let valid = [
+            "h2", "*", "note h2", "note > h2", "note>h2", "*[id]", "[kind=theorem]",
+            "note[a][b = 'c > d']",
+        ];
+        for source in valid {
+            assert!(Selector::parse(source).is_some(), "{}", source);
+        }
+        let invalid = [
+            "", ">", "> h2", "note >", "note > > h2", "h2[", "h2[]", "h2[=x]", "h.2", "[a]b",
+        ];
+        for source in invalid {
+            assert!(Selector::parse(source).is_none(), "{}", source);
+        }
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(select(SOURCE, "h2"), vec!["\\h2{A}", "\\h2{B}", "\\h2{C}"]);
+        assert_eq!(select(SOURCE, "*").len(), 7);
+    }
+
+    #[test]
+    fn combinators() {
+        assert_eq!(select(SOURCE, "note h2"), vec!["\\h2{B}", "\\h2{C}"]);
+        assert_eq!(select(SOURCE, "note > h2"), vec!["\\h2{B}"]);
+        assert_eq!(select(SOURCE, "note > div > h2"), vec!["\\h2{C}"]);
+        assert!(select(SOURCE, "div note").is_empty());
+    }
+
+    #[test]
+    fn parameters() {
+        assert_eq!(select(SOURCE, "note[kind=lemma] h3"), vec!["\\h3{D}"]);
+        assert_eq!(select(SOURCE, "*[id]").len(), 1);
+        assert!(select(SOURCE, "note[kind=proof]").is_empty());
+        assert_eq!(select("\\note[title='A B']{}", "note[title = 'A B']").len(), 1);
+    }
+
+    #[test]
+    fn blocks_are_transparent() {
+        assert_eq!(select("\\note{{\\p{a}}}", "note > p"), vec!["\\p{a}"]);
+    }
+
+    #[test]
+    fn parents() {
+        let nodes = run_compiler_frontend(SOURCE);
+        let selector = Selector::parse("h2").unwrap();
+        let parents = query(&nodes, &selector)
+            .into_iter()
+            .map(|x| x.parents.iter().map(|x| x.name()).collect::<Vec<_>>().join(" "))
+            .collect::<Vec<_>>();
+        assert_eq!(parents, vec!["", "note", "note div"]);
+    }
+
+    #[test]
+    fn mutable_visitors() {
+        struct Rename;
+        impl<'a> VisitorMut<'a> for Rename {
+            fn visit_tag_mut(&mut self, tag: &mut Tag<'a>) {
+                if tag.has_name("h2") {
+                    tag.name = Ann::unannotated(std::borrow::Cow::Borrowed("h3"));
+                }
+                walk_tag_mut(self, tag);
+            }
+        }
+        let mut nodes = run_compiler_frontend("\\h2{a \\h2{b}} \\div{{\\h2{c}}}");
+        for node in nodes.iter_mut() {
+            Rename.visit_node_mut(node);
+        }
+        assert_eq!(print_source(&nodes), "\\h3{a \\h3{b}} \\div{{\\h3{c}}}");
+    }
+}
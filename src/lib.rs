@@ -2,4 +2,7 @@
 pub mod codegen;
 pub mod cli;
 pub mod frontend;
+pub mod compiler;
+#[cfg(test)]
+mod test_util;
 
@@ -2,6 +2,9 @@
 pub mod codegen;
 pub mod cli;
 pub mod frontend;
+pub mod compiler;
+#[cfg(test)]
+mod test_util;
 
 
 fn main() {
@@ -0,0 +1,12 @@
+//! Shared fixtures for the unit tests.
+use std::path::PathBuf;
+
+/// An empty temporary directory, unique to the calling test (e.g.
+/// `scratch_dir("images", "svg")`).
+pub fn scratch_dir(module: &str, name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join(format!("subscript-{}-{}-{}", module, std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -0,0 +1,102 @@
+//! Differential tests of the streaming parser against the previous one (see
+//! `benches/parser/reference.rs`), which must produce the same trees.
+use subscript_compiler::frontend::parser;
+
+#[path = "../benches/parser/reference.rs"]
+mod reference;
+
+static SOURCES: &[(&str, &str)] = &[
+    ("chemistry", include_str!("../examples/source/chemistry.txt")),
+    ("electrical-engineering", include_str!("../examples/source/electrical-engineering.txt")),
+    ("mathematics", include_str!("../examples/source/mathematics.txt")),
+    ("complex-unicode", include_str!("../test/complex-unicode.txt")),
+];
+
+/// Hand picked edge cases (escapes, verbatim fences, unbalanced enclosures, …).
+static SNIPPETS: &[&str] = &[
+    "",
+    "\\",
+    "\\ ",
+    "\\  \n\t foo{bar}",
+    "\\{x^2\\}",
+    "\\ {x}",
+    "\\equation{\\{x\\} \\[y\\]}",
+    "\\verb{a {b} \\c}",
+    "\\verb#{a } b}#",
+    "\\verb##{a }# b}##",
+    "\\verb# {a}",
+    "\\code[lang=rust]{fn main() {}}",
+    "\\code[lang=rust] {unclosed",
+    "\\verb{",
+    "\\note{\\h1{A} [b] (c)",
+    "}])",
+    "a\r\nb \u{301}c 👍🏽 é",
+];
+
+/// Pieces of the random sources, weighted towards the tokens the parser
+/// special cases.
+static PIECES: &[&str] = &[
+    "\\", "\\", "{", "}", "{", "}", "[", "]", "(", ")", " ", " ", "\n", "\t",
+    "\r\n", "#", "##", "verb", "code", "equation", "math", "h1", "x", "=",
+    "*", "`", "_", "^", ".", ">", "é", "👍🏽", "\u{301}", "lang",
+];
+
+/// The number of random sources, and pieces per source.
+static RANDOM_SOURCES: usize = 2000;
+static RANDOM_PIECES: usize = 120;
+
+/// A linear congruential generator, deterministic so that failures reproduce.
+struct Random(u64);
+
+impl Random {
+    fn below(&mut self, bound: usize) -> usize {
+        self.0 = self.0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        ((self.0 >> 33) as usize) % bound
+    }
+}
+
+fn assert_same_tree(source: &str) {
+    assert_eq!(
+        format!("{:?}", parser::parse_source(source)),
+        format!("{:?}", reference::parse_source(source)),
+        "parsers disagree on {:?}",
+        source,
+    );
+}
+
+#[test]
+fn sources() {
+    for (_, source) in SOURCES {
+        assert_same_tree(source);
+    }
+}
+
+#[test]
+fn repeated_source() {
+    assert_same_tree(&SOURCES[2].1.repeat(4));
+}
+
+#[test]
+fn snippets() {
+    for snippet in SNIPPETS {
+        assert_same_tree(snippet);
+    }
+}
+
+#[test]
+fn long_whitespace() {
+    assert_same_tree(&format!("\\note{}{{x}}", " \n".repeat(1 << 10)));
+}
+
+#[test]
+fn random_sources() {
+    let mut random = Random(0x5eed);
+    for _ in 0..RANDOM_SOURCES {
+        let source = (0..RANDOM_PIECES)
+            .map(|_| PIECES[random.below(PIECES.len())])
+            .collect::<String>();
+        assert_same_tree(&source);
+    }
+}